@@ -20,6 +20,7 @@ use builder::{Builder, add_node_dependency};
 use node;
 
 mod dht22_pt;
+mod ds18b20_pt;
 
 pub fn attach(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
   node.materializer.set(Some(verify as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
@@ -28,6 +29,7 @@ pub fn attach(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
 
     match sub.path.as_str() {
       "dht22" => dht22_pt::attach(builder, cx, sub.clone()),
+      "ds18b20" => ds18b20_pt::attach(builder, cx, sub.clone()),
       _ => (),
     }
   }
@@ -35,5 +37,5 @@ pub fn attach(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
 
 fn verify(_: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
   node.expect_no_attributes(cx);
-  node.expect_subnodes(cx, &["dht22"]);
+  node.expect_subnodes(cx, &["dht22", "ds18b20"]);
 }
@@ -0,0 +1,240 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Driver for the DS18B20 1-Wire digital thermometer.
+
+Several sensors can share one bus, each addressed by its ROM code (see
+`OneWire::search`); a sensor alone on the bus can be used without one.
+Parasite-powered sensors are detected automatically, and the bus is driven
+high for them during conversions and EEPROM writes.
+*/
+
+use core::cell::Cell;
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use drivers::onewire::{OneWire, Rom, crc8};
+use hal::pin::Gpio;
+use hal::timer::Timer;
+
+const CONVERT_T: u8 = 0x44;
+const WRITE_SCRATCHPAD: u8 = 0x4E;
+const READ_SCRATCHPAD: u8 = 0xBE;
+const COPY_SCRATCHPAD: u8 = 0x48;
+const READ_POWER_SUPPLY: u8 = 0xB4;
+
+/// Time the EEPROM needs to store the scratchpad.
+const COPY_TIME_MS: u32 = 10;
+
+/// Errors talking to the sensor.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// No presence pulse on the bus.
+  NoDevice,
+  /// Scratchpad CRC didn't match.
+  Crc,
+  /// Conversion didn't finish in time.
+  Timeout,
+}
+
+/// Conversion resolution, trading precision for conversion time.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Resolution {
+  /// 0.5°C, 93.75ms.
+  Bits9 = 0,
+  /// 0.25°C, 187.5ms.
+  Bits10 = 1,
+  /// 0.125°C, 375ms.
+  Bits11 = 2,
+  /// 0.0625°C, 750ms.
+  Bits12 = 3,
+}
+
+impl Resolution {
+  /// Maximum conversion time in milliseconds, rounded up.
+  pub fn conversion_time_ms(self) -> u32 {
+    let shift = 3 - self as u32;
+    (750 + (1 << shift) - 1) >> shift
+  }
+}
+
+/// DS18B20 sensor on a 1-Wire bus.
+pub struct DS18B20<'a, T: 'a, P: 'a> {
+  bus: &'a OneWire<'a, T, P>,
+  rom: Option<Rom>,
+  resolution: Cell<Resolution>,
+  parasite: Cell<Option<bool>>,
+}
+
+impl<'a, T: Timer, P: Gpio> DS18B20<'a, T, P> {
+  /// Creates a new driver for the sensor with `rom`, or for the only device on
+  /// the bus if `rom` is None.
+  ///
+  /// The sensor is assumed to use its power-up resolution of 12 bits, until
+  /// `set_resolution` is called.
+  pub fn new(bus: &'a OneWire<'a, T, P>, rom: Option<Rom>)
+      -> DS18B20<'a, T, P> {
+    DS18B20 {
+      bus: bus,
+      rom: rom,
+      resolution: Cell::new(Resolution::Bits12),
+      parasite: Cell::new(None),
+    }
+  }
+
+  fn select(&self) -> Result<(), Error> {
+    if self.bus.select(self.rom.as_ref()) {
+      Ok(())
+    } else {
+      Err(Error::NoDevice)
+    }
+  }
+
+  /// Returns true if the sensor is powered from the data line.
+  pub fn is_parasite_powered(&self) -> Result<bool, Error> {
+    match self.parasite.get() {
+      Some(parasite) => Ok(parasite),
+      None => {
+        try!(self.select());
+        self.bus.write_byte(READ_POWER_SUPPLY);
+        let parasite = !self.bus.read_bit();
+        self.parasite.set(Some(parasite));
+        Ok(parasite)
+      },
+    }
+  }
+
+  /// Reads the 9-byte scratchpad, checking its CRC.
+  pub fn read_scratchpad(&self) -> Result<[u8; 9], Error> {
+    try!(self.select());
+    self.bus.write_byte(READ_SCRATCHPAD);
+    let mut scratchpad = [0u8; 9];
+    for b in scratchpad.iter_mut() {
+      *b = self.bus.read_byte();
+    }
+    if crc8(&scratchpad[..8]) != scratchpad[8] {
+      return Err(Error::Crc);
+    }
+    Ok(scratchpad)
+  }
+
+  /// Sets the conversion resolution, keeping the alarm thresholds.
+  ///
+  /// The setting is lost on power down unless `save` is called.
+  pub fn set_resolution(&self, resolution: Resolution)
+      -> Result<(), Error> {
+    let scratchpad = try!(self.read_scratchpad());
+    try!(self.select());
+    self.bus.write_byte(WRITE_SCRATCHPAD);
+    self.bus.write_byte(scratchpad[2]);
+    self.bus.write_byte(scratchpad[3]);
+    self.bus.write_byte(((resolution as u8) << 5) | 0x1F);
+    self.resolution.set(resolution);
+    Ok(())
+  }
+
+  /// Stores the alarm thresholds and resolution in the sensor EEPROM.
+  pub fn save(&self) -> Result<(), Error> {
+    let parasite = try!(self.is_parasite_powered());
+    try!(self.select());
+    self.bus.write_byte(COPY_SCRATCHPAD);
+    if parasite {
+      self.bus.power();
+      self.bus.timer().wait_ms(COPY_TIME_MS);
+      self.bus.release_power();
+    } else {
+      self.bus.timer().wait_ms(COPY_TIME_MS);
+    }
+    Ok(())
+  }
+
+  /// Runs a temperature conversion, returning once it's complete.
+  ///
+  /// Parasite-powered sensors can't signal completion, so the bus is powered
+  /// for the full conversion time; others are polled.
+  pub fn convert(&self) -> Result<(), Error> {
+    let parasite = try!(self.is_parasite_powered());
+    let time_ms = self.resolution.get().conversion_time_ms();
+    try!(self.select());
+    self.bus.write_byte(CONVERT_T);
+
+    if parasite {
+      self.bus.power();
+      self.bus.timer().wait_ms(time_ms);
+      self.bus.release_power();
+      return Ok(());
+    }
+
+    // Allow some slack over the datasheet maximum.
+    let start = self.bus.timer().get_counter();
+    while !self.bus.read_bit() {
+      if self.bus.timer().get_counter().wrapping_sub(start) >
+          time_ms * 1000 * 5 / 4 {
+        return Err(Error::Timeout);
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns the temperature of the last conversion, in °C.
+  pub fn temperature(&self) -> Result<f32, Error> {
+    let scratchpad = try!(self.read_scratchpad());
+    let resolution = match scratchpad[4] >> 5 & 0x3 {
+      0 => Resolution::Bits9,
+      1 => Resolution::Bits10,
+      2 => Resolution::Bits11,
+      _ => Resolution::Bits12,
+    };
+    Ok(to_celsius(scratchpad[0], scratchpad[1], resolution))
+  }
+
+  /// Runs a conversion and returns the temperature, in °C.
+  pub fn read(&self) -> Result<f32, Error> {
+    try!(self.convert());
+    self.temperature()
+  }
+}
+
+/// Converts the temperature register to °C, ignoring bits undefined at the
+/// given resolution.
+fn to_celsius(lsb: u8, msb: u8, resolution: Resolution) -> f32 {
+  let undefined = 3 - resolution as u16;
+  let raw = ((msb as u16) << 8 | lsb as u16) & !((1 << undefined) - 1);
+  (raw as i16) as f32 / 16.0
+}
+
+#[cfg(test)]
+mod test {
+  use super::{to_celsius, Resolution};
+
+  #[test]
+  fn converts_temperature() {
+    // Values from the datasheet.
+    assert!(to_celsius(0x91, 0x01, Resolution::Bits12) == 25.0625);
+    assert!(to_celsius(0x5E, 0xFF, Resolution::Bits12) == -10.125);
+    assert!(to_celsius(0x90, 0xFC, Resolution::Bits12) == -55.0);
+    assert!(to_celsius(0x91, 0x01, Resolution::Bits9) == 25.0);
+  }
+
+  #[test]
+  fn rounds_conversion_time_up() {
+    assert!(Resolution::Bits9.conversion_time_ms() == 94);
+    assert!(Resolution::Bits10.conversion_time_ms() == 188);
+    assert!(Resolution::Bits11.conversion_time_ms() == 375);
+    assert!(Resolution::Bits12.conversion_time_ms() == 750);
+  }
+}
@@ -0,0 +1,110 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::rc::Rc;
+use syntax::ext::base::ExtCtxt;
+
+use builder::{Builder, TokenString, add_node_dependency};
+use node;
+
+pub fn attach(builder: &mut Builder, _: &mut ExtCtxt, node: Rc<node::Node>) {
+  node.materializer.set(Some(build_ds18b20 as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
+  node.mutator.set(Some(mutate_pin as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
+
+  let pin_node_name = node.get_ref_attr("pin").unwrap();
+  let pin_node = builder.pt().get_by_name(pin_node_name.as_str()).unwrap();
+  add_node_dependency(&node, &pin_node);
+
+  let timer_node_name = node.get_ref_attr("timer").unwrap();
+  let timer_node = builder.pt().get_by_name(timer_node_name.as_str()).unwrap();
+  add_node_dependency(&node, &timer_node);
+}
+
+/// The bus idles released, and the driver switches direction as needed.
+fn mutate_pin(builder: &mut Builder, _: &mut ExtCtxt, node: Rc<node::Node>) {
+  let pin_node_name = node.get_ref_attr("pin").unwrap();
+  let pin_node = builder.pt().get_by_name(pin_node_name.as_str()).unwrap();
+  pin_node.attributes.borrow_mut().insert("direction".to_string(),
+        Rc::new(node::Attribute::new_nosp(node::StrValue("in".to_string()))));
+}
+
+fn build_ds18b20(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
+  if !node.expect_no_subnodes(cx) {return}
+
+  if !node.expect_attributes(cx,
+      &[("pin", node::RefAttribute), ("timer", node::RefAttribute)]) {
+    return
+  }
+
+  let pin_node_name = node.get_ref_attr("pin").unwrap();
+  let timer_node_name = node.get_ref_attr("timer").unwrap();
+
+  let pin = TokenString(pin_node_name);
+  let timer = TokenString(timer_node_name);
+  let name = TokenString(node.name.clone().unwrap());
+  let bus = TokenString(format!("{}_bus", node.name.clone().unwrap()));
+
+  let typename = format!("zinc::drivers::ds18b20::DS18B20");
+  node.set_type_name(typename);
+  let ty_params = vec!(
+      "'a".to_string(),
+      "zinc::hal::timer::Timer".to_string(),
+      "zinc::hal::pin::Gpio".to_string());
+  node.set_type_params(ty_params);
+
+  let bus_st = quote_stmt!(&*cx,
+      let $bus = zinc::drivers::onewire::OneWire::new(&$timer, &$pin);
+  ).unwrap();
+  builder.add_main_statement(bus_st);
+
+  let st = quote_stmt!(&*cx,
+      let $name = zinc::drivers::ds18b20::DS18B20::new(&$bus, None);
+  ).unwrap();
+  builder.add_main_statement(st);
+}
+
+#[cfg(test)]
+mod test {
+  use builder::Builder;
+  use test_helpers::{assert_equal_source, with_parsed};
+  use hamcrest::{assert_that, is, equal_to};
+
+  #[test]
+  fn builds_ds18b20_pt() {
+    with_parsed("
+      timer@timer;
+      pin@pin;
+      temp@ds18b20 {
+        pin = &pin;
+        timer = &timer;
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      pt.get_by_name("timer").unwrap().set_type_name("T".to_string());
+      pt.get_by_name("pin").unwrap().set_type_name("P".to_string());
+      super::mutate_pin(&mut builder, cx, pt.get_by_name("temp").unwrap());
+      super::build_ds18b20(&mut builder, cx, pt.get_by_name("temp").unwrap());
+      assert_that(unsafe{*failed}, is(equal_to(false)));
+      assert_that(builder.main_stmts().len(), is(equal_to(2usize)));
+
+      assert_equal_source(&builder.main_stmts()[0],
+          "let temp_bus = zinc::drivers::onewire::OneWire::new(&timer, &pin);");
+      assert_equal_source(&builder.main_stmts()[1],
+          "let temp = zinc::drivers::ds18b20::DS18B20::new(&temp_bus, None);");
+
+      let pin_node = pt.get_by_name("pin").unwrap();
+      assert_that(pin_node.get_string_attr("direction").unwrap(),
+          is(equal_to("in".to_string())));
+    });
+  }
+}
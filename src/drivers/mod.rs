@@ -19,3 +19,5 @@ pub mod lcd;
 pub mod bluenrg;
 pub mod chario;
 pub mod dht22;
+pub mod ds18b20;
+pub mod onewire;
@@ -0,0 +1,306 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Dallas 1-Wire bus master.
+
+The bus is driven open-drain over a single `Gpio`: it's pulled low by switching
+the pin to output low, and released by switching it back to input, so an
+external pull-up (usually 4.7k) is required. Bit timings follow the standard
+speed values recommended by Maxim, and need a timer with 1us resolution.
+Interrupts arriving in the middle of a time slot can corrupt it, so it's a good
+idea to run the transfers with interrupts disabled.
+*/
+
+use core::option::Option::{self, Some, None};
+use core::iter::Iterator;
+
+use hal::pin::{Gpio, High, In, Out};
+use hal::timer::Timer;
+
+/// 64-bit device ROM code: family code, 48-bit serial number and CRC.
+pub type Rom = [u8; 8];
+
+/// ROM commands.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub enum RomCommand {
+  SearchRom = 0xF0,
+  ReadRom = 0x33,
+  MatchRom = 0x55,
+  SkipRom = 0xCC,
+  AlarmSearch = 0xEC,
+}
+
+/// 1-Wire bus master.
+pub struct OneWire<'a, T: 'a, P: 'a> {
+  timer: &'a T,
+  pin: &'a P,
+}
+
+impl<'a, T: Timer, P: Gpio> OneWire<'a, T, P> {
+  /// Creates a new bus master on `pin`, leaving the bus released.
+  pub fn new(timer: &'a T, pin: &'a P) -> OneWire<'a, T, P> {
+    pin.set_direction(In);
+    OneWire {
+      timer: timer,
+      pin: pin,
+    }
+  }
+
+  /// Returns the timer used for bus timings.
+  pub fn timer(&self) -> &'a T {
+    self.timer
+  }
+
+  fn pull_low(&self) {
+    self.pin.set_low();
+    self.pin.set_direction(Out);
+  }
+
+  fn release(&self) {
+    self.pin.set_direction(In);
+  }
+
+  /// Sends a reset pulse, returns true if any device answered with a presence
+  /// pulse.
+  pub fn reset(&self) -> bool {
+    self.pull_low();
+    self.timer.wait_us(480);
+    self.release();
+    self.timer.wait_us(70);
+    let present = self.pin.level() != High;
+    self.timer.wait_us(410);
+    present
+  }
+
+  /// Writes a single bit.
+  pub fn write_bit(&self, bit: bool) {
+    self.pull_low();
+    if bit {
+      self.timer.wait_us(6);
+      self.release();
+      self.timer.wait_us(64);
+    } else {
+      self.timer.wait_us(60);
+      self.release();
+      self.timer.wait_us(10);
+    }
+  }
+
+  /// Reads a single bit.
+  pub fn read_bit(&self) -> bool {
+    self.pull_low();
+    self.timer.wait_us(6);
+    self.release();
+    self.timer.wait_us(9);
+    let bit = self.pin.level() == High;
+    self.timer.wait_us(55);
+    bit
+  }
+
+  /// Writes a byte, LSB first.
+  pub fn write_byte(&self, value: u8) {
+    for i in 0..8 {
+      self.write_bit(value & (1 << i) != 0);
+    }
+  }
+
+  /// Reads a byte, LSB first.
+  pub fn read_byte(&self) -> u8 {
+    let mut value: u8 = 0;
+    for i in 0..8 {
+      if self.read_bit() {
+        value |= 1 << i;
+      }
+    }
+    value
+  }
+
+  /// Resets the bus and addresses the device with `rom`, or all devices if
+  /// `rom` is None. Returns false if no device is present.
+  pub fn select(&self, rom: Option<&Rom>) -> bool {
+    if !self.reset() {
+      return false;
+    }
+    match rom {
+      Some(rom) => {
+        self.write_byte(RomCommand::MatchRom as u8);
+        for &b in rom.iter() {
+          self.write_byte(b);
+        }
+      },
+      None => self.write_byte(RomCommand::SkipRom as u8),
+    }
+    true
+  }
+
+  /// Reads the ROM code of the only device on the bus.
+  ///
+  /// Returns None if no device is present or the CRC doesn't match, which is
+  /// also the case if more than one device answers.
+  pub fn read_rom(&self) -> Option<Rom> {
+    if !self.reset() {
+      return None;
+    }
+    self.write_byte(RomCommand::ReadRom as u8);
+    let mut rom: Rom = [0; 8];
+    for b in rom.iter_mut() {
+      *b = self.read_byte();
+    }
+    if crc8(&rom[..7]) == rom[7] { Some(rom) } else { None }
+  }
+
+  /// Drives the bus high, to supply parasite-powered devices during an
+  /// operation that needs more current than the pull-up can provide.
+  ///
+  /// Nothing else can be done on the bus until `release_power` is called.
+  pub fn power(&self) {
+    self.pin.set_high();
+    self.pin.set_direction(Out);
+  }
+
+  /// Stops driving the bus after `power`.
+  pub fn release_power(&self) {
+    self.release();
+  }
+
+  /// Returns an iterator over ROM codes of all devices on the bus.
+  pub fn search<'b>(&'b self) -> Search<'b, 'a, T, P> {
+    Search::new(self, RomCommand::SearchRom)
+  }
+
+  /// Returns an iterator over ROM codes of devices with an alarm condition.
+  pub fn alarm_search<'b>(&'b self) -> Search<'b, 'a, T, P> {
+    Search::new(self, RomCommand::AlarmSearch)
+  }
+}
+
+/// ROM search in progress; see Maxim application note 187.
+///
+/// Devices with a bad CRC end the search.
+pub struct Search<'b, 'a: 'b, T: 'a, P: 'a> {
+  bus: &'b OneWire<'a, T, P>,
+  command: RomCommand,
+  rom: Rom,
+  last_discrepancy: u8,
+  done: bool,
+}
+
+impl<'b, 'a, T: Timer, P: Gpio> Search<'b, 'a, T, P> {
+  fn new(bus: &'b OneWire<'a, T, P>, command: RomCommand)
+      -> Search<'b, 'a, T, P> {
+    Search {
+      bus: bus,
+      command: command,
+      rom: [0; 8],
+      last_discrepancy: 0,
+      done: false,
+    }
+  }
+}
+
+impl<'b, 'a, T: Timer, P: Gpio> Iterator for Search<'b, 'a, T, P> {
+  type Item = Rom;
+
+  fn next(&mut self) -> Option<Rom> {
+    if self.done || !self.bus.reset() {
+      self.done = true;
+      return None;
+    }
+    self.bus.write_byte(self.command as u8);
+
+    let mut last_zero: u8 = 0;
+    for n in 1..65u8 {
+      let byte = ((n - 1) / 8) as usize;
+      let mask = 1 << ((n - 1) % 8);
+
+      let bit = self.bus.read_bit();
+      let complement = self.bus.read_bit();
+      let direction = match (bit, complement) {
+        (true, true) => {
+          // Nobody answered.
+          self.done = true;
+          return None;
+        },
+        (true, false) => true,
+        (false, true) => false,
+        (false, false) => {
+          // Devices differ at this bit.
+          let direction = if n < self.last_discrepancy {
+            self.rom[byte] & mask != 0
+          } else {
+            n == self.last_discrepancy
+          };
+          if !direction {
+            last_zero = n;
+          }
+          direction
+        },
+      };
+
+      if direction {
+        self.rom[byte] |= mask;
+      } else {
+        self.rom[byte] &= !mask;
+      }
+      self.bus.write_bit(direction);
+    }
+
+    self.last_discrepancy = last_zero;
+    if last_zero == 0 {
+      self.done = true;
+    }
+
+    if crc8(&self.rom[..7]) == self.rom[7] {
+      Some(self.rom)
+    } else {
+      self.done = true;
+      None
+    }
+  }
+}
+
+/// Computes the Dallas/Maxim CRC8 (x^8 + x^5 + x^4 + 1) of `data`.
+///
+/// The CRC of data followed by its CRC byte is zero.
+pub fn crc8(data: &[u8]) -> u8 {
+  let mut crc: u8 = 0;
+  for &b in data.iter() {
+    let mut b = b;
+    for _ in 0..8 {
+      let mix = (crc ^ b) & 1;
+      crc >>= 1;
+      if mix != 0 {
+        crc ^= 0x8C;
+      }
+      b >>= 1;
+    }
+  }
+  crc
+}
+
+#[cfg(test)]
+mod test {
+  use super::crc8;
+
+  #[test]
+  fn computes_crc8() {
+    // Example from Maxim application note 27.
+    let rom = [0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2];
+    assert!(crc8(&rom[..7]) == 0xA2);
+    assert!(crc8(&rom) == 0);
+  }
+}
@@ -0,0 +1,253 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Bit-banged I2C master.
+
+Both lines are driven open-drain: a line is pulled low by switching the pin to
+output low, and released by switching it back to input, so external pull-ups
+are required. Devices may stretch the clock by holding SCL low, for up to
+`STRETCH_TIMEOUT_US`.
+*/
+
+use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::i2c;
+use hal::i2c::Error;
+use hal::pin::{Gpio, High, In, Out};
+use hal::timer::Timer;
+
+/// Longest time a device may hold SCL low, in microseconds.
+pub const STRETCH_TIMEOUT_US: u32 = 25_000;
+
+/// Bit-banged I2C master.
+pub struct I2C<'a, T: 'a, P: 'a> {
+  timer: &'a T,
+  scl: &'a P,
+  sda: &'a P,
+  half_period_us: u32,
+}
+
+impl<'a, T: Timer, P: Gpio> I2C<'a, T, P> {
+  /// Creates a new I2C master, running at up to `frequency` Hz, which must not
+  /// be 0.
+  pub fn new(timer: &'a T, scl: &'a P, sda: &'a P, frequency: u32)
+      -> I2C<'a, T, P> {
+    if frequency == 0 {
+      unsafe { abort() };
+    }
+    let half_period_us = 500_000 / frequency;
+    let i2c = I2C {
+      timer: timer,
+      scl: scl,
+      sda: sda,
+      half_period_us: if half_period_us == 0 { 1 } else { half_period_us },
+    };
+    i2c.release(sda);
+    i2c.release(scl);
+    i2c
+  }
+
+  fn release(&self, pin: &P) {
+    pin.set_direction(In);
+  }
+
+  fn pull_low(&self, pin: &P) {
+    pin.set_low();
+    pin.set_direction(Out);
+  }
+
+  fn delay(&self) {
+    self.timer.wait_us(self.half_period_us);
+  }
+
+  /// Releases SCL and waits for devices to stop stretching the clock.
+  fn scl_high(&self) -> Result<(), Error> {
+    self.release(self.scl);
+    let start = self.timer.get_counter();
+    while self.scl.level() != High {
+      if self.timer.get_counter().wrapping_sub(start) > STRETCH_TIMEOUT_US {
+        return Err(Error::Timeout);
+      }
+    }
+    Ok(())
+  }
+
+  /// Generates a start condition; also works as a repeated start when SCL is
+  /// low after a byte.
+  fn start(&self) -> Result<(), Error> {
+    self.release(self.sda);
+    self.delay();
+    try!(self.scl_high());
+    self.delay();
+    self.pull_low(self.sda);
+    self.delay();
+    self.pull_low(self.scl);
+    Ok(())
+  }
+
+  fn stop(&self) -> Result<(), Error> {
+    self.pull_low(self.sda);
+    self.delay();
+    try!(self.scl_high());
+    self.delay();
+    self.release(self.sda);
+    self.delay();
+    Ok(())
+  }
+
+  fn write_bit(&self, bit: bool) -> Result<(), Error> {
+    if bit {
+      self.release(self.sda);
+    } else {
+      self.pull_low(self.sda);
+    }
+    self.delay();
+    try!(self.scl_high());
+    self.delay();
+    self.pull_low(self.scl);
+    Ok(())
+  }
+
+  fn read_bit(&self) -> Result<bool, Error> {
+    self.release(self.sda);
+    self.delay();
+    try!(self.scl_high());
+    let bit = self.sda.level() == High;
+    self.delay();
+    self.pull_low(self.scl);
+    Ok(bit)
+  }
+
+  fn write_byte(&self, value: u8) -> Result<(), Error> {
+    for i in 0..8 {
+      try!(self.write_bit(value & (0x80 >> i) != 0));
+    }
+    match try!(self.read_bit()) {
+      true => Err(Error::Nack),
+      false => Ok(()),
+    }
+  }
+
+  /// Reads a byte, acknowledging it if more are to follow.
+  fn read_byte(&self, ack: bool) -> Result<u8, Error> {
+    let mut value: u8 = 0;
+    for _ in 0..8 {
+      value = (value << 1) | if try!(self.read_bit()) { 1 } else { 0 };
+    }
+    try!(self.write_bit(!ack));
+    Ok(value)
+  }
+
+  fn send(&self, address: u8, data: &[u8]) -> Result<(), Error> {
+    try!(self.start());
+    try!(self.write_byte(address << 1));
+    for &b in data.iter() {
+      try!(self.write_byte(b));
+    }
+    Ok(())
+  }
+
+  fn receive(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+    try!(self.start());
+    try!(self.write_byte((address << 1) | 1));
+    let len = buffer.len();
+    for i in 0..len {
+      buffer[i] = try!(self.read_byte(i + 1 < len));
+    }
+    Ok(())
+  }
+
+  /// Ends the transfer with a stop condition, whatever its result.
+  fn finish(&self, result: Result<(), Error>) -> Result<(), Error> {
+    let stop = self.stop();
+    match result {
+      Ok(()) => stop,
+      err => err,
+    }
+  }
+}
+
+impl<'a, T: Timer, P: Gpio> i2c::I2C for I2C<'a, T, P> {
+  fn write(&self, address: u8, data: &[u8]) -> Result<(), Error> {
+    let result = self.send(address, data);
+    self.finish(result)
+  }
+
+  fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+    let result = self.receive(address, buffer);
+    self.finish(result)
+  }
+
+  fn write_read(&self, address: u8, data: &[u8], buffer: &mut [u8])
+      -> Result<(), Error> {
+    let result = match self.send(address, data) {
+      Ok(()) => self.receive(address, buffer),
+      err => err,
+    };
+    self.finish(result)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::result::Result::{Ok, Err};
+
+  use hal::bitbang::test::{MockPin, MockTimer};
+  use hal::i2c::{I2C as I2CTrait, Error};
+  use hal::pin::{High, Low};
+  use super::I2C;
+
+  #[test]
+  fn nacks_without_device() {
+    let timer = MockTimer::new();
+    let sda = MockPin::new();
+    let scl = MockPin::sampling(&sda, High);
+    let i2c = I2C::new(&timer, &scl, &sda, 100_000);
+
+    assert!(i2c.write(0x50, &[0x12]) == Err(Error::Nack));
+    // Address with write bit, a missing ACK, then the stop condition.
+    assert!(scl.captured.get() == ((0xA0 << 1 | 1) << 1) | 0);
+    assert!(scl.line_level() == High);
+    assert!(sda.line_level() == High);
+  }
+
+  #[test]
+  fn times_out_when_clock_is_held() {
+    let timer = MockTimer::new();
+    let sda = MockPin::new();
+    let scl = MockPin::new();
+    scl.hold(Low);
+    let i2c = I2C::new(&timer, &scl, &sda, 100_000);
+
+    assert!(i2c.write(0x50, &[0x12]) == Err(Error::Timeout));
+  }
+
+  #[test]
+  fn transfers_with_acks() {
+    let timer = MockTimer::new();
+    let sda = MockPin::new();
+    let scl = MockPin::new();
+    sda.hold(Low);
+    let i2c = I2C::new(&timer, &scl, &sda, 100_000);
+
+    let mut buffer = [0xffu8; 2];
+    assert!(i2c.write(0x50, &[0x12, 0x34]) == Ok(()));
+    assert!(i2c.write_read(0x50, &[0x00], &mut buffer) == Ok(()));
+    assert!(buffer[0] == 0 && buffer[1] == 0);
+  }
+}
@@ -0,0 +1,154 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Software (bit-banged) peripherals.
+
+These are built only on `hal::pin::Gpio` and `hal::timer::Timer`, so they work
+on any MCU and can drive devices on pins without a free hardware peripheral.
+Timing is derived from the timer, which is expected to count microseconds;
+maximum bus speed is thus limited to a few hundred kHz, and interrupts
+stretch the bit times (which is fine for synchronous buses, but not for the
+UART).
+*/
+
+pub mod i2c;
+pub mod spi;
+pub mod uart;
+
+#[cfg(test)]
+pub mod test {
+  use core::cell::Cell;
+  use core::option::Option::{self, Some, None};
+
+  use hal::pin::{Gpio, GpioDirection, GpioLevel, High, Low, In, Out};
+  use hal::timer::Timer;
+
+  /// Timer advancing by one microsecond on every read.
+  pub struct MockTimer {
+    counter: Cell<u32>,
+  }
+
+  impl MockTimer {
+    pub fn new() -> MockTimer {
+      MockTimer { counter: Cell::new(0) }
+    }
+  }
+
+  impl Timer for MockTimer {
+    fn get_counter(&self) -> u32 {
+      let counter = self.counter.get();
+      self.counter.set(counter + 1);
+      counter
+    }
+  }
+
+  /// Pin with a pull-up.
+  ///
+  /// When not driven, the line level is taken from the pin it's wired to or
+  /// from what an external device holds it at. Every level written is shifted
+  /// into `history`, and the level of the watched pin is shifted into
+  /// `captured` whenever the line goes to `sample_on`.
+  pub struct MockPin<'a> {
+    output: Cell<GpioLevel>,
+    driven: Cell<bool>,
+    held: Cell<GpioLevel>,
+    wired_to: Option<&'a MockPin<'a>>,
+    watched: Option<&'a MockPin<'a>>,
+    sample_on: GpioLevel,
+    last: Cell<GpioLevel>,
+    pub history: Cell<u32>,
+    pub captured: Cell<u32>,
+  }
+
+  impl<'a> MockPin<'a> {
+    pub fn new() -> MockPin<'a> {
+      MockPin {
+        output: Cell::new(Low),
+        driven: Cell::new(false),
+        held: Cell::new(High),
+        wired_to: None,
+        watched: None,
+        sample_on: High,
+        last: Cell::new(High),
+        history: Cell::new(0),
+        captured: Cell::new(0),
+      }
+    }
+
+    /// A pin reading the level of `other`.
+    pub fn wired_to(other: &'a MockPin<'a>) -> MockPin<'a> {
+      MockPin { wired_to: Some(other), ..MockPin::new() }
+    }
+
+    /// A pin sampling `watched` on transitions to `level`.
+    pub fn sampling(watched: &'a MockPin<'a>, level: GpioLevel) -> MockPin<'a> {
+      MockPin { watched: Some(watched), sample_on: level, ..MockPin::new() }
+    }
+
+    /// Makes an external device hold the line at `level` when not driven.
+    pub fn hold(&self, level: GpioLevel) {
+      self.held.set(level);
+    }
+
+    pub fn line_level(&self) -> GpioLevel {
+      if self.driven.get() {
+        return self.output.get();
+      }
+      match self.wired_to {
+        Some(pin) => pin.line_level(),
+        None => self.held.get(),
+      }
+    }
+
+    fn update(&self) {
+      let level = self.line_level();
+      if level == self.last.get() {
+        return;
+      }
+      self.last.set(level);
+      if level == self.sample_on {
+        match self.watched {
+          Some(pin) => {
+            let bit = if pin.line_level() == High { 1 } else { 0 };
+            self.captured.set((self.captured.get() << 1) | bit);
+          },
+          None => (),
+        }
+      }
+    }
+
+    fn write(&self, level: GpioLevel) {
+      let bit = if level == High { 1 } else { 0 };
+      self.history.set((self.history.get() << 1) | bit);
+      self.output.set(level);
+      self.update();
+    }
+  }
+
+  impl<'a> Gpio for MockPin<'a> {
+    fn set_high(&self) { self.write(High); }
+    fn set_low(&self) { self.write(Low); }
+    fn level(&self) -> GpioLevel { self.line_level() }
+
+    fn set_direction(&self, new_mode: GpioDirection) {
+      self.driven.set(match new_mode {
+        In => false,
+        Out => true,
+      });
+      self.update();
+    }
+  }
+}
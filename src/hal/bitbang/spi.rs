@@ -0,0 +1,174 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Bit-banged SPI master.
+
+All four SPI modes are supported, data is sent MSB first, 8 bits at a time.
+The chip-select pin is not managed here.
+*/
+
+use core::cell::Cell;
+use core::intrinsics::abort;
+
+use hal::pin::{Gpio, GpioLevel, High, Low, In, Out};
+use hal::spi;
+use hal::timer::Timer;
+
+/// Bit-banged SPI master.
+pub struct Spi<'a, T: 'a, P: 'a> {
+  timer: &'a T,
+  sck: &'a P,
+  mosi: &'a P,
+  miso: &'a P,
+  idle: GpioLevel,
+  sample_on_leading: bool,
+  half_period_us: u32,
+  last_read: Cell<u8>,
+}
+
+impl<'a, T: Timer, P: Gpio> Spi<'a, T, P> {
+  /// Creates a new SPI master.
+  ///
+  /// `mode` is the SPI mode number (0-3), `frequency` is the bus frequency in
+  /// Hz; it's rounded down to a whole number of microseconds per half clock.
+  pub fn new(timer: &'a T, sck: &'a P, mosi: &'a P, miso: &'a P, mode: u8,
+      frequency: u32) -> Spi<'a, T, P> {
+    if mode > 3 || frequency == 0 {
+      unsafe { abort() };
+    }
+    let half_period_us = 500_000 / frequency;
+
+    let spi = Spi {
+      timer: timer,
+      sck: sck,
+      mosi: mosi,
+      miso: miso,
+      idle: if mode & 0x2 != 0 { High } else { Low },
+      sample_on_leading: mode & 0x1 == 0,
+      half_period_us: if half_period_us == 0 { 1 } else { half_period_us },
+      last_read: Cell::new(0),
+    };
+
+    spi.set_sck(false);
+    sck.set_direction(Out);
+    mosi.set_direction(Out);
+    miso.set_direction(In);
+    spi
+  }
+
+  fn set_sck(&self, active: bool) {
+    match (self.idle, active) {
+      (Low, false) | (High, true) => self.sck.set_low(),
+      _ => self.sck.set_high(),
+    }
+  }
+
+  fn set_mosi(&self, bit: bool) {
+    if bit {
+      self.mosi.set_high();
+    } else {
+      self.mosi.set_low();
+    }
+  }
+
+  fn sample_miso(&self) -> u8 {
+    if self.miso.level() == High { 1 } else { 0 }
+  }
+
+  /// Shifts out `value` while shifting in a byte from MISO.
+  fn exchange(&self, value: u8) -> u8 {
+    let mut read: u8 = 0;
+    for i in 0..8 {
+      let bit = value & (0x80 >> i) != 0;
+      if self.sample_on_leading {
+        self.set_mosi(bit);
+        self.timer.wait_us(self.half_period_us);
+        self.set_sck(true);
+        read = (read << 1) | self.sample_miso();
+        self.timer.wait_us(self.half_period_us);
+        self.set_sck(false);
+      } else {
+        self.set_sck(true);
+        self.set_mosi(bit);
+        self.timer.wait_us(self.half_period_us);
+        self.set_sck(false);
+        read = (read << 1) | self.sample_miso();
+        self.timer.wait_us(self.half_period_us);
+      }
+    }
+    read
+  }
+}
+
+impl<'a, T: Timer, P: Gpio> spi::Spi for Spi<'a, T, P> {
+  fn write(&self, value: u8) {
+    self.last_read.set(self.exchange(value));
+  }
+
+  fn read(&self) -> u8 {
+    self.last_read.get()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use hal::bitbang::test::{MockPin, MockTimer};
+  use hal::pin::{High, Low};
+  use hal::spi::Spi as SpiTrait;
+  use super::Spi;
+
+  #[test]
+  fn loops_back_in_all_modes() {
+    for mode in 0..4 {
+      let timer = MockTimer::new();
+      let sck = MockPin::new();
+      let mosi = MockPin::new();
+      let miso = MockPin::wired_to(&mosi);
+      let spi = Spi::new(&timer, &sck, &mosi, &miso, mode, 100_000);
+      assert!(spi.transfer(0xA5) == 0xA5);
+      assert!(spi.transfer(0x3C) == 0x3C);
+    }
+  }
+
+  #[test]
+  fn sck_idles_at_polarity() {
+    let timer = MockTimer::new();
+    let mosi = MockPin::new();
+    let miso = MockPin::new();
+
+    let sck = MockPin::new();
+    Spi::new(&timer, &sck, &mosi, &miso, 1, 100_000).write(0xff);
+    assert!(sck.line_level() == Low);
+
+    let sck = MockPin::new();
+    Spi::new(&timer, &sck, &mosi, &miso, 2, 100_000).write(0xff);
+    assert!(sck.line_level() == High);
+  }
+
+  #[test]
+  fn data_is_valid_on_sampling_edge() {
+    // (mode, SCK level at the edge a slave samples on)
+    for &(mode, edge) in [(0, High), (1, Low), (2, Low), (3, High)].iter() {
+      let timer = MockTimer::new();
+      let mosi = MockPin::new();
+      let miso = MockPin::new();
+      let sck = MockPin::sampling(&mosi, edge);
+      let spi = Spi::new(&timer, &sck, &mosi, &miso, mode, 100_000);
+      spi.write(0x96);
+      assert!(sck.captured.get() == 0x96);
+    }
+  }
+}
@@ -0,0 +1,91 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Bit-banged UART transmitter.
+
+Frames are 8N1. Bit times are measured from the start of the frame, so the
+timing error doesn't accumulate over a character, but an interrupt arriving
+mid-frame can still corrupt it; keep the baud rate low or disable interrupts
+around output.
+*/
+
+use core::intrinsics::abort;
+
+use drivers::chario::CharIO;
+use hal::pin::{Gpio, Out};
+use hal::timer::Timer;
+
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+/// Bit-banged UART transmitter.
+pub struct Uart<'a, T: 'a, P: 'a> {
+  timer: &'a T,
+  tx: &'a P,
+  baudrate: u32,
+}
+
+impl<'a, T: Timer, P: Gpio> Uart<'a, T, P> {
+  /// Creates a new transmitter on `tx`, leaving the line idle (high).
+  ///
+  /// `baudrate` must not be 0.
+  pub fn new(timer: &'a T, tx: &'a P, baudrate: u32) -> Uart<'a, T, P> {
+    if baudrate == 0 {
+      unsafe { abort() };
+    }
+    tx.set_high();
+    tx.set_direction(Out);
+    Uart {
+      timer: timer,
+      tx: tx,
+      baudrate: baudrate,
+    }
+  }
+}
+
+impl<'a, T: Timer, P: Gpio> CharIO for Uart<'a, T, P> {
+  fn putc(&self, value: char) {
+    // Start bit, 8 data bits LSB first, stop bit.
+    let frame: u32 = ((value as u32 & 0xff) << 1) | (1 << 9);
+    let start = self.timer.get_counter();
+    for i in 0..10 {
+      if frame & (1 << i) != 0 {
+        self.tx.set_high();
+      } else {
+        self.tx.set_low();
+      }
+      let end = (i + 1) * 1_000_000 / self.baudrate;
+      wait_for!(self.timer.get_counter().wrapping_sub(start) >= end);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use drivers::chario::CharIO;
+  use hal::bitbang::test::{MockPin, MockTimer};
+  use super::Uart;
+
+  #[test]
+  fn sends_8n1_frame() {
+    let timer = MockTimer::new();
+    let tx = MockPin::new();
+    let uart = Uart::new(&timer, &tx, 9600);
+    uart.putc('A');
+    // Idle, start, 0x41 LSB first, stop.
+    assert!(tx.history.get() == 0b1_0_10000010_1);
+  }
+}
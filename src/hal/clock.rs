@@ -0,0 +1,141 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Common interface to clock tree frequencies.
+
+Every MCU provides a `Clocks` object implementing `ClockTree`, and its
+peripheral clocks implement `PeripheralFrequency`. Drivers computing baud
+rates, dividers or prescalers should take these instead of calling MCU-specific
+functions.
+
+On MCUs without separate AHB/APB domains the bus frequencies are reported as
+the closest equivalent; the frequency of a given peripheral clock is always
+authoritative.
+
+All frequencies are in Hz and reflect the current hardware configuration.
+
+When the clock tree is reconfigured at runtime, registered `ClockListener`s are
+notified so peripherals can recompute their dividers.
+*/
+
+use core::option::Option::{self, Some, None};
+
+/// A clock domain.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bus {
+  /// Clock driving the CPU core.
+  Core,
+  /// High-performance bus, memories and DMA.
+  Ahb,
+  /// Low-speed peripheral bus.
+  Apb1,
+  /// High-speed peripheral bus.
+  Apb2,
+}
+
+/// Frequencies of the main clock domains.
+pub trait ClockTree {
+  /// Returns the core clock frequency.
+  fn core_frequency(&self) -> u32;
+  /// Returns the AHB clock frequency.
+  fn ahb_frequency(&self) -> u32;
+  /// Returns the APB1 (low-speed) clock frequency.
+  fn apb1_frequency(&self) -> u32;
+  /// Returns the APB2 (high-speed) clock frequency.
+  fn apb2_frequency(&self) -> u32;
+
+  /// Returns the frequency of the given domain.
+  fn frequency(&self, bus: Bus) -> u32 {
+    match bus {
+      Bus::Core => self.core_frequency(),
+      Bus::Ahb  => self.ahb_frequency(),
+      Bus::Apb1 => self.apb1_frequency(),
+      Bus::Apb2 => self.apb2_frequency(),
+    }
+  }
+}
+
+/// Frequency of a clock feeding a single peripheral.
+pub trait PeripheralFrequency {
+  /// Returns the frequency of the clock feeding the peripheral.
+  fn frequency(&self) -> u32;
+}
+
+/// Number of checks a listener makes for a transfer in flight to complete,
+/// with a `SpinDeadline`, before letting the clock change go ahead anyway.
+///
+/// A stuck transmitter, e.g. with CTS held, then loses its transfer instead of
+/// hanging the clock switch.
+pub const DRAIN_SPINS: u32 = 1_000_000;
+
+/// Receives notifications about runtime clock tree changes.
+pub trait ClockListener {
+  /// Called before the clock tree changes, e.g. to let transfers in flight
+  /// complete. It must not wait without bound, see `DRAIN_SPINS`.
+  fn clock_will_change(&self) {}
+
+  /// Called once the new configuration is in effect.
+  fn clock_changed(&self);
+}
+
+/// Maximum number of registered clock listeners.
+pub const MAX_LISTENERS: usize = 8;
+
+static mut Listeners: [Option<&'static ClockListener>; MAX_LISTENERS] =
+    [None; MAX_LISTENERS];
+
+/// Registers a listener to be notified about clock changes.
+///
+/// Returns false if there's no room left.
+pub fn add_listener(listener: &'static ClockListener) -> bool {
+  unsafe {
+    for slot in Listeners.iter_mut() {
+      if slot.is_none() {
+        *slot = Some(listener);
+        return true;
+      }
+    }
+  }
+  false
+}
+
+/// Notifies listeners that the clock tree is about to change.
+///
+/// Called by MCU-specific code.
+pub fn notify_will_change() {
+  unsafe {
+    for slot in Listeners.iter() {
+      match *slot {
+        Some(listener) => listener.clock_will_change(),
+        None => (),
+      }
+    }
+  }
+}
+
+/// Notifies listeners that the clock tree has changed.
+///
+/// Called by MCU-specific code.
+pub fn notify_changed() {
+  unsafe {
+    for slot in Listeners.iter() {
+      match *slot {
+        Some(listener) => listener.clock_changed(),
+        None => (),
+      }
+    }
+  }
+}
@@ -0,0 +1,64 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Interface to the cycle counter of the Data Watchpoint and Trace unit.
+//!
+//! DWT memory location is 0xE000_1000. Not present on Cortex-M0.
+
+#[inline(always)]
+fn get_reg() -> &'static reg::DWT {
+  unsafe { &*(0xE000_1000 as *mut reg::DWT) }
+}
+
+#[inline(always)]
+fn get_debug_reg() -> &'static reg::DEBUG {
+  unsafe { &*(0xE000_EDFC as *mut reg::DEBUG) }
+}
+
+/// Enables trace and starts the cycle counter from zero.
+pub fn enable_cycle_counter() {
+  get_debug_reg().demcr.set_trcena(true);
+  get_reg().cyccnt.set_count(0);
+  get_reg().ctrl.set_cyccntena(true);
+}
+
+/// Returns the number of core clock cycles, wrapping at 32 bits.
+pub fn cycle_count() -> u32 {
+  get_reg().cyccnt.count()
+}
+
+#[allow(dead_code)]
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(DWT = {
+    /// Control Register
+    0x0 => reg32 ctrl {
+      0 => cyccntena : rw,    //= Enable the cycle counter
+    },
+
+    /// Cycle Count Register
+    0x4 => reg32 cyccnt {
+      31..0 => count : rw,    //= Core clock cycles
+    },
+  });
+
+  ioregs!(DEBUG = {
+    /// Debug Exception and Monitor Control Register
+    0x0 => reg32 demcr {
+      24 => trcena : rw,      //= Enable DWT and ITM
+    },
+  });
+}
@@ -0,0 +1,275 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Fault handler and crash reporter.
+
+All fault exceptions (and any other exception without a handler) end up in
+`zinc_fault_handler`, which decodes the fault status registers and the register
+frame stacked on exception entry, prints a report to the output set with
+`setup` and then either resets the system or stops at a breakpoint.
+
+MemManage, BusFault and UsageFault escalate to HardFault unless enabled, which
+`setup` does; the report is the same either way, as the cause is taken from
+CFSR.
+*/
+
+use core::option::Option::{self, Some, None};
+
+use drivers::chario::CharIO;
+use super::scb;
+
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+const HFSR_VECTTBL: u32 = 1 << 1;
+const HFSR_FORCED: u32 = 1 << 30;
+
+/// EXC_RETURN bit set when returning to the process stack.
+const EXC_RETURN_PSP: u32 = 1 << 2;
+
+/// Fault causes in CFSR, by bit.
+const CAUSES: [(u32, &'static str); 16] = [
+  (1 << 0,  "instruction access violation"),
+  (1 << 1,  "data access violation"),
+  (1 << 3,  "MemManage fault on unstacking"),
+  (1 << 4,  "MemManage fault on stacking"),
+  (1 << 5,  "MemManage fault on FP lazy state preservation"),
+  (1 << 8,  "instruction bus error"),
+  (1 << 9,  "precise data bus error"),
+  (1 << 10, "imprecise data bus error"),
+  (1 << 11, "BusFault on unstacking"),
+  (1 << 12, "BusFault on stacking"),
+  (1 << 13, "BusFault on FP lazy state preservation"),
+  (1 << 16, "undefined instruction"),
+  (1 << 17, "invalid state (Thumb bit cleared)"),
+  (1 << 18, "invalid PC load on exception return"),
+  (1 << 19, "no coprocessor"),
+  (1 << 24, "unaligned access"),
+];
+const DIVBYZERO: u32 = 1 << 25;
+
+static mut Output: Option<&'static CharIO> = None;
+static mut ResetOnFault: bool = false;
+static mut FaultHook: Option<fn(&FaultReport)> = None;
+
+/// Fault type, from the active exception number.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Fault {
+  /// HardFault, or a configurable fault escalated to it.
+  HardFault,
+  /// Memory protection fault.
+  MemManage,
+  /// Bus error.
+  BusFault,
+  /// Instruction execution fault.
+  UsageFault,
+  /// Any other exception that has no handler.
+  Unhandled(u32),
+}
+
+/// Registers stacked by the hardware on exception entry.
+#[repr(C)]
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub struct ExceptionFrame {
+  pub r0: u32,
+  pub r1: u32,
+  pub r2: u32,
+  pub r3: u32,
+  pub r12: u32,
+  pub lr: u32,
+  pub pc: u32,
+  pub psr: u32,
+}
+
+/// Decoded fault state.
+#[derive(Clone, Copy)]
+pub struct FaultReport {
+  /// Fault type.
+  pub fault: Fault,
+  /// Registers at the time of the fault.
+  pub frame: ExceptionFrame,
+  /// Configurable fault status register.
+  pub cfsr: u32,
+  /// HardFault status register.
+  pub hfsr: u32,
+  /// Faulting data address, if known.
+  pub address: Option<u32>,
+  /// Stack pointer before the exception entry, ignoring FP state and
+  /// alignment padding.
+  pub sp: u32,
+  /// Index of the task running when the fault happened, if the fault happened
+  /// in a task.
+  pub task: Option<usize>,
+}
+
+/// Sets the report output and whether to reset after a fault, and enables the
+/// configurable fault handlers.
+pub fn setup(output: Option<&'static CharIO>, reset: bool) {
+  unsafe {
+    Output = output;
+    ResetOnFault = reset;
+  }
+  scb::enable_fault_handlers();
+}
+
+/// Sets a function to be called with the report before it's printed, e.g.
+/// to store it.
+///
+/// The hook runs in the fault handler, so it must not rely on interrupts.
+pub fn set_hook(hook: Option<fn(&FaultReport)>) {
+  unsafe { FaultHook = hook };
+}
+
+/// Returns the first cause of a fault from CFSR and HFSR.
+pub fn cause(cfsr: u32, hfsr: u32) -> &'static str {
+  for &(bit, description) in CAUSES.iter() {
+    if cfsr & bit != 0 {
+      return description;
+    }
+  }
+  if cfsr & DIVBYZERO != 0 {
+    "division by zero"
+  } else if hfsr & HFSR_VECTTBL != 0 {
+    "BusFault on vector table read"
+  } else {
+    "unknown"
+  }
+}
+
+fn put_hex(out: &CharIO, name: &str, value: u32) {
+  out.puts(name);
+  out.puts("0x");
+  out.putint(value, 16);
+}
+
+impl FaultReport {
+  /// Collects the fault state, `frame` being the stacked registers and
+  /// `exc_return` the LR value on exception entry.
+  pub fn new(frame: &ExceptionFrame, exc_return: u32) -> FaultReport {
+    let cfsr = scb::cfsr();
+    let fault = match scb::vectactive() {
+      3 => Fault::HardFault,
+      4 => Fault::MemManage,
+      5 => Fault::BusFault,
+      6 => Fault::UsageFault,
+      n => Fault::Unhandled(n),
+    };
+    let address = if cfsr & MMARVALID != 0 {
+      Some(scb::mmfar())
+    } else if cfsr & BFARVALID != 0 {
+      Some(scb::bfar())
+    } else {
+      None
+    };
+    let task = if exc_return & EXC_RETURN_PSP != 0 {
+      current_task()
+    } else {
+      None
+    };
+
+    FaultReport {
+      fault: fault,
+      frame: *frame,
+      cfsr: cfsr,
+      hfsr: scb::hfsr(),
+      address: address,
+      sp: frame as *const ExceptionFrame as u32 + 0x20,
+      task: task,
+    }
+  }
+
+  /// Prints a human-readable report.
+  pub fn print(&self, out: &CharIO) {
+    out.puts("\n*** ");
+    match self.fault {
+      Fault::HardFault => out.puts("HardFault"),
+      Fault::MemManage => out.puts("MemManage fault"),
+      Fault::BusFault => out.puts("BusFault"),
+      Fault::UsageFault => out.puts("UsageFault"),
+      Fault::Unhandled(n) => {
+        out.puts("unhandled exception ");
+        out.puti(n);
+      },
+    }
+    if self.hfsr & HFSR_FORCED != 0 {
+      out.puts(" (escalated)");
+    }
+    out.puts(": ");
+    out.puts(cause(self.cfsr, self.hfsr));
+    out.puts("\n");
+
+    put_hex(out, "pc  ", self.frame.pc);
+    put_hex(out, "  lr  ", self.frame.lr);
+    put_hex(out, "  psr ", self.frame.psr);
+    put_hex(out, "  sp  ", self.sp);
+    out.puts("\n");
+    put_hex(out, "r0  ", self.frame.r0);
+    put_hex(out, "  r1  ", self.frame.r1);
+    put_hex(out, "  r2  ", self.frame.r2);
+    put_hex(out, "  r3  ", self.frame.r3);
+    put_hex(out, "  r12 ", self.frame.r12);
+    out.puts("\n");
+    put_hex(out, "cfsr ", self.cfsr);
+    put_hex(out, "  hfsr ", self.hfsr);
+    match self.address {
+      Some(address) => put_hex(out, "  address ", address),
+      None => (),
+    }
+    out.puts("\n");
+    match self.task {
+      Some(task) => {
+        out.puts("task ");
+        out.puti(task as u32);
+        out.puts("\n");
+      },
+      None => (),
+    }
+  }
+}
+
+#[cfg(feature = "multitasking")]
+fn current_task() -> Option<usize> {
+  Some(unsafe { ::os::task::Tasks.current_task })
+}
+
+#[cfg(not(feature = "multitasking"))]
+fn current_task() -> Option<usize> {
+  None
+}
+
+/// Entry point from `isr_default_fault`, called with the stacked register
+/// frame and EXC_RETURN.
+#[no_mangle]
+pub unsafe extern fn zinc_fault_handler(frame: *const ExceptionFrame,
+    exc_return: u32) -> ! {
+  let report = FaultReport::new(&*frame, exc_return);
+  match FaultHook {
+    Some(hook) => hook(&report),
+    None => (),
+  }
+  match Output {
+    Some(out) => report.print(out),
+    None => (),
+  }
+
+  if ResetOnFault {
+    scb::system_reset();
+  }
+  loop {
+    ::core::intrinsics::breakpoint();
+  }
+}
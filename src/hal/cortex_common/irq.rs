@@ -14,9 +14,17 @@
 // limitations under the License.
 
 //! Disabling and enabling interrupts
+//!
+//! `NoInterrupts` masks all interrupts with PRIMASK. On cores with BASEPRI
+//! (all but Cortex-M0), `PriorityMask` masks only interrupts at or below a
+//! priority ceiling, so that higher priority interrupts keep their latency;
+//! data shared with a critical section must then never be touched by those.
+//!
+//! Priorities follow NVIC conventions: lower values are more urgent, and only
+//! the implemented most significant bits count.
 
 use core::ops::Drop;
-#[cfg(target_os = "none")]
+#[cfg(any(target_os = "none", not(feature = "cpu_cortex-m0")))]
 use core::intrinsics::abort;
 
 /// Phantom type to indicate that interrupts are disabled.
@@ -43,6 +51,103 @@ impl Drop for NoInterrupts {
   }
 }
 
+/// Types proving that a critical section is active.
+///
+/// Implementing this is unsafe: while a value of the type is alive, nothing
+/// that touches the protected data may preempt its owner.
+pub unsafe trait CriticalSection {}
+
+unsafe impl CriticalSection for NoInterrupts {}
+
+/// Phantom type to indicate that interrupts at or below a priority ceiling
+/// are masked.
+///
+/// Sections nest: an inner section can only raise the ceiling, and the
+/// previous one is restored on drop.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+pub struct PriorityMask {
+  previous: u8,
+}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+impl PriorityMask {
+  /// Start a new critical section, masking interrupts with priority values
+  /// greater than or equal to `ceiling`.
+  ///
+  /// `ceiling` must not be 0, as priority 0 can't be masked with BASEPRI: a
+  /// BASEPRI of 0 masks nothing at all, so this aborts.
+  pub fn new(ceiling: u8) -> PriorityMask {
+    if ceiling == 0 {
+      unsafe { abort() };
+    }
+    let previous = unsafe { get_basepri() };
+    unsafe { raise_basepri(ceiling) };
+    PriorityMask { previous: previous }
+  }
+}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+impl Drop for PriorityMask {
+  fn drop(&mut self) {
+    unsafe { set_basepri(self.previous) };
+  }
+}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+unsafe impl CriticalSection for PriorityMask {}
+
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+unsafe fn get_basepri() -> u8 {
+  let val: u32;
+  asm!("mrs $0, basepri" : "=r"(val) ::: "volatile");
+  val as u8
+}
+
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+unsafe fn set_basepri(val: u8) {
+  asm!("msr basepri, $0" :: "r"(val as u32) :: "volatile");
+}
+
+/// Sets BASEPRI to `val` only if that masks more interrupts.
+#[cfg(all(target_os = "none",
+          not(feature = "cpu_cortex-m0"),
+          not(feature = "cpu_cortex-m7")))]
+#[inline(always)]
+unsafe fn raise_basepri(val: u8) {
+  asm!("msr basepri_max, $0" :: "r"(val as u32) :: "volatile");
+}
+
+/// Cortex-M7 r0p1 may take an interrupt right after a BASEPRI write that
+/// should have masked it (ARM erratum 837070), so writes are done with
+/// PRIMASK set.
+#[cfg(all(target_os = "none", feature = "cpu_cortex-m7"))]
+#[inline(always)]
+unsafe fn raise_basepri(val: u8) {
+  asm!("mrs r1, primask
+        cpsid i
+        msr basepri_max, $0
+        msr primask, r1" :: "r"(val as u32) : "r1" : "volatile");
+}
+
+/// BASEPRI of host builds, so that tests can enter priority masks.
+#[cfg(all(not(target_os = "none"), not(feature = "cpu_cortex-m0")))]
+static mut host_basepri: u8 = 0;
+
+#[cfg(all(not(target_os = "none"), not(feature = "cpu_cortex-m0")))]
+unsafe fn get_basepri() -> u8 { host_basepri }
+
+#[cfg(all(not(target_os = "none"), not(feature = "cpu_cortex-m0")))]
+unsafe fn set_basepri(val: u8) { host_basepri = val; }
+
+#[cfg(all(not(target_os = "none"), not(feature = "cpu_cortex-m0")))]
+unsafe fn raise_basepri(val: u8) {
+  if val != 0 && (host_basepri == 0 || val < host_basepri) {
+    host_basepri = val;
+  }
+}
+
 #[cfg(target_os = "none")]
 static mut irq_level : usize = 0;
 
@@ -77,3 +182,25 @@ unsafe fn enable_irqs() {
 
 #[cfg(not(target_os = "none"))]
 unsafe fn enable_irqs() { unimplemented!() }
+
+#[cfg(all(test, not(feature = "cpu_cortex-m0")))]
+mod test {
+  use core::mem::drop;
+
+  use super::{PriorityMask, get_basepri};
+
+  #[test]
+  fn nests_priority_masks() {
+    let outer = PriorityMask::new(0x40);
+    assert!(unsafe { get_basepri() } == 0x40);
+    {
+      let _inner = PriorityMask::new(0x20);
+      assert!(unsafe { get_basepri() } == 0x20);
+      let _lower = PriorityMask::new(0x80);
+      assert!(unsafe { get_basepri() } == 0x20);
+    }
+    assert!(unsafe { get_basepri() } == 0x40);
+    drop(outer);
+    assert!(unsafe { get_basepri() } == 0);
+  }
+}
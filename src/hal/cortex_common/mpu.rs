@@ -16,15 +16,233 @@
 //! Interface to Memory Protection Unit.
 //!
 //! MPU memory location is 0xE000_ED90.
+//!
+//! Regions are numbered from 0; where regions overlap, the attributes of the
+//! highest numbered one apply. Sizes are powers of two from 32 bytes to 4GB,
+//! and the base address must be aligned to the size. Regions of 256 bytes and
+//! more are split into 8 subregions that can be individually disabled.
+//!
+//! The stack guard is a no-access region placed right below the current stack
+//! limit, so that a stack overflow causes a MemManage fault instead of
+//! silently overwriting whatever is below. Once enabled, the OS moves it along
+//! with the stack limit on every task switch.
 //  Link: http://infocenter.arm.com/help/topic/com.arm.doc.dui0552a/BIHJJABA.html
 
-// TODO(bharrisau): Remove dead_code when MPU is implemented.
-#[allow(dead_code)]
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use util::support::{dsb, isb};
+
+/// Size of the stack guard region.
+pub const STACK_GUARD_SIZE: u32 = 32;
+
+static mut StackGuardRegion: Option<u8> = None;
+
+/// Errors in region configuration.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// Region number is not implemented by the MPU.
+  InvalidRegion,
+  /// Size is not a power of two between 32 bytes and 4GB.
+  InvalidSize,
+  /// Base address is not aligned to the size.
+  Misaligned,
+}
+
+/// Access permissions, for privileged/unprivileged code.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+  /// No access at all.
+  NoAccess = 0,
+  /// Privileged read/write, unprivileged no access.
+  PrivilegedReadWrite = 1,
+  /// Privileged read/write, unprivileged read only.
+  UnprivilegedReadOnly = 2,
+  /// Read/write for everyone.
+  ReadWrite = 3,
+  /// Privileged read only, unprivileged no access.
+  PrivilegedReadOnly = 5,
+  /// Read only for everyone.
+  ReadOnly = 6,
+}
+
+/// Memory type and cache policy.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Attributes {
+  /// Strongly ordered, always shared.
+  StronglyOrdered,
+  /// Shared device (peripherals).
+  Device,
+  /// Normal memory, not cached.
+  NonCacheable,
+  /// Normal memory, write-through, no write allocate.
+  WriteThrough,
+  /// Normal memory, write-back, no write allocate.
+  WriteBack,
+  /// Normal memory, write-back, read and write allocate.
+  WriteBackAllocate,
+}
+
+impl Attributes {
+  /// Returns the (TEX, C, B) encoding.
+  fn encoding(self) -> (u32, bool, bool) {
+    match self {
+      Attributes::StronglyOrdered   => (0, false, false),
+      Attributes::Device            => (0, false, true),
+      Attributes::WriteThrough      => (0, true,  false),
+      Attributes::WriteBack         => (0, true,  true),
+      Attributes::NonCacheable      => (1, false, false),
+      Attributes::WriteBackAllocate => (1, true,  true),
+    }
+  }
+}
+
+/// MPU region definition.
+#[derive(Clone, Copy)]
+pub struct Region {
+  /// Base address, aligned to `size`.
+  pub base: u32,
+  /// Size in bytes, a power of two of at least 32. 0 stands for 4GB.
+  pub size: u32,
+  /// Subregions to disable, bit n for the n-th eighth of the region.
+  pub subregion_disable: u8,
+  /// Access permissions.
+  pub access: Access,
+  /// Allows instruction fetches.
+  pub executable: bool,
+  /// Memory type and cache policy.
+  pub attributes: Attributes,
+  /// Shareable between bus masters; only used with normal memory.
+  pub shareable: bool,
+}
+
 #[inline(always)]
 fn get_reg() -> &'static reg::MPU {
   unsafe { &*(0xE000_ED90 as *mut reg::MPU) }
 }
 
+/// Returns the number of regions supported, 0 if there's no MPU.
+pub fn regions() -> u8 {
+  get_reg().mpu_type.dregion() as u8
+}
+
+/// Returns the RASR size field for `size`.
+fn size_field(size: u32) -> Result<u32, Error> {
+  match size {
+    0 => Ok(31),
+    s if s < 32 || s & (s - 1) != 0 => Err(Error::InvalidSize),
+    s => Ok(31 - s.leading_zeros() - 1),
+  }
+}
+
+/// Configures and enables region `number`.
+///
+/// The change takes effect right away if the MPU is enabled, so reconfiguring
+/// a region that covers the running code or stack needs care.
+pub fn set_region(number: u8, region: &Region) -> Result<(), Error> {
+  if number >= regions() {
+    return Err(Error::InvalidRegion);
+  }
+  let size = try!(size_field(region.size));
+  if region.size != 0 && region.base & (region.size - 1) != 0 {
+    return Err(Error::Misaligned);
+  }
+  let (tex, c, b) = region.attributes.encoding();
+
+  let mpu = get_reg();
+  mpu.rnr.set_region(number as u32);
+  mpu.rasr.set_enable(false);
+  mpu.rbar.ignoring_state().set_addr(region.base >> 5);
+  mpu.rasr.ignoring_state()
+    .set_enable(true)
+    .set_size(size)
+    .set_srd(region.subregion_disable as u32)
+    .set_b(b)
+    .set_c(c)
+    .set_s(region.shareable)
+    .set_tex(tex)
+    .set_ap(region.access as u32)
+    .set_xn(!region.executable);
+  dsb();
+  isb();
+  Ok(())
+}
+
+/// Disables region `number`.
+pub fn disable_region(number: u8) {
+  let mpu = get_reg();
+  mpu.rnr.set_region(number as u32);
+  mpu.rasr.set_enable(false);
+  dsb();
+  isb();
+}
+
+/// Enables the MPU.
+///
+/// With `background` set, privileged code can access all memory not covered
+/// by any region using the default memory map; otherwise any such access
+/// faults. `during_faults` keeps the MPU enabled in HardFault and NMI
+/// handlers.
+pub fn enable(background: bool, during_faults: bool) {
+  get_reg().ctrl.ignoring_state()
+    .set_enable(true)
+    .set_privdefena(background)
+    .set_hfnmiena(during_faults);
+  dsb();
+  isb();
+}
+
+/// Returns true if the MPU is enabled.
+pub fn is_enabled() -> bool {
+  get_reg().ctrl.enable()
+}
+
+/// Disables the MPU.
+pub fn disable() {
+  dsb();
+  get_reg().ctrl.ignoring_state().set_enable(false);
+  isb();
+}
+
+/// Configures region `number` as a no-access guard below a stack that grows
+/// down to `stack_limit`.
+///
+/// The guard is aligned to its size, so if `stack_limit` isn't, up to
+/// `STACK_GUARD_SIZE - 4` bytes between the guard and the stack are left
+/// unguarded. The bottom of the stack, which may hold a canary, stays
+/// accessible.
+pub fn set_stack_guard(number: u8, stack_limit: u32) -> Result<(), Error> {
+  set_region(number, &Region {
+    base: (stack_limit & !(STACK_GUARD_SIZE - 1)) - STACK_GUARD_SIZE,
+    size: STACK_GUARD_SIZE,
+    subregion_disable: 0,
+    access: Access::NoAccess,
+    executable: false,
+    attributes: Attributes::StronglyOrdered,
+    shareable: false,
+  })
+}
+
+/// Uses region `number` to guard the current stack, and keeps it below the
+/// stack limit on task switches.
+///
+/// The region should be the highest numbered one in use, so that no other
+/// region overrides it.
+pub fn enable_stack_guard(number: u8) -> Result<(), Error> {
+  try!(set_stack_guard(number, ::hal::stack::stack_limit()));
+  unsafe { StackGuardRegion = Some(number) };
+  Ok(())
+}
+
+/// Moves the stack guard, if enabled, below a new stack limit.
+pub fn move_stack_guard(stack_limit: u32) {
+  match unsafe { StackGuardRegion } {
+    Some(number) => { let _ = set_stack_guard(number, stack_limit); },
+    None => (),
+  }
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
@@ -39,6 +39,128 @@ pub fn set_pendsv(val: bool) {
   }
 }
 
+/// Returns true if the SysTick exception is pending.
+pub fn is_systick_pending() -> bool {
+  get_reg().icsr.pendstset()
+}
+
+/// Selects deep sleep (`true`) or sleep (`false`) as the low power mode
+/// entered by `wfi`.
+pub fn set_sleepdeep(val: bool) {
+  get_reg().scr.set_sleepdeep(val);
+}
+
+/// Enables or disables entering sleep on return from an ISR to thread mode.
+pub fn set_sleeponexit(val: bool) {
+  get_reg().scr.set_sleeponexit(val);
+}
+
+/// Enables or disables wake up from `wfe` on any pending interrupt,
+/// including disabled ones.
+pub fn set_sevonpend(val: bool) {
+  get_reg().scr.set_sevonpend(val);
+}
+
+/// Returns the exception number currently being handled, 0 in thread mode.
+pub fn vectactive() -> u32 {
+  get_reg().icsr.vectactive()
+}
+
+/// Enables the MemManage, BusFault and UsageFault handlers. When disabled,
+/// these faults escalate to HardFault.
+pub fn enable_fault_handlers() {
+  get_reg().shcsr
+    .set_memfaultena(true)
+    .set_busfaultena(true)
+    .set_usgfaultena(true);
+}
+
+/// Returns the configurable fault status register.
+pub fn cfsr() -> u32 {
+  get_reg().cfsr.get().raw()
+}
+
+/// Returns the HardFault status register.
+pub fn hfsr() -> u32 {
+  get_reg().hfsr.get().raw()
+}
+
+/// Returns the address that caused the last MemManage fault; only valid if
+/// MMARVALID is set in CFSR.
+pub fn mmfar() -> u32 {
+  get_reg().mmfar.address()
+}
+
+/// Returns the address that caused the last BusFault; only valid if
+/// BFARVALID is set in CFSR.
+pub fn bfar() -> u32 {
+  get_reg().bfar.address()
+}
+
+/// Clears all the fault status bits.
+pub fn clear_fault_status() {
+  get_reg().cfsr
+    .set_memmanage(0xff)
+    .set_busfault(0xff)
+    .set_usagefault(0xffff);
+  get_reg().hfsr
+    .set_vecttbl(true)
+    .set_forced(true)
+    .set_debugevt(true);
+}
+
+/// Sets the priority grouping, which splits interrupt priorities into group
+/// priority, used for preemption, and subpriority, used to order pending
+/// interrupts of the same group.
+///
+/// Bits `prigroup:0` of a priority value are the subpriority, the rest is the
+/// group priority; 0 means a 7-bit group priority (up to the number of
+/// implemented bits) and no subpriority.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+pub fn set_priority_grouping(prigroup: u8) {
+  get_reg().aircr
+    .set_vectkey(0x05FA)
+    .set_prigroup((prigroup & 0x7) as u32);
+}
+
+/// Returns the priority grouping.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+pub fn priority_grouping() -> u8 {
+  get_reg().aircr.prigroup() as u8
+}
+
+/// Sets the priority of a system handler, by exception number: 11 is SVCall,
+/// 14 is PendSV and 15 is SysTick.
+pub fn set_system_handler_priority(exception: usize, prio: u8) {
+  get_reg().shpr[(exception - 4) / 4].set_pri(exception % 4, prio as u32);
+}
+
+/// Gives full access to the FPU (coprocessors 10 and 11).
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub fn enable_fpu() {
+  get_reg().cpacr
+    .set_cp(10, 0b11)
+    .set_cp(11, 0b11);
+}
+
+/// Enables stacking of the FPU context on exception entry, reserving space
+/// for S0-S15 and FPSCR only if thread code used the FPU, and saving them
+/// only if the handler does too.
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub fn enable_lazy_fpu_stacking() {
+  get_reg().fpccr
+    .set_aspen(true)
+    .set_lspen(true);
+}
+
+/// Requests a system reset.
+pub fn system_reset() -> ! {
+  get_reg().aircr
+    .set_vectkey(0x05FA)
+    .set_sysresetreq(true);
+  loop {}
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
@@ -101,7 +223,7 @@ mod reg {
       13      => memfaultpended,
       14      => busfaultpended,
       15      => svfaultpended,
-      16      => memfaultpendena,
+      16      => memfaultena,
       17      => busfaultena,
       18      => usgfaultena,
     }
@@ -132,7 +254,11 @@ mod reg {
       0..31   => afsr,
     }
     0x88      => reg32 cpacr { //! Coprocessor access control register
-      0..23   => cp[24],
+      0..23   => cp[12],
+    }
+    0x234     => reg32 fpccr { //! Floating-point context control register
+      30      => lspen,
+      31      => aspen,
     }
   });
 }
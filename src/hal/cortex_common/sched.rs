@@ -0,0 +1,388 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Cortex-M support code for the scheduler.
+//!
+//! Context switches happen in PendSV, which runs at the lowest priority so
+//! that it never preempts another interrupt. The handler saves r4-r11 on the
+//! task stack, on top of the frame stacked by hardware, and lets
+//! `task_scheduler` swap the task stack pointer.
+//!
+//! On Cortex-M4 and M7 the handler also saves EXC_RETURN. If the task used the
+//! FPU, hardware has stacked an extended frame with S0-S15 and FPSCR (lazily,
+//! only if PendSV itself touches the FPU), and the handler saves S16-S31 too.
+//! A task using the FPU needs 136 more bytes of stack for these.
+//!
+//! Cortex-M0 can't store or load r8-r11 directly, so they are moved through
+//! r4-r7; the layout of the saved registers is the same.
+
+use core::intrinsics::volatile_store;
+
+use os::task::Task;
+use super::scb;
+
+/// Exception number of SVCall.
+pub const SVCALL: usize = 11;
+/// Exception number of PendSV.
+pub const PENDSV: usize = 14;
+/// Exception number of SysTick.
+pub const SYSTICK: usize = 15;
+
+/// Size of the registers PendSV saves below the hardware frame, in bytes.
+#[cfg(not(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+pub const SOFTWARE_FRAME_SIZE: u32 = 8*4;
+
+/// Size of the registers PendSV saves below the hardware frame, in bytes.
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub const SOFTWARE_FRAME_SIZE: u32 = 9*4;
+
+/// EXC_RETURN for returning to thread mode on PSP, with a basic frame.
+#[allow(dead_code)]
+const EXC_RETURN_THREAD_PSP: u32 = 0xffff_fffd;
+
+/// Force context switch. Triggers PendSV interrupt.
+#[inline(always)]
+pub fn switch_context() {
+   scb::set_pendsv(true);
+}
+
+/// Sets task stack pointer (PSP).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn set_task_stack_pointer(val: u32) {
+  unsafe { asm!("msr psp, $0" :: "r"(val) :: "volatile") };
+}
+
+/// Sets task stack pointer (mock).
+#[cfg(not(target_arch = "arm"))]
+pub fn set_task_stack_pointer(_: u32) { unimplemented!() }
+
+/// Returns task stack pointer (PSP).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn get_task_stack_pointer() -> u32 {
+  let mut val: u32;
+  unsafe { asm!("mrs $0, psp" : "=r"(val) ::: "volatile") };
+  val
+}
+
+/// Returns task stack pointer (mock).
+#[cfg(not(target_arch = "arm"))]
+pub fn get_task_stack_pointer() -> u32 { unimplemented!() }
+
+/// Returns current stack pointer (SP, which may be PSP or MSP).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn get_current_stack_pointer() -> u32 {
+  let mut val: u32;
+  unsafe { asm!("mov $0, sp" : "=r"(val) ::: "volatile") };
+  val
+}
+
+/// Returns current stack pointer (mock).
+#[cfg(not(target_arch = "arm"))]
+pub fn get_current_stack_pointer() -> u32 { unimplemented!() }
+
+/// Returns true if thread mode is privileged (CONTROL.nPRIV clear).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn thread_privileged() -> bool {
+  let mut val: u32;
+  unsafe { asm!("mrs $0, control" : "=r"(val) ::: "volatile") };
+  val & 1 == 0
+}
+
+/// Returns true if thread mode is privileged (mock).
+#[cfg(not(target_arch = "arm"))]
+pub fn thread_privileged() -> bool { unimplemented!() }
+
+/// Sets the privilege of thread mode. Called from a handler, it takes effect
+/// on return to thread mode.
+///
+/// Cortex-M0 threads are always privileged.
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn set_thread_privileged(privileged: bool) {
+  let mut val: u32;
+  unsafe { asm!("mrs $0, control" : "=r"(val) ::: "volatile") };
+  if privileged {
+    val &= !1;
+  } else {
+    val |= 1;
+  }
+  unsafe { asm!("msr control, $0
+                 isb" :: "r"(val) :: "volatile") };
+}
+
+/// Sets the privilege of thread mode (mock).
+#[cfg(not(target_arch = "arm"))]
+pub fn set_thread_privileged(_: bool) { unimplemented!() }
+
+/// State, that's saved by hardware upon entering an ISR.
+#[repr(C)]
+#[allow(missing_docs)]
+pub struct SavedState {
+  pub r0: u32,
+  pub r1: u32,
+  pub r2: u32,
+  pub r3: u32,
+  pub r12: u32,
+  pub lr: u32,
+  pub pc: u32,
+  pub psr: u32,
+}
+
+impl SavedState {
+  /// Returns the state a task starts from, calling `t` with `arg`.
+  #[inline(always)]
+  pub fn new(t: Task, arg: u32) -> SavedState {
+    SavedState {
+      r0:  arg,
+      r1:  0,
+      r2:  0,
+      r3:  0,
+      r12: 0,
+      lr:  task_finished as u32,
+      pc:  t as u32,
+      psr: 0x01000000,  // thumb state
+    }
+  }
+}
+
+/// Writes the registers PendSV restores for a new task below `stack_top`,
+/// returning the new stack top.
+pub unsafe fn push_software_frame(stack_top: u32) -> u32 {
+  let top = stack_top - SOFTWARE_FRAME_SIZE;
+  for i in 0..8 {
+    volatile_store((top + i * 4) as *mut u32, 0);  // r4-r11
+  }
+  push_exc_return(top);
+  top
+}
+
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+#[inline(always)]
+unsafe fn push_exc_return(top: u32) {
+  volatile_store((top + 8 * 4) as *mut u32, EXC_RETURN_THREAD_PSP);
+}
+
+#[cfg(not(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+#[inline(always)]
+unsafe fn push_exc_return(_: u32) {}
+
+/// Gives PendSV and SysTick the lowest priority, so that context switches
+/// and time slicing never delay other interrupts.
+pub fn set_priorities() {
+  scb::set_system_handler_priority(PENDSV, 0xff);
+  scb::set_system_handler_priority(SYSTICK, 0xff);
+}
+
+/// Enables the FPU, with lazy stacking of its registers.
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub fn enable_fpu() {
+  scb::enable_fpu();
+  scb::enable_lazy_fpu_stacking();
+}
+
+/// No FPU on this core.
+#[cfg(not(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+pub fn enable_fpu() {}
+
+// TODO(farcaller): this should actually kill the task.
+// TODO(bgamari): It should also unlock anything the task holds
+/// Default handler for task that tries to return.
+#[cfg(target_os = "none")]
+unsafe fn task_finished() {
+  ::core::intrinsics::breakpoint();
+}
+
+#[cfg(not(target_os = "none"))]
+unsafe fn task_finished() { unimplemented!() }
+
+/// PendSV and SVCall handlers, and `syscall`.
+///
+/// PendSV saves r4-r11 below the hardware frame on the task stack, calls
+/// `task_scheduler` and restores r4-r11 from the stack of the task picked,
+/// returning to thread mode on PSP. The first switch comes from `setup` on
+/// the main stack, so nothing is saved then. SVCall is handled by
+/// `os::svc::svc_dispatch`.
+#[cfg(all(target_os = "none", feature = "cpu_cortex-m3"))]
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn sched_handlers_wrapper() {
+  asm!(".global isr_pendsv, isr_svcall, syscall
+
+      .thumb_func
+      isr_pendsv:
+      mrs r0, psp
+      tst lr, #4  /* first switch, from the main stack? */
+      beq 1f
+      stmdb r0!, {r4-r11}
+      msr psp, r0
+
+      1:
+      bl task_scheduler
+
+      mrs r0, psp
+      ldmfd r0!, {r4-r11}
+      msr psp, r0
+
+      mvn lr, #2  /* 0xfffffffd, return to thread mode using PSP */
+      bx lr
+      "
+      :::: "volatile");
+  svcall_handler();
+}
+
+/// PendSV and SVCall handlers, and `syscall`.
+///
+/// As on Cortex-M3, with EXC_RETURN saved along r4-r11, and S16-S31 saved
+/// before them if EXC_RETURN shows an extended frame.
+#[cfg(all(target_os = "none",
+          any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn sched_handlers_wrapper() {
+  asm!(".global isr_pendsv, isr_svcall, syscall
+      .fpu fpv4-sp-d16
+
+      .thumb_func
+      isr_pendsv:
+      mrs r0, psp
+      tst lr, #4  /* first switch, from the main stack? */
+      beq 1f
+      tst lr, #0x10  /* extended frame, the task used the FPU */
+      it eq
+      vstmdbeq r0!, {s16-s31}
+      stmdb r0!, {r4-r11, lr}
+      msr psp, r0
+
+      1:
+      bl task_scheduler
+
+      mrs r0, psp
+      ldmfd r0!, {r4-r11, lr}
+      tst lr, #0x10
+      it eq
+      vldmiaeq r0!, {s16-s31}
+      msr psp, r0
+
+      bx lr
+      "
+      :::: "volatile");
+  svcall_handler();
+}
+
+/// PendSV and SVCall handlers, and `syscall`.
+///
+/// As on Cortex-M3, with r8-r11 moved through r4-r7.
+#[cfg(all(target_os = "none", feature = "cpu_cortex-m0"))]
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn sched_handlers_wrapper() {
+  asm!(".global isr_pendsv, isr_svcall, syscall
+
+      .thumb_func
+      isr_pendsv:
+      mov r1, lr
+      movs r2, #4
+      tst r1, r2  /* first switch, from the main stack? */
+      beq 1f
+      mrs r0, psp
+      subs r0, #32
+      msr psp, r0
+      stmia r0!, {r4-r7}
+      mov r4, r8
+      mov r5, r9
+      mov r6, r10
+      mov r7, r11
+      stmia r0!, {r4-r7}
+
+      1:
+      bl task_scheduler
+
+      mrs r0, psp
+      adds r0, #16
+      ldmia r0!, {r4-r7}
+      mov r8, r4
+      mov r9, r5
+      mov r10, r6
+      mov r11, r7
+      msr psp, r0
+      subs r0, #32
+      ldmia r0!, {r4-r7}
+
+      movs r0, #2
+      mvns r0, r0  /* 0xfffffffd, return to thread mode using PSP */
+      bx r0
+      "
+      :::: "volatile");
+  svcall_handler();
+}
+
+/// SVCall handler and `syscall`.
+///
+/// SVCall passes the frame stacked by hardware, on whichever stack the caller
+/// used, and EXC_RETURN to `svc_dispatch`, which finds the call number in the
+/// SVC instruction and may change the frame to return elsewhere.
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+unsafe fn svcall_handler() {
+  asm!("
+      .thumb_func
+      isr_svcall:
+      tst lr, #4
+      ite eq
+      mrseq r0, msp
+      mrsne r0, psp
+      mov r1, lr
+      push {lr}
+
+      bl svc_dispatch
+
+      pop {pc}
+
+      .thumb_func
+      syscall:
+      svc 0
+      bx lr" :::: "volatile");
+}
+
+/// SVCall handler and `syscall`, without IT blocks.
+#[cfg(all(target_os = "none", feature = "cpu_cortex-m0"))]
+#[inline(always)]
+unsafe fn svcall_handler() {
+  asm!("
+      .thumb_func
+      isr_svcall:
+      mov r1, lr
+      movs r2, #4
+      tst r1, r2
+      beq 2f
+      mrs r0, psp
+      b 3f
+      2:
+      mrs r0, msp
+      3:
+      push {lr}
+
+      bl svc_dispatch
+
+      pop {pc}
+
+      .thumb_func
+      syscall:
+      svc 0
+      bx lr" :::: "volatile");
+}
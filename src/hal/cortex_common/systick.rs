@@ -69,6 +69,22 @@ pub fn disable_irq() {
   get_reg().csr.set_tickint(false);
 }
 
+/// Sets the 24bit reload value, loaded when the counter wraps or is cleared.
+pub fn set_reload(reload: u32) {
+  get_reg().rvr.set_reload(reload);
+}
+
+/// Clears the current value, so that the counter loads the reload value on
+/// the next clock.
+pub fn clear_current() {
+  get_reg().cvr.set_current(0);
+}
+
+/// Gets the 24bit reload value.
+pub fn get_reload() -> u32 {
+  get_reg().rvr.reload()
+}
+
 /// Gets the current 24bit systick value.
 pub fn get_current() -> u32 {
   get_reg().cvr.current()
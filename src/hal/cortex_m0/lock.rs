@@ -13,31 +13,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::ty::Unsafe;
+//! Lock taken with interrupts masked.
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
 use core::option::Option::{self, Some, None};
 use core::ops::Drop;
-use core::kinds::Share;
+
+use hal::cortex_m0::irq::NoInterrupts;
 
 /// A lock. Note that this disables interrupts. Consequently, a task
 /// dying (e.g. by running out of stack space) while holding a lock
 /// may cause a deadlock.
 pub struct Lock {
-  locked: Unsafe<bool>
+  locked: UnsafeCell<bool>
 }
 
+/// Held lock, released on drop.
 #[must_use]
 pub struct Guard<'a>(&'a Lock);
 
-pub static STATIC_LOCK: Lock = Lock { locked: Unsafe { value: false, marker1: InvariantType } };
+/// A lock for static initialization.
+pub static STATIC_LOCK: Lock = Lock { locked: UnsafeCell::new(false) };
 
 impl Lock {
-  pub fn new() -> Lock {
-    Lock { locked: Unsafe::new(false) }
+  /// Creates an unlocked lock.
+  pub const fn new() -> Lock {
+    Lock { locked: UnsafeCell::new(false) }
   }
 
+  /// Takes the lock, returning None if it's already held.
   pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
     unsafe {
-      let crit = NoInterrupts::new();
+      let _crit = NoInterrupts::new();
       let locked = self.locked.get();
       match *locked {
         true  => return None,
@@ -51,18 +59,17 @@ impl Lock {
 
   fn unlock<'a>(&'a self) {
     unsafe {
-      let crit = NoInterrupts::new();
+      let _crit = NoInterrupts::new();
       *self.locked.get() = false;
     }
   }
 }
 
-#[unsafe_destructor]
 impl<'a> Drop for Guard<'a> {
   fn drop(&mut self) {
-    let &Guard(ref lock) = self;
+    let &mut Guard(ref lock) = self;
     lock.unlock();
   }
 }
 
-impl Share for Lock { }
+unsafe impl Sync for Lock { }
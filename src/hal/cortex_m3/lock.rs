@@ -13,21 +13,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::ty::Unsafe;
+//! Lock built on exclusive loads and stores.
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
 use core::option::Option::{self, Some, None};
 use core::ops::Drop;
-use core::kinds::Share;
-use core::kinds::marker::InvariantType;
 
 /// A lock
 pub struct Lock {
-    locked: Unsafe<u32>
+    locked: UnsafeCell<u32>
 }
 
+/// Held lock, released on drop.
 #[must_use]
 pub struct Guard<'a>(&'a Lock);
 
-pub static STATIC_LOCK: Lock = Lock { locked: Unsafe { value: 0, marker1: InvariantType } };
+/// A lock for static initialization.
+pub static STATIC_LOCK: Lock = Lock { locked: UnsafeCell::new(0) };
 
 #[cfg(target_arch = "arm")]
 #[inline(always)]
@@ -43,7 +46,7 @@ unsafe fn exclusive_load(addr: *const u32) -> u32 {
 }
 
 #[cfg(not(target_arch = "arm"))]
-unsafe fn exclusive_load(addr: *const u32) -> u32 { unimplemented!() }
+unsafe fn exclusive_load(addr: *const u32) -> u32 { *addr }
 
 #[cfg(target_arch = "arm")]
 #[inline(always)]
@@ -60,14 +63,17 @@ unsafe fn exclusive_store(addr: *mut u32, value: u32) -> bool {
 
 #[cfg(not(target_arch = "arm"))]
 unsafe fn exclusive_store(addr: *mut u32, value: u32) -> bool {
-  unimplemented!()
+  *addr = value;
+  true
 }
 
 impl Lock {
-  pub fn new() -> Lock {
-    Lock { locked: Unsafe::new(0) }
+  /// Creates an unlocked lock.
+  pub const fn new() -> Lock {
+    Lock { locked: UnsafeCell::new(0) }
   }
 
+  /// Takes the lock, returning None if it's already held.
   pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
     unsafe {
       let ptr: *mut u32 = self.locked.get();
@@ -95,12 +101,11 @@ impl Lock {
   }
 }
 
-#[unsafe_destructor]
 impl<'a> Drop for Guard<'a> {
   fn drop(&mut self) {
-    let &Guard(ref lock) = self;
+    let &mut Guard(ref lock) = self;
     lock.unlock();
   }
 }
 
-impl Share for Lock { }
+unsafe impl Sync for Lock { }
@@ -25,5 +25,8 @@ pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::mpu;
 pub use super::cortex_common::irq;
-#[cfg(feature = "multitasking")] pub mod sched;
+pub use super::cortex_common::fault;
+pub use super::cortex_common::dwt;
+#[cfg(feature = "multitasking")]
+pub use super::cortex_common::sched;
 #[cfg(feature = "multitasking")] pub mod lock;
@@ -25,3 +25,7 @@ pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::mpu;
 pub use super::cortex_common::irq;
+pub use super::cortex_common::fault;
+pub use super::cortex_common::dwt;
+#[cfg(feature = "multitasking")]
+pub use super::cortex_common::sched;
@@ -0,0 +1,181 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Crash record kept across resets.
+
+The record lives in the `.noinit` RAM section, which isn't cleared on boot, so
+the fault report saved by the fault handler is still there after the reset that
+follows it, as long as power stays on. A magic number and a checksum tell a
+valid record from the random RAM contents after power on.
+
+`init` should be called early on boot. It returns the record of the previous
+boot if that one ended with a fault, so it can be printed with
+`CrashRecord::print` or sent elsewhere, and starts a new record.
+*/
+
+use core::intrinsics::volatile_load;
+use core::mem::size_of;
+use core::option::Option::{self, Some, None};
+use core::slice;
+
+use drivers::chario::CharIO;
+use hal::cortex_common::fault::{self, FaultReport};
+use hal::power::ResetReason;
+use hal::stack::stack_base;
+
+const MAGIC: u32 = 0xC4A5_11ED;
+
+/// Number of stack words saved above the exception frame.
+pub const STACK_WORDS: usize = 16;
+
+/// State of one boot.
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+  /// Boots since the record was created, usually at power on.
+  pub boot_count: u32,
+  /// Cause of the reset that started the boot.
+  pub reset_reason: ResetReason,
+  /// Fault that ended the boot.
+  pub fault: Option<FaultReport>,
+  /// Stack contents above the exception frame, zero past the stack base.
+  pub stack: [u32; STACK_WORDS],
+}
+
+#[repr(C)]
+struct Stored {
+  magic: u32,
+  record: CrashRecord,
+  checksum: u32,
+}
+
+#[link_section = ".noinit"]
+static mut Record: Stored = Stored {
+  magic: 0,
+  record: CrashRecord {
+    boot_count: 0,
+    reset_reason: ResetReason::Unknown,
+    fault: None,
+    stack: [0; STACK_WORDS],
+  },
+  checksum: 0,
+};
+
+/// Checksums the record as raw words, so that garbage can be checked before
+/// it's interpreted. The size is mixed in to catch layout changes across
+/// firmware updates.
+fn checksum(stored: &Stored) -> u32 {
+  let words = unsafe {
+    slice::from_raw_parts(&stored.record as *const CrashRecord as *const u32,
+        size_of::<CrashRecord>() / 4)
+  };
+  let mut sum = MAGIC ^ size_of::<CrashRecord>() as u32;
+  for &w in words.iter() {
+    sum = sum.rotate_left(5) ^ w;
+  }
+  sum
+}
+
+/// Starts the record of a new boot and installs the fault hook that fills
+/// it.
+///
+/// Returns the record of the previous boot if it ended with a fault; its
+/// `reset_reason` is the cause of the reset that followed the fault.
+pub fn init(reason: ResetReason) -> Option<CrashRecord> {
+  let previous = unsafe {
+    let valid = Record.magic == MAGIC && Record.checksum == checksum(&Record);
+    if !valid {
+      Record.magic = MAGIC;
+      Record.record.boot_count = 0;
+      Record.record.fault = None;
+    }
+    Record.record.reset_reason = reason;
+
+    let previous = match Record.record.fault {
+      Some(_) => Some(Record.record),
+      None => None,
+    };
+
+    Record.record.boot_count = Record.record.boot_count.wrapping_add(1);
+    Record.record.fault = None;
+    Record.record.stack = [0; STACK_WORDS];
+    Record.checksum = checksum(&Record);
+    previous
+  };
+
+  fault::set_hook(Some(save as fn(&FaultReport)));
+  previous
+}
+
+/// Returns the number of boots since the record was created.
+pub fn boot_count() -> u32 {
+  unsafe { Record.record.boot_count }
+}
+
+/// Stores a fault report and a stack snapshot in the record.
+///
+/// Called from the fault handler once `init` has run.
+pub fn save(report: &FaultReport) {
+  unsafe {
+    Record.record.fault = Some(*report);
+    let base = stack_base() as u32;
+    for i in 0..STACK_WORDS {
+      let addr = report.sp + i as u32 * 4;
+      Record.record.stack[i] = if addr >= report.sp && addr + 4 <= base {
+        volatile_load(addr as *const u32)
+      } else {
+        0
+      };
+    }
+    Record.checksum = checksum(&Record);
+  }
+}
+
+fn reason_name(reason: ResetReason) -> &'static str {
+  match reason {
+    ResetReason::PowerOn => "power on",
+    ResetReason::External => "reset pin",
+    ResetReason::Watchdog => "watchdog",
+    ResetReason::BrownOut => "brown-out",
+    ResetReason::Software => "software",
+    ResetReason::LowPower => "low power",
+    ResetReason::Unknown => "unknown",
+  }
+}
+
+impl CrashRecord {
+  /// Prints a human-readable report.
+  pub fn print(&self, out: &CharIO) {
+    out.puts("boot ");
+    out.puti(self.boot_count);
+    out.puts(", reset: ");
+    out.puts(reason_name(self.reset_reason));
+    out.puts("\n");
+
+    match self.fault {
+      Some(ref report) => {
+        report.print(out);
+        out.puts("stack:");
+        for (i, &word) in self.stack.iter().enumerate() {
+          out.puts(if i % 4 == 0 { "\n  " } else { " " });
+          out.puts("0x");
+          out.putint(word, 16);
+        }
+        out.puts("\n");
+      },
+      None => (),
+    }
+  }
+}
@@ -0,0 +1,126 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+On-chip flash programming interface.
+
+Flash is a MCU-specific struct implementing the `Flash` trait.
+
+Flash memory can only be programmed from the erased state (all ones), and can
+only be erased a whole sector (or page) at a time. Every MCU layout reserves a
+storage region at the end of the flash (`__STORAGE_START` to `__STORAGE_END`)
+that is never used for code, `storage_start()` and `storage_end()` return its
+bounds.
+
+Programming stalls the CPU if code is executed from the same flash bank, and
+some implementations have to disable interrupts for the duration of the
+operation.
+
+Controllers that are polled for completion also implement `TryFlash`, which
+gives up waiting at a deadline.
+*/
+
+use core::intrinsics::transmute;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::time::Deadline;
+
+extern {
+  fn __STORAGE_START();
+  fn __STORAGE_END();
+}
+
+/// Flash operation errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// Address is outside of flash or not aligned as required.
+  InvalidAddress,
+  /// Target sector is write-protected.
+  Protected,
+  /// The controller reported a programming or erase failure.
+  Failed,
+  /// Data read back doesn't match what was programmed.
+  VerifyFailed,
+  /// The operation didn't complete before the deadline, it may still be
+  /// running.
+  Timeout,
+}
+
+/// Flash trait.
+pub trait Flash {
+  /// Returns the size of erase unit (sector or page) containing `addr`.
+  fn sector_size(&self, addr: u32) -> u32;
+
+  /// Erases the sector (or page) containing `addr`.
+  fn erase_sector(&self, addr: u32) -> Result<(), Error>;
+
+  /// Programs consecutive words starting at `addr`, which must be word-aligned
+  /// and erased.
+  fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error>;
+
+  /// Returns true if flash contents can't be read externally (via debug port
+  /// or ISP).
+  fn is_read_protected(&self) -> bool;
+
+  /// Reads a word from flash.
+  fn read(&self, addr: u32) -> u32 {
+    unsafe { *(addr as *const u32) }
+  }
+
+  /// Compares flash contents at `addr` with `data`.
+  fn verify(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    for i in 0..data.len() {
+      if self.read(addr + (i as u32) * 4) != data[i] {
+        return Err(Error::VerifyFailed);
+      }
+    }
+    Ok(())
+  }
+
+  /// Programs and verifies consecutive words starting at `addr`.
+  fn program_verified(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    match self.program(addr, data) {
+      Ok(()) => self.verify(addr, data),
+      err => err,
+    }
+  }
+}
+
+/// Flash operations with a deadline.
+pub trait TryFlash {
+  /// Erases the sector (or page) containing `addr`.
+  fn try_erase_sector(&self, addr: u32, deadline: &Deadline)
+      -> Result<(), Error>;
+
+  /// Programs consecutive words starting at `addr`, which must be word-aligned
+  /// and erased. The deadline covers all of them.
+  fn try_program(&self, addr: u32, data: &[u32], deadline: &Deadline)
+      -> Result<(), Error>;
+}
+
+/// Returns the first address of the reserved storage region.
+pub fn storage_start() -> u32 {
+  unsafe {
+    transmute::<_, usize>(__STORAGE_START as unsafe extern "C" fn()) as u32
+  }
+}
+
+/// Returns the address just past the reserved storage region.
+pub fn storage_end() -> u32 {
+  unsafe {
+    transmute::<_, usize>(__STORAGE_END as unsafe extern "C" fn()) as u32
+  }
+}
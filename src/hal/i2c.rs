@@ -0,0 +1,46 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+I2C interface.
+
+I2C objects are created by MCU-specific or software (`hal::bitbang::i2c`)
+implementations. Only master mode with 7-bit addresses is covered.
+*/
+
+use core::result::Result;
+
+/// I2C transfer errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// The device didn't acknowledge its address or a data byte.
+  Nack,
+  /// SCL was held low by a device for too long.
+  Timeout,
+}
+
+/// I2C master.
+pub trait I2C {
+  /// Writes `data` to the device at `address`.
+  fn write(&self, address: u8, data: &[u8]) -> Result<(), Error>;
+
+  /// Fills `buffer` with data read from the device at `address`.
+  fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error>;
+
+  /// Writes `data` and then reads into `buffer` with a repeated start, e.g. to
+  /// read a register of the device.
+  fn write_read(&self, address: u8, data: &[u8], buffer: &mut [u8])
+      -> Result<(), Error>;
+}
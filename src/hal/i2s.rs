@@ -0,0 +1,280 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+I2S interface.
+
+I2SConf is a MCU-specific struct.
+
+I2S peripherals are always configured as bus masters here, generating both the
+bit clock and the word select signal for the attached codec. Samples are passed
+around as `u32` regardless of the configured word width; only the lower
+`width` bits are transmitted.
+
+`DoubleBuffer` allows to stream PCM data without gaps: the interrupt handler
+drains one buffer into the peripheral while the application fills the other.
+
+`TryI2S` provides the blocking transfers bounded by a deadline, for when the
+codec may stop the clock.
+*/
+
+use core::cell::{Cell, UnsafeCell};
+use core::marker::Sync;
+use core::result::Result;
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+use util::shared::dummy_irq::NoInterrupts;
+use hal::time::{Deadline, Timeout};
+
+/// Transfer direction.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+  /// Master transmitter, i.e. playback.
+  Transmit,
+  /// Master receiver, i.e. recording.
+  Receive,
+}
+
+/// Number of bits in one sample.
+///
+/// Not every MCU supports every width, setup aborts on unsupported values.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum WordWidth {
+  Bits8,
+  Bits16,
+  Bits24,
+  Bits32,
+}
+
+impl WordWidth {
+  /// Returns the number of bits in one sample.
+  pub fn bits(self) -> u32 {
+    match self {
+      WordWidth::Bits8  => 8,
+      WordWidth::Bits16 => 16,
+      WordWidth::Bits24 => 24,
+      WordWidth::Bits32 => 32,
+    }
+  }
+}
+
+/// Channel layout of the stream.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channels {
+  /// The same sample is sent in both left and right slots.
+  Mono,
+  /// Samples alternate between left and right slots.
+  Stereo,
+}
+
+/// Returns the bit clock frequency required for the given audio format.
+///
+/// Word select always has two slots per frame, even in mono mode.
+pub fn bit_clock(sample_rate: u32, width: WordWidth) -> u32 {
+  sample_rate * width.bits() * 2
+}
+
+/// I2S trait.
+pub trait I2S {
+  /// Returns true if the transmit FIFO can accept another sample.
+  fn can_write(&self) -> bool;
+
+  /// Returns true if there is a received sample waiting in the FIFO.
+  fn can_read(&self) -> bool;
+
+  /// Writes one sample, blocks until there is space in the FIFO. Part of it
+  /// may be queued, as with `TryI2S::try_write`.
+  fn write(&self, sample: u32);
+
+  /// Reads one sample, blocks until one is available.
+  fn read(&self) -> u32;
+
+  /// Writes all given samples.
+  fn write_samples(&self, samples: &[u32]) {
+    for &s in samples.iter() {
+      self.write(s);
+    }
+  }
+
+  /// Fills the given buffer with received samples.
+  fn read_samples(&self, samples: &mut [u32]) {
+    for s in samples.iter_mut() {
+      *s = self.read();
+    }
+  }
+}
+
+/// I2S transfers with a deadline.
+pub trait TryI2S {
+  /// Writes one sample, waiting for space in the FIFO.
+  ///
+  /// A driver whose FIFO can't take a whole sample at once may queue part of
+  /// it, to be sent as the FIFO drains.
+  fn try_write(&self, sample: u32, deadline: &Deadline) -> Result<(), Timeout>;
+
+  /// Reads one sample, waiting for one to arrive.
+  fn try_read(&self, deadline: &Deadline) -> Result<u32, Timeout>;
+}
+
+/// Two sample buffers shared between the application and the I2S interrupt.
+///
+/// The ISR calls `service()` every time the peripheral requests data. While
+/// one buffer is being played, the application fills the other one with
+/// `fill()`. Playback stalls (the FIFO underruns) only if the application
+/// doesn't keep up.
+pub struct DoubleBuffer<'a> {
+  buffers: [UnsafeCell<&'a mut [u32]>; 2],
+  full: [Cell<bool>; 2],
+  playing: Cell<usize>,
+  position: Cell<usize>,
+}
+
+impl<'a> DoubleBuffer<'a> {
+  /// Creates a new stream over two equally sized buffers.
+  pub fn new(first: &'a mut [u32], second: &'a mut [u32]) -> DoubleBuffer<'a> {
+    DoubleBuffer {
+      buffers: [UnsafeCell::new(first), UnsafeCell::new(second)],
+      full: [Cell::new(false), Cell::new(false)],
+      playing: Cell::new(0),
+      position: Cell::new(0),
+    }
+  }
+
+  /// Returns true if there is a buffer waiting to be filled.
+  pub fn wants_data(&self) -> bool {
+    !self.full[0].get() || !self.full[1].get()
+  }
+
+  /// Fills the next free buffer using the provided closure.
+  ///
+  /// Returns false if both buffers are still queued for playback, in which
+  /// case the closure is not called.
+  pub fn fill<F>(&self, f: F) -> bool where F: FnOnce(&mut [u32]) {
+    let idx = {
+      let _crit = NoInterrupts::new();
+      let playing = self.playing.get();
+      if !self.full[playing].get() {
+        playing
+      } else if !self.full[1 - playing].get() {
+        1 - playing
+      } else {
+        return false;
+      }
+    };
+    // The interrupt doesn't touch a buffer until it's marked full.
+    unsafe { f(&mut **self.buffers[idx].get()); }
+    let _crit = NoInterrupts::new();
+    self.full[idx].set(true);
+    true
+  }
+
+  /// Moves as many queued samples as possible into the peripheral.
+  ///
+  /// Should be called from the I2S interrupt handler.
+  pub fn service<T: I2S>(&self, i2s: &T) {
+    while i2s.can_write() {
+      let idx = self.playing.get();
+      if !self.full[idx].get() {
+        return;
+      }
+
+      let buf: &[u32] = unsafe { &**self.buffers[idx].get() };
+      let pos = self.position.get();
+      i2s.write(buf[pos]);
+
+      if pos + 1 == buf.len() {
+        let _crit = NoInterrupts::new();
+        self.position.set(0);
+        self.full[idx].set(false);
+        self.playing.set(1 - idx);
+      } else {
+        self.position.set(pos + 1);
+      }
+    }
+  }
+}
+
+// Buffer ownership passes through the `full` flags, which are only changed,
+// and the playing buffer only swapped, in critical sections.
+unsafe impl<'a> Sync for DoubleBuffer<'a> {}
+
+#[cfg(test)]
+mod test {
+  use core::cell::{Cell, RefCell};
+  use std::vec::Vec;
+
+  use super::{I2S, DoubleBuffer, WordWidth, bit_clock};
+
+  /// Transmitter with room for `space` samples.
+  struct MockI2S {
+    space: Cell<usize>,
+    written: RefCell<Vec<u32>>,
+  }
+
+  impl MockI2S {
+    fn new(space: usize) -> MockI2S {
+      MockI2S { space: Cell::new(space), written: RefCell::new(Vec::new()) }
+    }
+  }
+
+  impl I2S for MockI2S {
+    fn can_write(&self) -> bool { self.space.get() > 0 }
+    fn can_read(&self) -> bool { false }
+    fn write(&self, sample: u32) {
+      self.space.set(self.space.get() - 1);
+      self.written.borrow_mut().push(sample);
+    }
+    fn read(&self) -> u32 { 0 }
+  }
+
+  #[test]
+  fn computes_bit_clock() {
+    assert!(bit_clock(44_100, WordWidth::Bits16) == 1_411_200);
+    assert!(bit_clock(48_000, WordWidth::Bits32) == 3_072_000);
+  }
+
+  #[test]
+  fn plays_buffers_in_turn() {
+    let mut a = [0u32; 2];
+    let mut b = [0u32; 2];
+    let db = DoubleBuffer::new(&mut a, &mut b);
+    assert!(db.fill(|buf| { buf[0] = 1; buf[1] = 2; }));
+    assert!(db.fill(|buf| { buf[0] = 3; buf[1] = 4; }));
+    assert!(!db.wants_data());
+    assert!(!db.fill(|_| panic!()));
+
+    let i2s = MockI2S::new(3);
+    db.service(&i2s);
+    assert!(&i2s.written.borrow()[..] == &[1, 2, 3]);
+    assert!(db.wants_data());
+
+    assert!(db.fill(|buf| { buf[0] = 5; buf[1] = 6; }));
+    i2s.space.set(10);
+    db.service(&i2s);
+    assert!(&i2s.written.borrow()[..] == &[1, 2, 3, 4, 5, 6]);
+  }
+}
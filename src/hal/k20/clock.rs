@@ -0,0 +1,196 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Clock tree of Kinetis K20.
+
+The frequency of MCGOUTCLK is decoded from the current MCG mode. External
+reference frequency can't be read from the hardware and has to be set with
+`set_external_clock` if the MCG runs off the crystal (FEE, FBE, PBE and PEE
+modes).
+
+The core and the system clock run at MCGOUTCLK / OUTDIV1, the bus clock at
+MCGOUTCLK / OUTDIV2. UART0 and UART1 are clocked from the system clock, all
+other peripherals from the bus clock.
+*/
+
+use hal::clock::{ClockTree, PeripheralFrequency};
+use hal::k20::sim::reg::SIM;
+
+/// Slow internal reference clock.
+const SLOW_IRC: u32 = 32_768;
+/// Fast internal reference clock.
+const FAST_IRC: u32 = 4_000_000;
+
+// Teensy 3.x boards use a 16MHz crystal.
+static mut ExternalClock: u32 = 16_000_000;
+
+/// Sets the frequency of the external reference clock (OSCCLK).
+pub fn set_external_clock(freq: u32) {
+  unsafe { ExternalClock = freq };
+}
+
+/// Returns the frequency of the external reference clock.
+pub fn external_clock() -> u32 {
+  unsafe { ExternalClock }
+}
+
+/// Returns the frequency of MCGOUTCLK.
+pub fn mcg_out_clock() -> u32 {
+  let mcg = &reg::MCG;
+  match mcg.s.clkst() {
+    0 => fll_clock(),
+    1 => if mcg.c2.ircs() { FAST_IRC } else { SLOW_IRC },
+    2 => external_clock(),
+    _ => {
+      let prdiv = mcg.c5.prdiv0() as u32 + 1;
+      let vdiv = mcg.c6.vdiv0() as u32 + 24;
+      external_clock() / prdiv * vdiv
+    },
+  }
+}
+
+fn fll_clock() -> u32 {
+  let mcg = &reg::MCG;
+  let reference = if mcg.s.irefst() {
+    SLOW_IRC
+  } else {
+    let frdiv = mcg.c1.frdiv() as u32;
+    let divisor = match (mcg.c2.range0(), frdiv) {
+      (0, _) => 1 << frdiv,
+      (_, 6) => 1280,
+      (_, 7) => 1536,
+      (_, _) => 32 << frdiv,
+    };
+    external_clock() / divisor
+  };
+  let factor = match (mcg.c4.dmx32(), mcg.c4.drst_drs()) {
+    (false, n) => 640 * (n as u32 + 1),
+    (true, 0)  => 732,
+    (true, 1)  => 1464,
+    (true, 2)  => 2197,
+    (true, _)  => 2929,
+  };
+  reference * factor
+}
+
+/// Clock tree of K20.
+///
+/// The system clock is reported as AHB and the bus clock as both APB1 and
+/// APB2.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 {
+    mcg_out_clock() / (SIM.clkdiv1.outdiv1() + 1)
+  }
+
+  fn ahb_frequency(&self) -> u32 {
+    self.core_frequency()
+  }
+
+  fn apb1_frequency(&self) -> u32 {
+    mcg_out_clock() / (SIM.clkdiv1.outdiv2() + 1)
+  }
+
+  fn apb2_frequency(&self) -> u32 {
+    self.apb1_frequency()
+  }
+}
+
+/// Peripheral clock domains.
+#[derive(Clone, Copy)]
+pub enum PeripheralClock {
+  /// System clock, feeds UART0 and UART1.
+  SystemClock,
+  /// Bus clock, feeds all other peripherals.
+  BusClock,
+}
+
+impl PeripheralFrequency for PeripheralClock {
+  fn frequency(&self) -> u32 {
+    match *self {
+      PeripheralClock::SystemClock => Clocks.core_frequency(),
+      PeripheralClock::BusClock => Clocks.apb1_frequency(),
+    }
+  }
+}
+
+/// Registers
+#[allow(dead_code)]
+pub mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(MCG = {
+    0x0 => reg8 c1 {
+      0    => irefsten,
+      1    => irclken,
+      2    => irefs,
+      3..5 => frdiv,
+      6..7 => clks,
+    },
+
+    0x1 => reg8 c2 {
+      0    => ircs,
+      1    => lp,
+      2    => erefs0,
+      3    => hgo0,
+      4..5 => range0,
+    },
+
+    0x3 => reg8 c4 {
+      0    => scftrim,
+      1..4 => fctrim,
+      5..6 => drst_drs,
+      7    => dmx32,
+    },
+
+    0x4 => reg8 c5 {
+      0..4 => prdiv0,
+      5    => pllsten0,
+      6    => pllclken0,
+    },
+
+    0x5 => reg8 c6 {
+      0..4 => vdiv0,
+      5    => cme0,
+      6    => plls,
+      7    => lolie0,
+    },
+
+    0x6 => reg8 s {
+      0    => ircst: ro,
+      1    => oscinit0: ro,
+      2..3 => clkst: ro,
+      4    => irefst: ro,
+      5    => pllst: ro,
+      6    => lock0: ro,
+      7    => lols0: ro,
+    },
+  });
+
+  extern {
+    #[link_name="k20_iomem_MCG"] pub static MCG: MCG;
+  }
+}
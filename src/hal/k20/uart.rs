@@ -18,8 +18,13 @@ UART configuration.
 */
 
 use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
 
 use drivers::chario::CharIO;
+use hal::clock::{ClockListener, DRAIN_SPINS, PeripheralFrequency};
+use hal::k20::clock::PeripheralClock;
+use hal::time::{Deadline, SpinDeadline, Timeout};
 use hal::uart;
 
 use self::UARTPeripheral::*;
@@ -40,6 +45,8 @@ pub enum UARTPeripheral {
 #[derive(Clone, Copy)]
 pub struct UART {
   reg: &'static reg::UART,
+  clock: PeripheralClock,
+  baudrate: u32,
 }
 
 /// Stop bits configuration.
@@ -68,6 +75,13 @@ impl UARTPeripheral {
       UART2 => &reg::UART2,
     }
   }
+
+  fn clock(self) -> PeripheralClock {
+    match self {
+      UART0|UART1 => PeripheralClock::SystemClock,
+      UART2 => PeripheralClock::BusClock,
+    }
+  }
 }
 
 impl UART {
@@ -75,7 +89,9 @@ impl UART {
   pub fn new(peripheral: UARTPeripheral, baudrate:  u32, word_len: u8,
       parity: uart::Parity, stop_bits: u8) -> UART {
     let uart = UART {
-      reg: peripheral.reg()
+      reg: peripheral.reg(),
+      clock: peripheral.clock(),
+      baudrate: baudrate,
     };
     uart.set_baud_rate(baudrate);
     uart.set_mode(reg::UART_c1_m::from_u8(word_len), parity, StopBit::from_u8(stop_bits));
@@ -85,7 +101,7 @@ impl UART {
   }
 
   fn uart_clock(&self) -> u32 {
-    48000000 // FIXME(bgamari): Use peripheral clocks
+    self.clock.frequency()
   }
 
   fn set_baud_rate(&self, baud_rate: u32) {
@@ -115,6 +131,19 @@ impl UART {
   }
 }
 
+impl ClockListener for UART {
+  /// Lets the transmitter drain, so no character goes out at a wrong rate.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(self.reg.s1.tc(), deadline);
+  }
+
+  /// Recomputes divisors for the configured baud rate.
+  fn clock_changed(&self) {
+    self.set_baud_rate(self.baudrate);
+  }
+}
+
 impl CharIO for UART {
   fn putc(&self, value: char) {
     wait_for!(self.reg.s1.tdre());
@@ -122,6 +151,14 @@ impl CharIO for UART {
   }
 }
 
+impl uart::TryCharIO for UART {
+  fn try_putc(&self, value: char, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.reg.s1.tdre(), deadline));
+    self.reg.d.set_re(value as u8);
+    Ok(())
+  }
+}
+
 /// Register definitions
 pub mod reg {
   use volatile_cell::VolatileCell;
@@ -0,0 +1,157 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Flash programming via In-Application Programming (IAP) ROM calls.
+
+IAP uses the top 32 bytes of the local SRAM, which must not hold any live data
+while a flash operation is running; layout.ld checks that the RAM region stays
+below them. Interrupts are disabled for the duration of each call, as the flash
+is not accessible while it's being programmed.
+*/
+
+use core::intrinsics::transmute;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::cortex_m3::irq::NoInterrupts;
+use hal::flash;
+use hal::flash::Error;
+use hal::lpc17xx::system_clock::system_clock;
+
+/// IAP entry point, in thumb mode.
+const IAP_LOCATION: u32 = 0x1FFF_1FF1;
+
+/// Smallest block the IAP "copy RAM to flash" command accepts.
+const BLOCK_SIZE: u32 = 256;
+
+/// End of the on-chip flash for the largest part of the family.
+const FLASH_END: u32 = 0x0008_0000;
+
+/// Location of the Code Read Protection word.
+const CRP_ADDRESS: u32 = 0x0000_02FC;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+enum Command {
+  PrepareSectors = 50,
+  CopyRamToFlash = 51,
+  EraseSectors = 52,
+  BlankCheck = 53,
+  Compare = 56,
+}
+
+/// Flash controller.
+#[derive(Clone, Copy)]
+pub struct Flash;
+
+impl Flash {
+  /// Returns the flash controller.
+  pub fn new() -> Flash {
+    Flash
+  }
+}
+
+/// Returns the sector number that contains `addr`.
+///
+/// Sectors 0-15 are 4kB each, sectors 16-29 are 32kB each.
+fn sector_number(addr: u32) -> u32 {
+  if addr < 0x1_0000 {
+    addr / 0x1000
+  } else {
+    16 + (addr - 0x1_0000) / 0x8000
+  }
+}
+
+fn iap(command: Command, params: &[u32]) -> Result<u32, Error> {
+  let mut cmd: [u32; 5] = [command as u32, 0, 0, 0, 0];
+  let mut result: [u32; 5] = [0; 5];
+  for i in 0..params.len() {
+    cmd[i + 1] = params[i];
+  }
+
+  let entry: extern "C" fn(*const u32, *mut u32) =
+      unsafe { transmute(IAP_LOCATION as usize) };
+
+  {
+    let _crit = NoInterrupts::new();
+    entry(&cmd[0], &mut result[0]);
+  }
+
+  match result[0] {
+    0 => Ok(result[1]),                    // CMD_SUCCESS
+    2|3|7|10|11|13 => Err(Error::InvalidAddress),
+    8 => Err(Error::VerifyFailed),         // COMPARE_ERROR
+    9 => Err(Error::Protected),            // SECTOR_NOT_PREPARED_FOR_WRITE
+    _ => Err(Error::Failed),
+  }
+}
+
+fn prepare(sector: u32) -> Result<u32, Error> {
+  iap(Command::PrepareSectors, &[sector, sector])
+}
+
+impl flash::Flash for Flash {
+  fn sector_size(&self, addr: u32) -> u32 {
+    if addr < 0x1_0000 { 0x1000 } else { 0x8000 }
+  }
+
+  fn erase_sector(&self, addr: u32) -> Result<(), Error> {
+    if addr >= FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+    let sector = sector_number(addr);
+    let cclk_khz = system_clock() / 1000;
+
+    try!(prepare(sector));
+    try!(iap(Command::EraseSectors, &[sector, sector, cclk_khz]));
+    Ok(())
+  }
+
+  fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    let end = addr + data.len() as u32 * 4;
+    if addr & 3 != 0 || end > FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+    let cclk_khz = system_clock() / 1000;
+
+    // IAP programs whole 256-byte blocks; words we don't touch are written as
+    // ones, which leaves them unchanged.
+    let mut block_start = addr & !(BLOCK_SIZE - 1);
+    while block_start < end {
+      let mut buf: [u32; 64] = [0xffff_ffff; 64];
+      for i in 0..64 {
+        let word_addr = block_start + i as u32 * 4;
+        if word_addr >= addr && word_addr < end {
+          buf[i] = data[((word_addr - addr) / 4) as usize];
+        }
+      }
+
+      try!(prepare(sector_number(block_start)));
+      try!(iap(Command::CopyRamToFlash,
+          &[block_start, &buf[0] as *const u32 as u32, BLOCK_SIZE, cclk_khz]));
+
+      block_start += BLOCK_SIZE;
+    }
+    Ok(())
+  }
+
+  fn is_read_protected(&self) -> bool {
+    match self.read(CRP_ADDRESS) {
+      0x1234_5678 | 0x8765_4321 | 0x4321_8765 => true,
+      _ => false,
+    }
+  }
+}
@@ -0,0 +1,300 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+I2S configuration.
+
+The bit clock is derived from the I2S peripheral clock through the fractional
+rate divider, so the achievable sample rate depends on the configured system
+clock. Pins are not configured here, use `Pin::new` with the I2S function for
+TX_SDA, TX_SCK and TX_WS (or their RX counterparts).
+*/
+
+use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::Ok;
+
+use hal::i2s;
+use hal::i2s::{Direction, WordWidth, Channels};
+use hal::time::{Deadline, Timeout};
+use hal::lpc17xx::peripheral_clock::PeripheralClock::I2SClock;
+
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+/// Depth of both transmit and receive FIFOs, in words.
+const FIFO_DEPTH: u32 = 8;
+
+/// I2S configuration.
+#[derive(Clone, Copy)]
+pub struct I2SConf {
+  /// Transfer direction.
+  pub direction: Direction,
+  /// Sample rate in Hz.
+  pub sample_rate: u32,
+  /// Number of bits in one sample.
+  pub width: WordWidth,
+  /// Channel layout.
+  pub channels: Channels,
+  /// Divisor of the I2S peripheral clock.
+  pub divisor: u8,
+}
+
+/// Structure describing the I2S instance.
+#[derive(Clone, Copy)]
+pub struct I2S {
+  direction: Direction,
+}
+
+impl I2SConf {
+  /// Configures the I2S peripheral and returns an object to talk to it.
+  pub fn setup(&self) -> I2S {
+    I2SClock.enable();
+    I2SClock.set_divisor(self.divisor);
+
+    let wordwidth = match self.width {
+      WordWidth::Bits8  => 0b00,
+      WordWidth::Bits16 => 0b01,
+      WordWidth::Bits32 => 0b11,
+      WordWidth::Bits24 => unsafe { abort() },
+    };
+    let mono = self.channels == Channels::Mono;
+    let halfperiod = self.width.bits() - 1;
+
+    let (x, y) = rate_divider(I2SClock.frequency(),
+        i2s::bit_clock(self.sample_rate, self.width));
+
+    let i2s = I2S { direction: self.direction };
+    match self.direction {
+      Direction::Transmit => {
+        reg::I2S.dao
+          .set_stop(true)
+          .set_reset(true)
+          .set_wordwidth(wordwidth)
+          .set_mono(mono)
+          .set_ws_sel(false)  // master
+          .set_ws_halfperiod(halfperiod);
+        reg::I2S.txmode.set_txclksel(0);  // fractional rate divider
+        reg::I2S.txrate.set_x_divider(x).set_y_divider(y);
+        reg::I2S.txbitrate.set_bitrate(0);
+        reg::I2S.dao.set_reset(false).set_stop(false).set_mute(false);
+      },
+      Direction::Receive => {
+        reg::I2S.dai
+          .set_stop(true)
+          .set_reset(true)
+          .set_wordwidth(wordwidth)
+          .set_mono(mono)
+          .set_ws_sel(false)
+          .set_ws_halfperiod(halfperiod);
+        reg::I2S.rxmode.set_rxclksel(0);
+        reg::I2S.rxrate.set_x_divider(x).set_y_divider(y);
+        reg::I2S.rxbitrate.set_bitrate(0);
+        reg::I2S.dai.set_reset(false).set_stop(false);
+      },
+    }
+
+    i2s
+  }
+}
+
+impl I2S {
+  /// Enables the FIFO level interrupt for the configured direction.
+  ///
+  /// TX interrupt fires when the FIFO has at most `level` words in it, RX
+  /// interrupt fires when it has more than `level` words. The NVIC line is
+  /// `isr_i2s`.
+  pub fn enable_irq(&self, level: u32) {
+    match self.direction {
+      Direction::Transmit => {
+        reg::I2S.irq.set_tx_depth_irq(level).set_tx_irq_enable(true);
+      },
+      Direction::Receive => {
+        reg::I2S.irq.set_rx_depth_irq(level).set_rx_irq_enable(true);
+      },
+    };
+  }
+
+  /// Disables the FIFO level interrupts.
+  pub fn disable_irq(&self) {
+    reg::I2S.irq.set_tx_irq_enable(false).set_rx_irq_enable(false);
+  }
+
+  /// Mutes or unmutes the transmit channel.
+  pub fn set_mute(&self, mute: bool) {
+    reg::I2S.dao.set_mute(mute);
+  }
+}
+
+impl i2s::I2S for I2S {
+  fn can_write(&self) -> bool {
+    reg::I2S.state.tx_level() < FIFO_DEPTH
+  }
+
+  fn can_read(&self) -> bool {
+    reg::I2S.state.rx_level() > 0
+  }
+
+  fn write(&self, sample: u32) {
+    wait_for!(self.can_write());
+    reg::I2S.txfifo.set_data(sample);
+  }
+
+  fn read(&self) -> u32 {
+    wait_for!(self.can_read());
+    reg::I2S.rxfifo.data()
+  }
+}
+
+impl i2s::TryI2S for I2S {
+  fn try_write(&self, sample: u32, deadline: &Deadline)
+      -> Result<(), Timeout> {
+    try!(try_wait_for!(i2s::I2S::can_write(self), deadline));
+    reg::I2S.txfifo.set_data(sample);
+    Ok(())
+  }
+
+  fn try_read(&self, deadline: &Deadline) -> Result<u32, Timeout> {
+    try!(try_wait_for!(i2s::I2S::can_read(self), deadline));
+    Ok(reg::I2S.rxfifo.data())
+  }
+}
+
+/// Finds X/Y for the fractional divider, so that `pclk * X / (2 * Y)` is as
+/// close to the requested bit clock as possible. Rates out of range get the
+/// closest limit.
+fn rate_divider(pclk: u32, bit_clock: u32) -> (u32, u32) {
+  let mut best: (u32, u32) = (1, 255);
+  let mut best_err: u32 = 0xffff_ffff;
+
+  for y in 1..256u32 {
+    let x = ((bit_clock as u64 * 2 * y as u64 + pclk as u64 / 2) /
+        pclk as u64) as u32;
+    if x == 0 {
+      continue;
+    }
+    let x = if x > y { y } else { x };
+    let actual = (pclk as u64 * x as u64 / (2 * y as u64)) as u32;
+    let err = if actual > bit_clock {
+      actual - bit_clock
+    } else {
+      bit_clock - actual
+    };
+    if err < best_err {
+      best_err = err;
+      best = (x, y);
+      if err == 0 {
+        break;
+      }
+    }
+  }
+
+  best
+}
+
+#[cfg(test)]
+mod test {
+  use super::rate_divider;
+
+  #[test]
+  fn finds_exact_divider() {
+    // 48kHz, 16 bits from 24MHz.
+    assert!(rate_divider(24_000_000, 1_536_000) == (16, 125));
+  }
+
+  #[test]
+  fn finds_closest_divider() {
+    // 44.1kHz, 16 bits from 25MHz: 1411290Hz.
+    assert!(rate_divider(25_000_000, 1_411_200) == (7, 62));
+  }
+
+  #[test]
+  fn clamps_out_of_range_rates() {
+    assert!(rate_divider(24_000_000, 24_000_000) == (1, 1));
+    assert!(rate_divider(1_000_000, 10) == (1, 255));
+  }
+}
+
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(I2S = {
+    0x00 => reg32 dao {          // digital audio output
+      1..0  => wordwidth,
+      2     => mono,
+      3     => stop,
+      4     => reset,
+      5     => ws_sel,
+      14..6 => ws_halfperiod,
+      15    => mute,
+    },
+    0x04 => reg32 dai {          // digital audio input
+      1..0  => wordwidth,
+      2     => mono,
+      3     => stop,
+      4     => reset,
+      5     => ws_sel,
+      14..6 => ws_halfperiod,
+    },
+    0x08 => reg32 txfifo {
+      31..0 => data: wo,
+    },
+    0x0c => reg32 rxfifo {
+      31..0 => data: ro,
+    },
+    0x10 => reg32 state {
+      0      => irq: ro,
+      1      => dmareq1: ro,
+      2      => dmareq2: ro,
+      15..8  => rx_level: ro,
+      23..16 => tx_level: ro,
+    },
+    0x1c => reg32 irq {
+      0      => rx_irq_enable,
+      1      => tx_irq_enable,
+      15..8  => rx_depth_irq,
+      23..16 => tx_depth_irq,
+    },
+    0x20 => reg32 txrate {
+      7..0  => y_divider,
+      15..8 => x_divider,
+    },
+    0x24 => reg32 rxrate {
+      7..0  => y_divider,
+      15..8 => x_divider,
+    },
+    0x28 => reg32 txbitrate {
+      5..0 => bitrate,
+    },
+    0x2c => reg32 rxbitrate {
+      5..0 => bitrate,
+    },
+    0x30 => reg32 txmode {
+      1..0 => txclksel,
+      2    => tx4pin,
+      3    => txmcena,
+    },
+    0x34 => reg32 rxmode {
+      1..0 => rxclksel,
+      2    => rx4pin,
+      3    => rxmcena,
+    },
+  });
+
+  extern {
+    #[link_name="lpc17xx_iomem_I2S"] pub static I2S: I2S;
+  }
+}
@@ -17,7 +17,10 @@
 
 pub mod system_clock;
 pub mod peripheral_clock;
+pub mod flash;
+pub mod i2s;
 pub mod pin;
+pub mod power;
 pub mod pwm;
 // pub mod ssp;
 pub mod timer;
@@ -21,6 +21,7 @@ This module should be considered private until further notice.
 
 use core::intrinsics::abort;
 
+use hal::clock::PeripheralFrequency;
 use super::system_clock::system_clock;
 use self::PeripheralClock::*;
 use self::PeripheralDivisor::*;
@@ -216,6 +217,12 @@ impl PeripheralClock {
   }
 }
 
+impl PeripheralFrequency for PeripheralClock {
+  fn frequency(&self) -> u32 {
+    system_clock() / self.get_divisor() as u32
+  }
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
 
@@ -22,6 +22,10 @@ on the package.
 
 use core::intrinsics::abort;
 use core::option::Option;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 use self::Port::*;
 
@@ -274,6 +278,16 @@ impl ::hal::pin::Gpio for Pin {
 impl ::hal::pin::Adc for Pin {
   /// Read analog input value of pin
   fn read(&self) -> u32 {
+    match ::hal::pin::TryAdc::try_read(self, &NoDeadline) {
+      Ok(value) => value,
+      Err(_) => unsafe { abort() },
+    }
+  }
+}
+
+impl ::hal::pin::TryAdc for Pin {
+  /// Read analog input value of pin, stopping the conversion on timeout
+  fn try_read(&self, deadline: &Deadline) -> Result<u32, Timeout> {
     let adc = &reg::ADC;
     let channel = self.adc_channel().unwrap();
     let mut cr = adc.CR();
@@ -282,7 +296,13 @@ impl ::hal::pin::Adc for Pin {
     cr |= (1 << 24) as u32;
     adc.set_CR(cr);
 
-    wait_for!((adc.STAT() & (1 << channel)) != 0);
+    match try_wait_for!((adc.STAT() & (1 << channel)) != 0, deadline) {
+      Ok(()) => (),
+      Err(e) => {
+        adc.set_CR((adc.CR() as u32) & !(1 << 24));
+        return Err(e);
+      },
+    }
 
     let data = match channel {
       0 => adc.DR0(),
@@ -297,7 +317,7 @@ impl ::hal::pin::Adc for Pin {
     };
 
     adc.set_CR((adc.CR() as u32) & !(1 << 24));
-    (data >> 4) & 0xFFF // 12 bit range
+    Ok((data >> 4) & 0xFFF) // 12 bit range
   }
 }
 
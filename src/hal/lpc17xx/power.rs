@@ -0,0 +1,204 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Power modes of LPC17xx.
+
+Sleep keeps all peripherals running. Deep sleep stops all clocks but the RTC,
+and power-down additionally turns off the flash. In both modes the MCU wakes
+up on any of the enabled wake up interrupts, running from the internal
+resonator; `enter` restores the clock configuration before returning.
+
+Deep power-down turns off everything but the RTC domain. It's left only
+through reset, so `deep_power_down` doesn't return.
+*/
+
+use core::intrinsics::abort;
+use core::option::Option::{self, Some, None};
+
+use hal::cortex_m3::nvic;
+use hal::power::{wait_for_interrupt, Mode as CoreMode, ResetReason};
+use hal::lpc17xx::system_clock::restore_clock;
+
+/// Power mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+  /// Core clock is stopped.
+  Sleep,
+  /// All clocks but the RTC are stopped.
+  DeepSleep,
+  /// Deep sleep with the flash turned off.
+  PowerDown,
+  /// Everything is turned off but the RTC domain.
+  DeepPowerDown,
+}
+
+/// Interrupts that can bring the MCU out of deep sleep and power-down.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub enum WakeSource {
+  Watchdog,
+  Rtc,
+  Eint0,
+  Eint1,
+  Eint2,
+  /// Also shared with GPIO interrupts.
+  Eint3,
+  BrownOut,
+  Ethernet,
+  UsbActivity,
+  CanActivity,
+}
+
+impl WakeSource {
+  fn irq(self) -> usize {
+    match self {
+      WakeSource::Watchdog    => 0,
+      WakeSource::Rtc         => 17,
+      WakeSource::Eint0       => 18,
+      WakeSource::Eint1       => 19,
+      WakeSource::Eint2       => 20,
+      WakeSource::Eint3       => 21,
+      WakeSource::BrownOut    => 23,
+      WakeSource::Ethernet    => 28,
+      WakeSource::UsbActivity => 33,
+      WakeSource::CanActivity => 34,
+    }
+  }
+}
+
+/// Allows the given interrupt to wake up the MCU.
+///
+/// The peripheral must be configured to raise the interrupt separately.
+pub fn enable_wake_source(source: WakeSource) {
+  nvic::enable_irq(source.irq());
+}
+
+/// Stops the given interrupt from waking up the MCU.
+pub fn disable_wake_source(source: WakeSource) {
+  nvic::disable_irq(source.irq());
+}
+
+/// Enters the given power mode until woken up.
+///
+/// For deep power-down this never returns.
+pub fn enter(mode: Mode) {
+  match mode {
+    Mode::Sleep => {
+      reg::PCON.pcon.set_pm(0);
+      wait_for_interrupt(CoreMode::Sleep);
+    },
+    Mode::DeepSleep|Mode::PowerDown => {
+      reg::PCON.pcon.set_pm(if mode == Mode::PowerDown { 1 } else { 0 });
+      wait_for_interrupt(CoreMode::DeepSleep);
+      reg::PCON.pcon.set_pm(0);
+      restore_clock();
+    },
+    Mode::DeepPowerDown => deep_power_down(),
+  }
+}
+
+/// Enters deep power-down mode.
+pub fn deep_power_down() -> ! {
+  reg::PCON.pcon.set_pm(3);
+  wait_for_interrupt(CoreMode::DeepSleep);
+  // Reset is the only way out, a spurious wake up shouldn't continue.
+  unsafe { abort() };
+}
+
+/// Returns the deepest mode the MCU was in since the flags were last read,
+/// and clears the flags.
+///
+/// Call on boot to distinguish waking up from deep power-down from a normal
+/// reset.
+pub fn last_mode() -> Option<Mode> {
+  let pcon = reg::PCON.pcon.get();
+  let mode = if pcon.dpdflag() {
+    Some(Mode::DeepPowerDown)
+  } else if pcon.pdflag() {
+    Some(Mode::PowerDown)
+  } else if pcon.dsflag() {
+    Some(Mode::DeepSleep)
+  } else if pcon.smflag() {
+    Some(Mode::Sleep)
+  } else {
+    None
+  };
+
+  reg::PCON.pcon
+    .clear_smflag()
+    .clear_dsflag()
+    .clear_pdflag()
+    .clear_dpdflag();
+  mode
+}
+
+/// Returns the cause of the last reset, and clears the reset flags.
+///
+/// Several flags may be set (e.g. after a power on, the external reset flag is
+/// usually set too), the most significant one is returned.
+pub fn reset_reason() -> ResetReason {
+  let rsid = reg::RSID.rsid.get();
+  let reason = if rsid.por() {
+    ResetReason::PowerOn
+  } else if rsid.bodr() {
+    ResetReason::BrownOut
+  } else if rsid.wdtr() {
+    ResetReason::Watchdog
+  } else if rsid.extr() {
+    ResetReason::External
+  } else {
+    ResetReason::Unknown
+  };
+
+  reg::RSID.rsid
+    .clear_por()
+    .clear_extr()
+    .clear_wdtr()
+    .clear_bodr();
+  reason
+}
+
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(PCON = {
+    0x00 => reg32 pcon {
+      0..1 => pm,                   // power mode, with SLEEPDEEP
+      2    => bodrpm,               // brown-out reduced power mode
+      3    => bogd,                 // brown-out global disable
+      4    => bord,                 // brown-out reset disable
+      8    => smflag: set_to_clear, // sleep mode entry flag
+      9    => dsflag: set_to_clear, // deep sleep entry flag
+      10   => pdflag: set_to_clear, // power-down entry flag
+      11   => dpdflag: set_to_clear, // deep power-down entry flag
+    }
+  });
+
+  ioregs!(RSID = {
+    0x00 => reg32 rsid {
+      0    => por: set_to_clear,    // power-on reset
+      1    => extr: set_to_clear,   // external reset
+      2    => wdtr: set_to_clear,   // watchdog reset
+      3    => bodr: set_to_clear,   // brown-out reset
+    }
+  });
+
+  extern {
+    #[link_name="lpc17xx_iomem_PCON"] pub static PCON: PCON;
+    #[link_name="lpc17xx_iomem_RSID"] pub static RSID: RSID;
+  }
+}
@@ -21,13 +21,19 @@ it's currently not supported at all.
 */
 
 use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
 
+use hal::clock::{ClockListener, DRAIN_SPINS};
 use hal::lpc17xx::peripheral_clock::{PeripheralClock, SSP0Clock, SSP1Clock};
 use hal::lpc17xx::system_clock::system_clock;
 use hal::pin::PinConf_;
 use hal::spi;
+use hal::time::{Deadline, SpinDeadline, Timeout};
 
 #[path="../../util/ioreg.rs"] mod ioreg;
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
 
 /// SPI configuration.
 ///
@@ -59,6 +65,7 @@ impl SPIConf {
     let ssp = SSP {
       peripheral: self.peripheral,
       reg: self.peripheral.reg(),
+      frequency: self.frequency,
     };
 
     let clock = self.peripheral.peripheral_clock();
@@ -80,6 +87,7 @@ impl SPIConf {
 pub struct SSP {
   peripheral: SSPPeripheral, // TODO(farcaller): clean up the warning
   reg: &'static reg::SSP,
+  frequency: u32,
 }
 
 pub enum SSPPeripheral {SSP0, SSP1}
@@ -100,6 +108,19 @@ impl SSPPeripheral {
   }
 }
 
+impl ClockListener for SSP {
+  /// Lets the current transfer complete.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(self.written(), deadline);
+  }
+
+  /// Recomputes the prescaler for the configured bus frequency.
+  fn clock_changed(&self) {
+    self.set_frequency(self.frequency);
+  }
+}
+
 impl SSP {
   #[allow(non_snake_case)]
   fn set_format(&self, bits: u8, mode: u8) {
@@ -221,6 +242,19 @@ impl spi::Spi for SSP {
   }
 }
 
+impl spi::TrySpi for SSP {
+  fn try_write(&self, value: u8, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.writeable(), deadline));
+    self.reg.set_DR(value as u32);
+    try_wait_for!(self.written(), deadline)
+  }
+
+  fn try_read(&self, deadline: &Deadline) -> Result<u8, Timeout> {
+    try!(try_wait_for!(self.readable(), deadline));
+    Ok((self.reg.DR() & 0xff) as u8)
+  }
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
 
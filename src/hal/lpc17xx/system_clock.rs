@@ -20,7 +20,13 @@ This module includes code for setting up the clock, flash, access time and
 performing initial peripheral configuration.
 */
 
+use core::intrinsics::abort;
 use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::clock::{ClockTree, notify_will_change, notify_changed};
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 #[path="../../util/ioreg.rs"]
 #[macro_use] mod ioreg;
@@ -74,9 +80,55 @@ pub fn system_clock() -> u32 {
   unsafe { SystemClock }
 }
 
+/// Clock tree of LPC17xx.
+///
+/// There are no bus prescalers, all buses run at the core clock. Peripherals
+/// have individual divisors, see `PeripheralClock`.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 { system_clock() }
+  fn ahb_frequency(&self) -> u32 { system_clock() }
+  fn apb1_frequency(&self) -> u32 { system_clock() }
+  fn apb2_frequency(&self) -> u32 { system_clock() }
+}
+
+static mut CurrentClock: Option<Clock> = None;
+
+/// Re-applies the configuration last passed to `init_clock`.
+///
+/// Deep sleep and power-down modes switch the MCU back to the internal
+/// resonator, this brings the PLL back up on wake up.
+pub fn restore_clock() {
+  match unsafe { CurrentClock } {
+    Some(ref clock) => init_clock(clock),
+    None => (),
+  }
+}
+
 /// Initialise the system clock.
 #[inline(always)]
 pub fn init_clock(clock: &Clock) {
+  match try_init_clock(clock, &NoDeadline) {
+    Ok(()) => (),
+    Err(_) => unsafe { abort() },
+  }
+}
+
+/// Initialise the system clock, giving up if the oscillator or PLL doesn't
+/// start before `deadline`.
+///
+/// On timeout the MCU may be left running from any of the clock sources.
+pub fn try_init_clock(clock: &Clock, deadline: &Deadline)
+    -> Result<(), Timeout> {
   use self::ClockSource::*;
   let src_clock: u32 = match clock.source {
     Internal =>   4_000_000,
@@ -88,27 +140,86 @@ pub fn init_clock(clock: &Clock) {
   match clock.pll {
     Some(ref pll) => {
       match clock.source {
-        Main(freq) => init_main_oscillator(freq),
+        Main(freq) => try!(init_main_oscillator(freq, deadline)),
         _ => (),
       }
       dst_clock = (src_clock * pll.m as u32 * 2) / pll.n as u32 / pll.divisor as u32;
       init_flash_access(dst_clock);
-      init_pll(pll, &clock.source);
+      try!(init_pll(pll, &clock.source, deadline));
+    },
+    None => {
+      match clock.source {
+        Main(freq) => try!(init_main_oscillator(freq, deadline)),
+        _ => (),
+      }
+      dst_clock = src_clock;
+      select_source(&clock.source);
+      reg::CCLKCFG.set_value(0);
+      init_flash_access(dst_clock);
     },
-    None => { dst_clock = src_clock; },
   }
 
-  unsafe { SystemClock = dst_clock };
+  unsafe {
+    SystemClock = dst_clock;
+    CurrentClock = Some(*clock);
+  };
+  Ok(())
+}
+
+/// Switches the system clock to the given configuration at runtime.
+///
+/// Clock listeners are notified before and after the switch. PLL0 is
+/// disconnected first, as it can't be reconfigured while in use, and flash
+/// access time is kept at the safe maximum until the new clock is running.
+pub fn reconfigure_clock(clock: &Clock) {
+  match try_reconfigure_clock(clock, &NoDeadline) {
+    Ok(()) => (),
+    Err(_) => unsafe { abort() },
+  }
+}
+
+/// Switches the system clock at runtime, giving up if a clock doesn't start
+/// or stop before `deadline`.
+///
+/// Listeners are notified of the change even on timeout, as the clock may
+/// have changed already.
+pub fn try_reconfigure_clock(clock: &Clock, deadline: &Deadline)
+    -> Result<(), Timeout> {
+  notify_will_change();
+
+  init_flash_access(MAX_FREQUENCY);
+  let result = match disconnect_pll(deadline) {
+    Ok(()) => try_init_clock(clock, deadline),
+    err => err,
+  };
+
+  notify_changed();
+  result
+}
+
+/// Fastest core clock of the family.
+const MAX_FREQUENCY: u32 = 120_000_000;
+
+fn disconnect_pll(deadline: &Deadline) -> Result<(), Timeout> {
+  let connected_bit = 1 << 25;
+  if reg::PLL0STAT.value() & connected_bit == connected_bit {
+    reg::PLL0CON.set_value(1);
+    write_pll0_changes();
+  }
+  reg::PLL0CON.set_value(0);
+  write_pll0_changes();
+  try_wait_for!(reg::PLL0STAT.value() & (1 << 24) == 0, deadline)
 }
 
 #[inline(always)]
-fn init_main_oscillator(freq: u32) {
+fn init_main_oscillator(freq: u32, deadline: &Deadline)
+    -> Result<(), Timeout> {
   let val: u32 = if freq > 15_000_000 { 1 << 4 } else { 0 } |
                  (1 << 5);
 
   reg::SCS.set_value(val);
 
-  wait_for!(reg::SCS.value() & (1 << 6) == (1 << 6));
+  try_wait_for!(reg::SCS.value() & (1 << 6) == (1 << 6), deadline)
 }
 
 #[inline(always)]
@@ -124,8 +235,9 @@ fn init_flash_access(freq: u32) {
 }
 
 #[inline(always)]
-fn wait_for_pll0stat_bit(bit: usize) {
-  wait_for!(reg::PLL0STAT.value() & (1 << bit) == (1 << bit));
+fn wait_for_pll0stat_bit(bit: usize, deadline: &Deadline)
+    -> Result<(), Timeout> {
+  try_wait_for!(reg::PLL0STAT.value() & (1 << bit) == (1 << bit), deadline)
 }
 
 #[inline(always)]
@@ -135,25 +247,31 @@ fn write_pll0_changes() {
 }
 
 #[inline(always)]
-fn init_pll(pll: &PLL0, source: &ClockSource) {
+fn select_source(source: &ClockSource) {
   use self::ClockSource::*;
   match source {
     &Internal => reg::CLKSRCSEL.set_value(0),
     &Main(_)  => reg::CLKSRCSEL.set_value(1),
     &RTC =>      reg::CLKSRCSEL.set_value(2),
   }
+}
+
+#[inline(always)]
+fn init_pll(pll: &PLL0, source: &ClockSource, deadline: &Deadline)
+    -> Result<(), Timeout> {
+  select_source(source);
 
   let val: u32 = ((pll.n as u32 - 1) << 16) | ((pll.m as u32 - 1) << 0);
   reg::PLL0CFG.set_value(val);
   write_pll0_changes();
   reg::PLL0CON.set_value(1);
   write_pll0_changes();
-  wait_for_pll0stat_bit(24);
+  try!(wait_for_pll0stat_bit(24, deadline));
   reg::CCLKCFG.set_value((pll.divisor - 1) as u32);
-  wait_for_pll0stat_bit(26);
+  try!(wait_for_pll0stat_bit(26, deadline));
   reg::PLL0CON.set_value(3);
   write_pll0_changes();
-  wait_for_pll0stat_bit(25);
+  wait_for_pll0stat_bit(25, deadline)
 }
 
 mod reg {
@@ -19,6 +19,8 @@ Timer configuration.
 This code supports all four primary timers of the MCU.
 */
 
+use hal::clock::ClockListener;
+use hal::lpc17xx::peripheral_clock::PeripheralClock;
 use hal::timer;
 
 use self::TimerPeripheral::*;
@@ -51,6 +53,8 @@ pub struct TimerConf {
 #[derive(Clone, Copy)]
 pub struct Timer {
   reg: &'static reg::TIMER,
+  clock: PeripheralClock,
+  tick_frequency: u32,
 }
 
 impl Timer {
@@ -74,10 +78,21 @@ impl Timer {
 
     Timer {
       reg: reg,
+      clock: clock,
+      tick_frequency: clock.frequency() / counter,
     }
   }
 }
 
+impl ClockListener for Timer {
+  /// Keeps the counter running at the same rate, or as close as the new
+  /// clock allows if it's slower than that.
+  fn clock_changed(&self) {
+    let counter = self.clock.frequency() / self.tick_frequency;
+    self.reg.set_PR(if counter > 0 { counter - 1 } else { 0 });
+  }
+}
+
 impl timer::Timer for Timer {
   #[inline(always)]
   fn get_counter(&self) -> u32 {
@@ -21,12 +21,16 @@ than other UARTs in MCU).
 */
 
 use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
 
 use hal::lpc17xx::peripheral_clock::PeripheralClock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART0Clock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART2Clock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART3Clock;
 use drivers::chario::CharIO;
+use hal::clock::{ClockListener, DRAIN_SPINS};
+use hal::time::{Deadline, SpinDeadline, Timeout};
 use hal::uart;
 
 use self::UARTPeripheral::*;
@@ -133,6 +137,7 @@ enum FIFOTriggerLevel {
 pub struct UART {
   reg: &'static reg::UART,
   clock: PeripheralClock,
+  baudrate: u32,
 }
 
 impl UARTPeripheral {
@@ -160,6 +165,7 @@ impl UART {
     let uart = UART {
       reg: peripheral.reg(),
       clock: peripheral.peripheral_clock(),
+      baudrate: baudrate,
     };
 
     uart.clock.enable();
@@ -176,7 +182,8 @@ impl UART {
   }
 
   fn set_baud_rate(&self, baud_rate: u32) {
-    self.reg.set_LCR(0b1000_0000); // enable divisor latch access
+    let lcr = self.reg.LCR();
+    self.reg.set_LCR(lcr | LCRDivisorLatch as u32); // enable divisor latch access
 
     let (dl, div_add_val, mul_val) = self.calculate_divisors(baud_rate);
 
@@ -184,7 +191,7 @@ impl UART {
     self.reg.set_DLL(dl & 0xff);
     self.reg.set_FDR(div_add_val | (mul_val << 4));
 
-    self.reg.set_LCR(3);
+    self.reg.set_LCR(lcr & !(LCRDivisorLatch as u32));
   }
 
   fn set_mode(&self, word_len: WordLen, parity: uart::Parity, stop_bits: StopBit) {
@@ -282,6 +289,20 @@ impl UART {
   }
 }
 
+impl ClockListener for UART {
+  /// Lets the transmitter drain, so no character goes out at a wrong rate.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(self.reg.LSR() as u8 & LSRTxEmpty == LSRTxEmpty,
+                          deadline);
+  }
+
+  /// Recomputes divisors for the configured baud rate.
+  fn clock_changed(&self) {
+    self.set_baud_rate(self.baudrate);
+  }
+}
+
 impl CharIO for UART {
   fn putc(&self, value: char) {
     wait_for!(self.reg.LSR() as u8 & LSRTHREmpty == LSRTHREmpty);
@@ -289,6 +310,15 @@ impl CharIO for UART {
   }
 }
 
+impl uart::TryCharIO for UART {
+  fn try_putc(&self, value: char, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.reg.LSR() as u8 & LSRTHREmpty == LSRTHREmpty,
+        deadline));
+    self.reg.set_THR(value as u32);
+    Ok(())
+  }
+}
+
 #[allow(non_upper_case_globals)]
 static FIFOResetRx: u8 = 0b1_0;
 #[allow(non_upper_case_globals)]
@@ -299,6 +329,11 @@ static LCRModeMask: u8 = 0b1_11_1_1_11;
 
 #[allow(non_upper_case_globals)]
 static LSRTHREmpty: u8 = 0x20;
+#[allow(non_upper_case_globals)]
+static LSRTxEmpty: u8 = 0x40;
+
+#[allow(non_upper_case_globals)]
+static LCRDivisorLatch: u8 = 0x80;
 
 mod reg {
   use volatile_cell::VolatileCell;
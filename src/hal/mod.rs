@@ -35,6 +35,8 @@ pub mod lpc11xx;
           feature = "cpu_cortex-m4",
           feature = "cpu_cortex-m7"))]
 mod cortex_common;
+#[cfg(feature = "cpu_cortex-m0")]
+pub mod cortex_m0;
 #[cfg(feature = "cpu_cortex-m3")]
 pub mod cortex_m3;
 #[cfg(feature = "cpu_cortex-m4")]
@@ -42,11 +44,31 @@ pub mod cortex_m4;
 #[cfg(feature = "cpu_cortex-m7")]
 pub mod cortex_m7;
 
+pub mod bitbang;
+pub mod clock;
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+pub mod crash;
+pub mod flash;
+pub mod i2c;
+pub mod i2s;
 pub mod mem_init;
 pub mod pin;
+#[cfg(any(feature = "cpu_cortex-m0",
+          feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+pub mod power;
 pub mod pwm;
 pub mod spi;
 pub mod stack;
+#[cfg(any(feature = "cpu_cortex-m0",
+          feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+pub mod tick;
+pub mod time;
 pub mod timer;
 pub mod uart;
 
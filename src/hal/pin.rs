@@ -15,6 +15,10 @@
 
 //! Common definitions for pin HAL.
 
+use core::result::Result;
+
+use hal::time::{Deadline, Timeout};
+
 pub use self::GpioDirection::*;
 pub use self::GpioLevel::*;
 
@@ -57,3 +61,10 @@ pub trait Adc {
   /// Read analog input value
   fn read(&self) -> u32;
 }
+
+/// Analog input with a deadline.
+pub trait TryAdc {
+  /// Read analog input value, giving up if the conversion doesn't complete
+  /// before `deadline`.
+  fn try_read(&self, deadline: &Deadline) -> Result<u32, Timeout>;
+}
@@ -0,0 +1,105 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Core low power modes.
+
+Cortex-M cores have two low power modes entered with `wfi`: sleep, which only
+gates the core clock, and deep sleep, whose meaning is defined by the MCU (it
+usually stops the PLL and most of the clocks). MCU-specific modules build on
+top of these to select the exact mode, configure wake up sources and restore
+clocks on wake up.
+
+The idle hook is what the OS calls when it has nothing to run. It sleeps until
+the next interrupt by default, and can be replaced by the application, e.g. to
+enter a deeper mode when it knows no timers are due soon.
+*/
+
+use core::option::Option::{self, Some, None};
+
+use hal::cortex_common::scb;
+use util::support::wfi;
+
+/// Core low power mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+  /// Core clock is stopped, peripherals keep running.
+  Sleep,
+  /// MCU-defined deep sleep mode.
+  DeepSleep,
+}
+
+/// Cause of the last reset, as reported by the MCU.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResetReason {
+  /// Power on.
+  PowerOn,
+  /// Reset pin.
+  External,
+  /// Watchdog timeout.
+  Watchdog,
+  /// Supply voltage dropped below the brown-out threshold.
+  BrownOut,
+  /// Reset requested by software.
+  Software,
+  /// Illegal low power mode entry.
+  LowPower,
+  /// None of the above, or not reported.
+  Unknown,
+}
+
+static mut IdleHook: Option<fn()> = None;
+
+/// Enters the given low power mode until an interrupt is pending.
+///
+/// The interrupt handler runs before this function returns, unless interrupts
+/// are masked with PRIMASK.
+pub fn wait_for_interrupt(mode: Mode) {
+  match mode {
+    Mode::Sleep => wfi(),
+    Mode::DeepSleep => {
+      scb::set_sleepdeep(true);
+      wfi();
+      scb::set_sleepdeep(false);
+    },
+  }
+}
+
+/// Makes the core go back to sleep after returning from an interrupt handler
+/// to thread mode.
+///
+/// Useful for fully interrupt-driven applications, which only need to call
+/// `wait_for_interrupt` once.
+pub fn set_sleep_on_exit(enabled: bool) {
+  scb::set_sleeponexit(enabled);
+}
+
+/// Replaces the idle hook.
+///
+/// Passing `None` restores the default behaviour of sleeping until the next
+/// interrupt.
+pub fn set_idle_hook(hook: Option<fn()>) {
+  unsafe { IdleHook = hook };
+}
+
+/// Runs the idle hook.
+///
+/// Called by the OS when there is nothing to run.
+pub fn idle() {
+  match unsafe { IdleHook } {
+    Some(hook) => hook(),
+    None => wait_for_interrupt(Mode::Sleep),
+  }
+}
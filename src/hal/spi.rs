@@ -21,8 +21,15 @@ SPIConf is a MCU-specific struct.
 As SPI performs read and write as one operation, special care should be taken if
 `write()` and `read()` methods are used with several devices on one SPI
 peripheral. The best way is to always use `transfer()`.
+
+`TrySpi` provides the same operations bounded by a deadline, for when a stuck
+bus or a slave stretching the clock must not hang the caller.
 */
 
+use core::result::Result;
+
+use hal::time::{Deadline, Timeout};
+
 /// SPI trait.
 pub trait Spi {
   /// Writes a byte over SPI.
@@ -44,3 +51,19 @@ pub trait Spi {
     self.read()
   }
 }
+
+/// SPI operations with a deadline.
+pub trait TrySpi {
+  /// Writes a byte over SPI.
+  fn try_write(&self, value: u8, deadline: &Deadline) -> Result<(), Timeout>;
+
+  /// Reads the last byte received.
+  fn try_read(&self, deadline: &Deadline) -> Result<u8, Timeout>;
+
+  /// Performs an SPI transfer operation.
+  fn try_transfer(&self, value: u8, deadline: &Deadline)
+      -> Result<u8, Timeout> {
+    try!(self.try_write(value, deadline));
+    self.try_read(deadline)
+  }
+}
@@ -0,0 +1,152 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Flash programming for STM32F1.
+//!
+//! Supports low- and medium-density devices with 1kB pages. Flash is
+//! programmed a half-word at a time.
+
+use core::intrinsics::volatile_store;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::flash;
+use hal::flash::Error;
+use hal::stm32f1::init::reg;
+use hal::time::{Deadline, NoDeadline};
+
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+const FLASH_START: u32 = 0x0800_0000;
+const FLASH_END: u32 = 0x0802_0000;
+const PAGE_SIZE: u32 = 0x400;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+/// Flash controller.
+#[derive(Clone, Copy)]
+pub struct Flash;
+
+impl Flash {
+  /// Returns the flash controller.
+  pub fn new() -> Flash {
+    Flash
+  }
+
+  fn unlock(&self) {
+    if reg::FLASH.cr.lock() {
+      reg::FLASH.keyr.set_key(KEY1);
+      reg::FLASH.keyr.set_key(KEY2);
+    }
+  }
+
+  fn lock(&self) {
+    reg::FLASH.cr.set_lock(true);
+  }
+
+  /// Waits for the current operation and returns its status.
+  fn finish(&self, deadline: &Deadline) -> Result<(), Error> {
+    match try_wait_for!(!reg::FLASH.sr.busy(), deadline) {
+      Ok(()) => (),
+      Err(_) => return Err(Error::Timeout),
+    }
+
+    let sr = reg::FLASH.sr.get();
+    let result = if sr.write_protection_error() {
+      Err(Error::Protected)
+    } else if sr.programming_error() {
+      Err(Error::Failed)
+    } else {
+      Ok(())
+    };
+
+    reg::FLASH.sr
+      .clear_end_of_operation()
+      .clear_programming_error()
+      .clear_write_protection_error();
+    result
+  }
+
+  fn program_half_word(&self, addr: u32, value: u16, deadline: &Deadline)
+      -> Result<(), Error> {
+    reg::FLASH.cr.set_program(true);
+    unsafe { volatile_store(addr as *mut u16, value) };
+    let result = self.finish(deadline);
+    reg::FLASH.cr.set_program(false);
+    result
+  }
+}
+
+impl flash::Flash for Flash {
+  fn sector_size(&self, _: u32) -> u32 {
+    PAGE_SIZE
+  }
+
+  fn erase_sector(&self, addr: u32) -> Result<(), Error> {
+    flash::TryFlash::try_erase_sector(self, addr, &NoDeadline)
+  }
+
+  fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    flash::TryFlash::try_program(self, addr, data, &NoDeadline)
+  }
+
+  fn is_read_protected(&self) -> bool {
+    reg::FLASH.obr.read_protection()
+  }
+}
+
+impl flash::TryFlash for Flash {
+  fn try_erase_sector(&self, addr: u32, deadline: &Deadline)
+      -> Result<(), Error> {
+    if addr < FLASH_START || addr >= FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+
+    self.unlock();
+    reg::FLASH.cr.set_page_erase(true);
+    reg::FLASH.ar.set_address(addr & !(PAGE_SIZE - 1));
+    reg::FLASH.cr.set_start(true);
+    let result = self.finish(deadline);
+    reg::FLASH.cr.set_page_erase(false);
+    self.lock();
+    result
+  }
+
+  fn try_program(&self, addr: u32, data: &[u32], deadline: &Deadline)
+      -> Result<(), Error> {
+    let end = addr + data.len() as u32 * 4;
+    if addr & 3 != 0 || addr < FLASH_START || end > FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+
+    self.unlock();
+    let mut result = Ok(());
+    for i in 0..data.len() {
+      let word_addr = addr + i as u32 * 4;
+      result = self.program_half_word(word_addr, data[i] as u16, deadline);
+      if result.is_ok() {
+        result = self.program_half_word(word_addr + 2, (data[i] >> 16) as u16,
+            deadline);
+      }
+      if result.is_err() {
+        break;
+      }
+    }
+    self.lock();
+    result
+  }
+}
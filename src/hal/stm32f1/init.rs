@@ -20,6 +20,13 @@
 
 //use hal::mem_init::init_data;
 use core::default;
+use core::intrinsics::abort;
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::clock::{ClockTree, notify_will_change, notify_changed};
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 use self::SystemClockSource::*;
 use self::PllClockSource::*;
@@ -201,6 +208,35 @@ impl default::Default for ClockConfig {
   }
 }
 
+static mut CurrentConfig: Option<ClockConfig> = None;
+
+/// Returns the configuration last set up on the hardware, or the reset
+/// configuration if `ClockConfig::setup` wasn't called.
+pub fn current_config() -> ClockConfig {
+  match unsafe { CurrentConfig } {
+    Some(config) => config,
+    None => ClockConfig::new_default(),
+  }
+}
+
+/// Clock tree of STM32F1, as set up by the last `ClockConfig::setup`.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 { current_config().get_ahb_frequency() }
+  fn ahb_frequency(&self) -> u32 { current_config().get_ahb_frequency() }
+  fn apb1_frequency(&self) -> u32 { current_config().get_apb1_frequency() }
+  fn apb2_frequency(&self) -> u32 { current_config().get_apb2_frequency() }
+}
+
 impl ClockConfig {
   /// Return the default clock configuration that hardware go to after reset.
   pub fn new_default() -> ClockConfig {
@@ -208,42 +244,63 @@ impl ClockConfig {
   }
 
   /// Set this configuration on the hardware.
+  ///
+  /// Can be called again at runtime to switch clocks, clock listeners are
+  /// notified before and after the switch.
   pub fn setup(&self) {
+    match self.try_setup(&NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Set this configuration on the hardware, giving up if a clock doesn't
+  /// become ready before `deadline`.
+  ///
+  /// The system runs from HSI while the clocks are reconfigured. Listeners
+  /// are notified of the change even on failure, as the system may be left
+  /// running from HSI.
+  pub fn try_setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
+    notify_will_change();
+    let result = self.switch(deadline);
+    match result {
+      Ok(()) => unsafe { CurrentConfig = Some(*self) },
+      Err(_) => (),
+    }
+    notify_changed();
+    result
+  }
+
+  fn switch(&self, deadline: &Deadline) -> Result<(), Timeout> {
     let rcc = &reg::RCC;
     let flash = &reg::FLASH;
 
+    // HSI is the reset clock, the latency set for the current clock is enough
+    // for it.
+    rcc.cr.set_hsi_on(true);
+    try!(try_wait_for!(rcc.cr.hsi_ready(), deadline));
+    rcc.cfgr.set_system_clock(0b00);
+    try!(try_wait_for!(rcc.cfgr.system_clock_status() == 0b00, deadline));
+
     let source_type = match self.source {
       SystemClockHSI => {
-        rcc.cr.set_hsi_on(true);
-        wait_for!(rcc.cr.hsi_ready());
         0b00  // system_clock = HSI
       },
       SystemClockHSE(_) => {
         rcc.cr.set_hse_on(true);
-        wait_for!(rcc.cr.hse_ready());
+        try!(try_wait_for!(rcc.cr.hse_ready(), deadline));
         0b01  // system_clock = HSE
       },
       SystemClockPLL(pll_conf) => {
-        match rcc.cfgr.system_clock() {
-            // if PLL is current clock source, temporarly switch it to HSI
-            // because otherwise we cannot modify it
-            // TODO(blazewicz): check if HSI is on
-            0b10 => {
-                rcc.cfgr.set_system_clock(0b00);
-                wait_for!(rcc.cfgr.system_clock_status() == 0b00);
-            },
-            _ => ()
-        };
-
-        // disable PLL
+        // disable PLL, it can't be modified while running
         rcc.cr.set_pll_on(false);
-        wait_for!(!rcc.cr.pll_ready());
+        try!(try_wait_for!(!rcc.cr.pll_ready(), deadline));
 
         // set pll clock source
         let pll_clock_source = match pll_conf.source {
             PllSourceHSIDiv2 => {
                 rcc.cr.set_hsi_on(true);
-                wait_for!(rcc.cr.hsi_ready());
+                try!(try_wait_for!(rcc.cr.hsi_ready(), deadline));
                 false // pll_clock_source = HSI divided by 2
             },
             PllSourceHSE(_)  => {
@@ -256,7 +313,7 @@ impl ClockConfig {
                 };
                 rcc.cfgr.set_pll_hse_divider(pll_hse_divider);
 
-                wait_for!(rcc.cr.hse_ready());
+                try!(try_wait_for!(rcc.cr.hse_ready(), deadline));
                 true // pll_clock_source = HSE
             },
         };
@@ -274,23 +331,14 @@ impl ClockConfig {
 
         // enable PLL
         rcc.cr.set_pll_on(true);
-        wait_for!(rcc.cr.pll_ready());
+        try!(try_wait_for!(rcc.cr.pll_ready(), deadline));
 
         0b10 // system_clock = PLL
        }
     };
 
-    /* TODO(blazewicz): configuring flash latency is straightforward
-     * and could be done automatically:
-     *       0 < SYSCLK <= 24 MHz => 0
-     *  24 MHz < SYSCLK <= 48 MHz => 1
-     *  48 MHz < SYSCLK <= 72 MHz => 2
-     */
-    flash.acr.set_latency(self.flash_latency as u32);
-
-    rcc.cfgr.set_system_clock(source_type);
-    wait_for!(rcc.cfgr.system_clock_status() == source_type);
-
+    // Prescalers are set while running from HSI, so that the buses never run
+    // faster than configured.
     let ahb_select = match self.ahb_prescaler {
         AhbDivNone => 0b0000u32,
         AhbDiv2    => 0b1000u32,
@@ -324,6 +372,24 @@ impl ClockConfig {
     };
     rcc.cfgr.set_apb2_prescaler(apb2_select);
 
+    /* TODO(blazewicz): configuring flash latency is straightforward
+     * and could be done automatically:
+     *       0 < SYSCLK <= 24 MHz => 0
+     *  24 MHz < SYSCLK <= 48 MHz => 1
+     *  48 MHz < SYSCLK <= 72 MHz => 2
+     */
+    // Latency must cover the faster of HSI and the new clock while switching.
+    let latency = self.flash_latency as u32;
+    if latency > flash.acr.latency() {
+      flash.acr.set_latency(latency);
+    }
+
+    rcc.cfgr.set_system_clock(source_type);
+    try!(try_wait_for!(rcc.cfgr.system_clock_status() == source_type,
+        deadline));
+
+    flash.acr.set_latency(latency);
+
     let mco_select = match self.mco {
         McoClockNone => 0b000u32,
         McoClockSys  => 0b100u32,
@@ -332,6 +398,7 @@ impl ClockConfig {
         McoClockPLL  => 0b111u32,
     };
     rcc.cfgr.set_mco(mco_select);
+    Ok(())
   }
 
   /// Returns AHB clock frequency
@@ -455,6 +522,29 @@ pub mod reg {
       4    => prefetch_buf_on : rw,
       5    => prefetch_buf_status : ro,
     },
+    0x04 => reg32 keyr {    // flash key
+      31..0 => key : wo,
+    },
+    0x0C => reg32 sr {      // status
+      0 => busy : ro,
+      2 => programming_error : set_to_clear,
+      4 => write_protection_error : set_to_clear,
+      5 => end_of_operation : set_to_clear,
+    },
+    0x10 => reg32 cr {      // control
+      0 => program : rw,
+      1 => page_erase : rw,
+      2 => mass_erase : rw,
+      6 => start : rw,
+      7 => lock : rw,
+    },
+    0x14 => reg32 ar {      // address
+      31..0 => address : wo,
+    },
+    0x1C => reg32 obr {     // option byte
+      0 => option_error : ro,
+      1 => read_protection : ro,
+    },
   });
 
   ioregs!(PWR = {
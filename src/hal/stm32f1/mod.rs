@@ -16,6 +16,7 @@
 //! HAL for STM32F1.
 
 pub mod init;
+pub mod flash;
 pub mod peripheral_clock;
 pub mod pin;
 pub mod spi;
@@ -0,0 +1,172 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Flash programming for ST STM32F4.
+//!
+//! Programming is done a word at a time, which requires 2.7-3.6V supply.
+//! Sectors 0-3 are 16kB, sector 4 is 64kB and sectors 5-11 are 128kB.
+
+use core::intrinsics::volatile_store;
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::flash;
+use hal::flash::Error;
+use hal::time::{Deadline, NoDeadline};
+use super::init::reg;
+
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+const FLASH_START: u32 = 0x0800_0000;
+const FLASH_END: u32 = 0x0810_0000;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_PSIZE_X32: u32 = 0b10 << 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+const SR_EOP: u32 = 1 << 0;
+const SR_OPERR: u32 = 1 << 1;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_PGPERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = SR_OPERR | SR_WRPERR | SR_PGAERR | SR_PGPERR | SR_PGSERR;
+
+/// Read protection level 0 (no protection) value in OPTCR.RDP.
+const RDP_LEVEL0: u32 = 0xAA;
+
+/// Flash controller.
+#[derive(Clone, Copy)]
+pub struct Flash;
+
+impl Flash {
+  /// Returns the flash controller.
+  pub fn new() -> Flash {
+    Flash
+  }
+
+  fn unlock(&self) {
+    if reg::FLASH.CR() & CR_LOCK != 0 {
+      reg::FLASH.set_KEYR(KEY1);
+      reg::FLASH.set_KEYR(KEY2);
+    }
+  }
+
+  fn lock(&self) {
+    reg::FLASH.set_CR(CR_LOCK);
+  }
+
+  /// Waits for the current operation and returns its status.
+  fn finish(&self, deadline: &Deadline) -> Result<(), Error> {
+    match try_wait_for!(reg::FLASH.SR() & SR_BSY == 0, deadline) {
+      Ok(()) => (),
+      Err(_) => return Err(Error::Timeout),
+    }
+
+    let sr = reg::FLASH.SR();
+    reg::FLASH.set_SR(SR_EOP | SR_ERRORS);  // write 1 to clear
+    if sr & SR_WRPERR != 0 {
+      Err(Error::Protected)
+    } else if sr & (SR_PGAERR | SR_PGPERR) != 0 {
+      Err(Error::InvalidAddress)
+    } else if sr & SR_ERRORS != 0 {
+      Err(Error::Failed)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Returns the sector number and sector size containing `addr`, or None if
+/// it's outside flash.
+fn sector(addr: u32) -> Option<(u32, u32)> {
+  if addr < FLASH_START || addr >= FLASH_END {
+    return None;
+  }
+  let offset = addr - FLASH_START;
+  Some(match offset {
+    0x0_0000...0x0_FFFF => (offset / 0x4000, 0x4000),
+    0x1_0000...0x1_FFFF => (4, 0x1_0000),
+    _ => (5 + (offset - 0x2_0000) / 0x2_0000, 0x2_0000),
+  })
+}
+
+impl flash::Flash for Flash {
+  /// Returns 0 for addresses outside flash.
+  fn sector_size(&self, addr: u32) -> u32 {
+    match sector(addr) {
+      Some((_, size)) => size,
+      None => 0,
+    }
+  }
+
+  fn erase_sector(&self, addr: u32) -> Result<(), Error> {
+    flash::TryFlash::try_erase_sector(self, addr, &NoDeadline)
+  }
+
+  fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    flash::TryFlash::try_program(self, addr, data, &NoDeadline)
+  }
+
+  fn is_read_protected(&self) -> bool {
+    (reg::FLASH.OPTCR() >> 8) & 0xff != RDP_LEVEL0
+  }
+}
+
+impl flash::TryFlash for Flash {
+  fn try_erase_sector(&self, addr: u32, deadline: &Deadline)
+      -> Result<(), Error> {
+    let snb = match sector(addr) {
+      Some((snb, _)) => snb,
+      None => return Err(Error::InvalidAddress),
+    };
+
+    self.unlock();
+    reg::FLASH.set_CR(CR_PSIZE_X32 | CR_SER | (snb << 3));
+    reg::FLASH.set_CR(reg::FLASH.CR() | CR_STRT);
+    let result = self.finish(deadline);
+    self.lock();
+    result
+  }
+
+  fn try_program(&self, addr: u32, data: &[u32], deadline: &Deadline)
+      -> Result<(), Error> {
+    let end = addr + data.len() as u32 * 4;
+    if addr & 3 != 0 || addr < FLASH_START || end > FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+
+    self.unlock();
+    reg::FLASH.set_CR(CR_PSIZE_X32 | CR_PG);
+    let mut result = Ok(());
+    for i in 0..data.len() {
+      unsafe { volatile_store((addr + i as u32 * 4) as *mut u32, data[i]) };
+      result = self.finish(deadline);
+      if result.is_err() {
+        break;
+      }
+    }
+    self.lock();
+    result
+  }
+}
@@ -0,0 +1,297 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! I2S configuration for ST STM32F4.
+//!
+//! I2S is provided by SPI2 and SPI3 in I2S mode. The I2S clock is generated by
+//! PLLI2S, which shares its input (and `m` divisor) with the main PLL, so
+//! `init::SysConf` must be set up before calling `I2SConf::setup`.
+//!
+//! Pins are not configured here, set them up with the I2S alternate function.
+//!
+//! The transmitter takes one half-word at a time, so a 24 or 32-bit sample, or
+//! a mono one sent in both slots, takes several writes. `write` only sends the
+//! first half-word of a sample and queues the rest, which go out one per
+//! `can_write` call as the transmitter empties. That way
+//! `DoubleBuffer::service` sends one half-word per TXE interrupt instead of
+//! spinning in the handler. `flush` waits for the end of the last sample.
+
+use core::cell::Cell;
+use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use super::init;
+use super::peripheral_clock;
+use hal::i2s;
+use hal::i2s::{Direction, WordWidth, Channels};
+use hal::time::{Deadline, NoDeadline, Timeout};
+
+#[path="../../util/ioreg.rs"]
+#[macro_use] mod ioreg;
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+/// Available I2S peripherals.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub enum I2SPeripheral {
+  I2S2,
+  I2S3,
+}
+
+/// I2S configuration.
+#[derive(Clone, Copy)]
+pub struct I2SConf {
+  /// Peripheral to use.
+  pub peripheral: I2SPeripheral,
+  /// Transfer direction.
+  pub direction: Direction,
+  /// Sample rate in Hz.
+  pub sample_rate: u32,
+  /// Number of bits in one sample (16, 24 or 32).
+  pub width: WordWidth,
+  /// Channel layout. In mono mode each sample is sent in both slots.
+  pub channels: Channels,
+  /// PLLI2S multiplication factor, 192 to 432.
+  pub pll_n: u16,
+  /// PLLI2S division factor, 2 to 7.
+  pub pll_r: u8,
+}
+
+/// Structure describing an I2S instance.
+pub struct I2S {
+  reg: &'static reg::SPI,
+  width: WordWidth,
+  channels: Channels,
+  /// Half-words of the current sample still to be sent, the next one in the
+  /// highest bits.
+  queue: Cell<u64>,
+  /// Number of half-words in `queue`.
+  queued: Cell<u32>,
+}
+
+impl I2SConf {
+  /// Configures the I2S peripheral and returns an object to talk to it.
+  pub fn setup(&self) -> I2S {
+    match self.try_setup(&NoDeadline) {
+      Ok(i2s) => i2s,
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Configures the I2S peripheral, giving up if PLLI2S doesn't lock before
+  /// `deadline`.
+  pub fn try_setup(&self, deadline: &Deadline) -> Result<I2S, Timeout> {
+    use self::I2SPeripheral::*;
+    let (clock, reg) = match self.peripheral {
+      I2S2 => (peripheral_clock::PeripheralClock::SPI2Clock, &reg::SPI2),
+      I2S3 => (peripheral_clock::PeripheralClock::SPI3Clock, &reg::SPI3),
+    };
+
+    let i2s_clock = try!(self.setup_pll(deadline));
+    clock.enable();
+
+    let (datlen, chlen) = match self.width {
+      WordWidth::Bits16 => (0b00u32, 0u32),
+      WordWidth::Bits24 => (0b01u32, 1u32),
+      WordWidth::Bits32 => (0b10u32, 1u32),
+      WordWidth::Bits8  => unsafe { abort() },
+    };
+    let i2scfg: u32 = match self.direction {
+      Direction::Transmit => 0b10,
+      Direction::Receive  => 0b11,
+    };
+
+    // Fs = I2SxCLK / (channel bits * 2 * (2 * div + odd))
+    let frame_bits: u32 = if chlen == 0 { 32 } else { 64 };
+    let scaled = (i2s_clock / frame_bits * 10 / self.sample_rate + 5) / 10;
+    let odd = scaled & 1;
+    let div = (scaled - odd) / 2;
+    if div < 2 || div > 255 {
+      unsafe { abort() };
+    }
+
+    reg.set_I2SCFGR(0);
+    reg.set_I2SPR(div | (odd << 8));
+    reg.set_I2SCFGR(
+      chlen |
+      (datlen << 1) |
+      (0b00 << 4) |     // Philips standard
+      (i2scfg << 8) |
+      (1 << 11));       // I2S mode
+    reg.set_I2SCFGR(reg.I2SCFGR() | (1 << 10));  // enable
+
+    Ok(I2S {
+      reg: reg,
+      width: self.width,
+      channels: self.channels,
+      queue: Cell::new(0),
+      queued: Cell::new(0),
+    })
+  }
+
+  fn setup_pll(&self, deadline: &Deadline) -> Result<u32, Timeout> {
+    if self.pll_r < 2 || self.pll_r > 7 {
+      unsafe { abort() };
+    }
+
+    let cr_pll_i2s_on: u32 = 1 << 26;
+    let cr_pll_i2s_ready: u32 = 1 << 27;
+
+    init::reg::RCC.set_CR(init::reg::RCC.CR() & !cr_pll_i2s_on);
+    init::reg::RCC.set_PLLI2SCFGR(
+      ((self.pll_n as u32) << 6) |
+      ((self.pll_r as u32) << 28));
+    init::reg::RCC.set_CR(init::reg::RCC.CR() | cr_pll_i2s_on);
+
+    try!(try_wait_for!(
+        init::reg::RCC.CR() & cr_pll_i2s_ready == cr_pll_i2s_ready, deadline));
+
+    Ok(init::pll_input_clock() * self.pll_n as u32 / self.pll_r as u32)
+  }
+}
+
+impl I2S {
+  /// Enables the TXE or RXNE interrupt. The NVIC line is the one of the
+  /// underlying SPI peripheral.
+  pub fn enable_irq(&self) {
+    self.reg.set_CR2(self.reg.CR2() | (1 << 7) | (1 << 6));
+  }
+
+  /// Disables TXE and RXNE interrupts.
+  pub fn disable_irq(&self) {
+    self.reg.set_CR2(self.reg.CR2() & !((1 << 7) | (1 << 6)));
+  }
+
+  /// Blocks until the last written sample is completely sent.
+  pub fn flush(&self) {
+    while self.queued.get() > 0 {
+      self.feed();
+    }
+  }
+
+  /// Sends the next queued half-word if the transmitter is empty.
+  fn feed(&self) {
+    let queued = self.queued.get();
+    if queued > 0 && self.reg.SR() & SR_TXE == SR_TXE {
+      let half = (self.queue.get() >> (16 * (queued - 1))) as u32;
+      self.reg.set_DR(half & 0xffff);
+      self.queued.set(queued - 1);
+    }
+  }
+
+  /// Returns the half-words of the frame slots taken by `sample`, the first
+  /// one in the highest bits, and their number.
+  fn halves(&self, sample: u32) -> (u64, u32) {
+    let (slot, count) = match self.width {
+      WordWidth::Bits16 => ((sample & 0xffff) as u64, 1),
+      WordWidth::Bits24 => (
+          (((sample >> 8) & 0xffff) << 16 | (sample & 0xff) << 8) as u64, 2),
+      _ => (sample as u64, 2),
+    };
+    if self.channels == Channels::Mono {
+      ((slot << (16 * count)) | slot, 2 * count)
+    } else {
+      (slot, count)
+    }
+  }
+
+  fn read_half(&self, deadline: &Deadline) -> Result<u32, Timeout> {
+    try!(try_wait_for!(self.reg.SR() & SR_RXNE == SR_RXNE, deadline));
+    Ok(self.reg.DR() & 0xffff)
+  }
+
+  fn read_slot(&self, deadline: &Deadline) -> Result<u32, Timeout> {
+    match self.width {
+      WordWidth::Bits16 => self.read_half(deadline),
+      WordWidth::Bits24 => {
+        let hi = try!(self.read_half(deadline));
+        Ok((hi << 8) | (try!(self.read_half(deadline)) >> 8))
+      },
+      _ => {
+        let hi = try!(self.read_half(deadline));
+        Ok((hi << 16) | try!(self.read_half(deadline)))
+      },
+    }
+  }
+}
+
+impl i2s::I2S for I2S {
+  /// Sends a queued half-word if possible, then returns true if the previous
+  /// sample is out and the transmitter is empty.
+  fn can_write(&self) -> bool {
+    self.feed();
+    self.queued.get() == 0 && self.reg.SR() & SR_TXE == SR_TXE
+  }
+
+  fn can_read(&self) -> bool {
+    self.reg.SR() & SR_RXNE == SR_RXNE
+  }
+
+  fn write(&self, sample: u32) {
+    match i2s::TryI2S::try_write(self, sample, &NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  fn read(&self) -> u32 {
+    match i2s::TryI2S::try_read(self, &NoDeadline) {
+      Ok(sample) => sample,
+      Err(_) => unsafe { abort() },
+    }
+  }
+}
+
+impl i2s::TryI2S for I2S {
+  fn try_write(&self, sample: u32, deadline: &Deadline)
+      -> Result<(), Timeout> {
+    try!(try_wait_for!(i2s::I2S::can_write(self), deadline));
+    let (halves, count) = self.halves(sample);
+    self.queue.set(halves);
+    self.queued.set(count);
+    self.feed();
+    Ok(())
+  }
+
+  fn try_read(&self, deadline: &Deadline) -> Result<u32, Timeout> {
+    let sample = try!(self.read_slot(deadline));
+    if self.channels == Channels::Mono {
+      try!(self.read_slot(deadline));
+    }
+    Ok(sample)
+  }
+}
+
+const SR_RXNE: u32 = 1 << 0;
+const SR_TXE: u32 = 1 << 1;
+
+mod reg {
+  use volatile_cell::VolatileCell;
+
+  ioreg_old!(SPI: u32, CR1, CR2, SR, DR, CRCPR, RXCRCR, TXCRCR, I2SCFGR, I2SPR);
+  reg_rw!(SPI, u32, CR1,     set_CR1,     CR1);
+  reg_rw!(SPI, u32, CR2,     set_CR2,     CR2);
+  reg_rw!(SPI, u32, SR,      set_SR,      SR);
+  reg_rw!(SPI, u32, DR,      set_DR,      DR);
+  reg_rw!(SPI, u32, I2SCFGR, set_I2SCFGR, I2SCFGR);
+  reg_rw!(SPI, u32, I2SPR,   set_I2SPR,   I2SPR);
+
+  extern {
+    #[link_name="stm32f4_iomem_SPI2"] pub static SPI2: SPI;
+    #[link_name="stm32f4_iomem_SPI3"] pub static SPI3: SPI;
+  }
+}
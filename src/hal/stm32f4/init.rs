@@ -20,6 +20,13 @@
 
 use hal::mem_init::init_data;
 use core::intrinsics::abort;
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::clock;
+use hal::clock::ClockTree;
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 #[path="../../util/ioreg.rs"]
 #[macro_use] mod ioreg;
@@ -101,22 +108,161 @@ pub fn apb_low_clock() -> u32 {
   unsafe { APBLowClock }
 }
 
+// TODO(farcaller): move to peripheral_clock?
+static mut PLLInputClock: u32 = 1_000_000;
+
+/// Returns the frequency of the clock feeding both PLL and PLLI2S (after the
+/// common `m` divisor).
+///
+/// Defaults to HSI / 16 if the main PLL was never configured.
+#[inline(always)]
+pub fn pll_input_clock() -> u32 {
+  unsafe { PLLInputClock }
+}
+
+/// Clock tree of STM32F4.
+///
+/// Bus frequencies are derived from the system clock and the prescalers
+/// currently set in RCC.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+/// Returns the divisor encoded in AHB prescaler bits.
+fn ahb_divisor(hpre: u32) -> u32 {
+  match hpre {
+    0b0000...0b0111 => 1,
+    0b1000...0b1011 => 2 << (hpre & 0b11),
+    _               => 4 << (hpre & 0b111),
+  }
+}
+
+/// Returns the divisor encoded in APB prescaler bits.
+fn apb_divisor(ppre: u32) -> u32 {
+  match ppre {
+    0b000...0b011 => 1,
+    _             => 2 << (ppre & 0b11),
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 {
+    // The core runs at HCLK.
+    self.ahb_frequency()
+  }
+
+  fn ahb_frequency(&self) -> u32 {
+    system_clock() / ahb_divisor((reg::RCC.CFGR() >> 4) & 0b1111)
+  }
+
+  fn apb1_frequency(&self) -> u32 {
+    self.ahb_frequency() / apb_divisor((reg::RCC.CFGR() >> 10) & 0b111)
+  }
+
+  fn apb2_frequency(&self) -> u32 {
+    self.ahb_frequency() / apb_divisor((reg::RCC.CFGR() >> 13) & 0b111)
+  }
+}
+
+static mut CurrentClock: Option<ClockConf> = None;
+
+/// Re-applies the clock configuration last set up by `SysConf::setup`.
+///
+/// Stop mode switches the MCU back to HSI, this brings HSE and the PLL back
+/// up on wake up.
+pub fn restore_clock() {
+  match unsafe { CurrentClock } {
+    Some(ref clock) => { let _ = clock.setup(&NoDeadline); },
+    None => (),
+  }
+}
+
 impl SysConf {
   /// Performs the MCU initialization.
   pub fn setup(&self) {
+    match self.try_setup(&NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Performs the MCU initialization, giving up if an oscillator or the PLL
+  /// doesn't become ready before `deadline`.
+  pub fn try_setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     init_data();
-    self.clock.setup();
+    try!(self.clock.setup(deadline));
+    unsafe { CurrentClock = Some(self.clock) };
+    Ok(())
   }
 }
 
 impl ClockConf {
-  fn setup(&self) {
+  /// Switches the system clock to this configuration at runtime.
+  ///
+  /// Clock listeners are notified before and after the switch. The system
+  /// runs from HSI in the meantime, as the PLL can't be reconfigured while it
+  /// is in use. Flash wait states are raised before and lowered after any
+  /// frequency change.
+  pub fn reconfigure(&self) {
+    match self.try_reconfigure(&NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Switches the system clock to this configuration, giving up if a clock
+  /// doesn't become ready before `deadline`.
+  ///
+  /// Listeners are notified of the change even on failure, as the system may
+  /// be left running from HSI; the frequencies they see are then the HSI ones.
+  pub fn try_reconfigure(&self, deadline: &Deadline) -> Result<(), Timeout> {
+    clock::notify_will_change();
+    let result = self.switch(deadline);
+    match result {
+      Ok(()) => (),
+      Err(_) => update_running_from_hsi(),
+    }
+    clock::notify_changed();
+    result
+  }
+
+  fn switch(&self, deadline: &Deadline) -> Result<(), Timeout> {
+    let hsi_on_bit: u32 = 1 << 0;
+    let hsi_ready_bit: u32 = 1 << 1;
+    reg::RCC.set_CR(reg::RCC.CR() | hsi_on_bit);
+    try!(try_wait_for!(reg::RCC.CR() & hsi_ready_bit == hsi_ready_bit,
+        deadline));
+    // Wait states set for the current clock are enough for HSI.
+    try!(self.set_system_clock(reg::SystemClockSwitch::SystemClockHSI,
+        deadline));
+
+    let pll_on_bit: u32 = 1 << 24;
+    let pll_ready_bit: u32 = 1 << 25;
+    reg::RCC.set_CR(reg::RCC.CR() & !pll_on_bit);
+    try!(try_wait_for!(reg::RCC.CR() & pll_ready_bit == 0, deadline));
+
+    try!(self.setup(deadline));
+    unsafe { CurrentClock = Some(*self) };
+    Ok(())
+  }
+
+  fn setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     use self::SystemClockSource::*;
     use self::PLLClockSource::*;
 
     match self.source {
       SystemClockHSI => {
-        // HSI is default boot mode, do nothing
+        // HSI is default boot mode, this only matters when switching back
+        try!(self.set_system_clock(reg::SystemClockSwitch::SystemClockHSI,
+            deadline));
+        self.set_clock_divisors(1, 1, 1);
+        self.setup_flash(16_000_000);
         unsafe {
           SystemClock = 16_000_000;
           APBLowClock = 16_000_000;  // no divisor
@@ -128,8 +274,11 @@ impl ClockConf {
           // this code doesn't support wait states configuration for HSE
           unsafe { abort() };
         } else {
-          self.enable_hse();
-          self.set_system_clock(reg::SystemClockSwitch::SystemClockHSE);
+          try!(self.enable_hse(deadline));
+          try!(self.set_system_clock(reg::SystemClockSwitch::SystemClockHSE,
+              deadline));
+          self.set_clock_divisors(1, 1, 1);
+          self.setup_flash(freq);
           unsafe {
             SystemClock = freq;
             APBLowClock = freq;  // no divisor
@@ -139,9 +288,13 @@ impl ClockConf {
       SystemClockPLL(pll_conf) => {
         // Init and switch to pll
         match pll_conf.source {
-          PLLClockHSE(_) => self.enable_hse(),
+          PLLClockHSE(_) => try!(self.enable_hse(deadline)),
           _ => (),
         }
+        let pll_input: u32 = match pll_conf.source {
+          PLLClockHSE(freq) => freq,
+          PLLClockHSI       => 16_000_000,
+        } as u32 / pll_conf.m as u32;
         let sysfreq: u32 = match pll_conf.source {
           PLLClockHSE(freq) => freq,
           PLLClockHSI       => 16_000_000,
@@ -154,16 +307,19 @@ impl ClockConf {
         // TODO(farcaller): this should be configureable via ClockConf
         let apb_low_divisor = 4;
         self.set_clock_divisors(1, apb_low_divisor, 2);
-        pll_conf.setup();
+        try!(pll_conf.setup(deadline));
         // TODO(farcaller): this doesn't really belong here.
         self.setup_flash(sysfreq);
-        self.set_system_clock(reg::SystemClockSwitch::SystemClockPLL);
+        try!(self.set_system_clock(reg::SystemClockSwitch::SystemClockPLL,
+            deadline));
         unsafe {
           SystemClock = sysfreq;
           APBLowClock = sysfreq / apb_low_divisor as u32;
+          PLLInputClock = pll_input;
         };
       },
     }
+    Ok(())
   }
 
   fn setup_flash(&self, freq: u32) {
@@ -183,21 +339,24 @@ impl ClockConf {
     );
   }
 
-  fn enable_hse(&self) {
+  fn enable_hse(&self, deadline: &Deadline) -> Result<(), Timeout> {
     let val = reg::RCC.CR();
     let hse_on_bit: u32 = 1 << 16;
     let hse_ready_bit: u32 = 1 << 17;
     reg::RCC.set_CR(val | hse_on_bit);
 
-    wait_for!(reg::RCC.CR() & hse_ready_bit == hse_ready_bit);
+    try_wait_for!(reg::RCC.CR() & hse_ready_bit == hse_ready_bit, deadline)
   }
 
-  fn set_system_clock(&self, clock: reg::SystemClockSwitch) {
+  fn set_system_clock(&self, clock: reg::SystemClockSwitch,
+      deadline: &Deadline) -> Result<(), Timeout> {
     let val = reg::RCC.CFGR();
     let bits: u32 = clock as u32;
     let mask: u32 = !0b1111;
 
     reg::RCC.set_CFGR((val & mask) | bits);
+
+    try_wait_for!((reg::RCC.CFGR() >> 2) & 0b11 == bits, deadline)
   }
 
   fn set_clock_divisors(&self, ahb: u16, apb_low_speed: u8, apb_hi_speed: u8) {
@@ -234,8 +393,29 @@ impl ClockConf {
   }
 }
 
+/// Updates the cached frequencies after a failed switch, if it got as far as
+/// HSI, with the prescalers it left set.
+fn update_running_from_hsi() {
+  let cfgr = reg::RCC.CFGR();
+  if (cfgr >> 2) & 0b11 != reg::SystemClockSwitch::SystemClockHSI as u32 {
+    return;
+  }
+  let hpre = (cfgr >> 4) & 0b1111;
+  let ahb_shift = match hpre {
+    0b1000...0b1011 => (hpre & 0b11) + 1,
+    0b1100...0b1111 => (hpre & 0b11) + 6,
+    _ => 0,
+  };
+  let ppre1 = (cfgr >> 10) & 0b111;
+  let apb_low_shift = if ppre1 & 0b100 != 0 { (ppre1 & 0b11) + 1 } else { 0 };
+  unsafe {
+    SystemClock = 16_000_000 >> ahb_shift;
+    APBLowClock = SystemClock >> apb_low_shift;
+  }
+}
+
 impl PLLConf {
-  fn setup(&self) {
+  fn setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     use self::PLLClockSource::*;
 
     // TODO(farcaller): cmsis code overrides reserved bits in here, is that ok?
@@ -259,16 +439,16 @@ impl PLLConf {
 
     reg::RCC.set_PLLCFGR(val & mask | bits);
 
-    self.enable_pll();
+    self.enable_pll(deadline)
   }
 
-  fn enable_pll(&self) {
+  fn enable_pll(&self, deadline: &Deadline) -> Result<(), Timeout> {
     let val = reg::RCC.CR();
     let pll_on_bit: u32 = 1 << 24;
     let pll_ready_bit: u32 = 1 << 25;
     reg::RCC.set_CR(val | pll_on_bit);
 
-    wait_for!(reg::RCC.CR() & pll_ready_bit == pll_ready_bit);
+    try_wait_for!(reg::RCC.CR() & pll_ready_bit == pll_ready_bit, deadline)
   }
 }
 
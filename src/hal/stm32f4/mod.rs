@@ -16,6 +16,9 @@
 //! HAL for STM32F4.
 
 pub mod init;
+pub mod flash;
+pub mod i2s;
 pub mod peripheral_clock;
 pub mod pin;
+pub mod power;
 pub mod timer;
@@ -0,0 +1,251 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Power modes of ST STM32F4.
+
+In stop mode all clocks in the 1.2V domain are stopped, while SRAM and
+register contents are kept. Any EXTI line configured as a wake up source
+brings the MCU back, running from HSI; `enter` restores the clock configuration
+before returning.
+
+Standby turns off the 1.2V domain completely. The MCU wakes up through reset,
+either on a rising edge of the WKUP pin (PA0), an RTC alarm or wake up event,
+or an external reset. Use `woke_from_standby` on boot to tell these apart.
+*/
+
+use core::intrinsics::abort;
+
+use hal::cortex_m4::nvic;
+use hal::power::{wait_for_interrupt, Mode as CoreMode, ResetReason};
+use hal::stm32f4::init::restore_clock;
+use hal::stm32f4::init::reg::{PWR, RCC};
+use hal::stm32f4::peripheral_clock::PeripheralClock::{PWRClock, SYSCFGClock};
+use hal::stm32f4::pin::Port;
+
+const CR_LPDS: u32 = 1 << 0;
+const CR_PDDS: u32 = 1 << 1;
+const CR_CWUF: u32 = 1 << 2;
+const CR_CSBF: u32 = 1 << 3;
+
+const CSR_SBF: u32 = 1 << 1;
+const CSR_EWUP: u32 = 1 << 8;
+
+const RCC_CSR_RMVF: u32 = 1 << 24;
+const RCC_CSR_BORRSTF: u32 = 1 << 25;
+const RCC_CSR_PINRSTF: u32 = 1 << 26;
+const RCC_CSR_PORRSTF: u32 = 1 << 27;
+const RCC_CSR_SFTRSTF: u32 = 1 << 28;
+const RCC_CSR_IWDGRSTF: u32 = 1 << 29;
+const RCC_CSR_WWDGRSTF: u32 = 1 << 30;
+const RCC_CSR_LPWRRSTF: u32 = 1 << 31;
+
+/// Power mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+  /// Core clock is stopped.
+  Sleep,
+  /// All clocks are stopped, voltage regulator stays in main mode.
+  Stop,
+  /// All clocks are stopped, voltage regulator is in low power mode. Wakes up
+  /// slower than `Stop`.
+  StopLowPower,
+  /// 1.2V domain is powered off.
+  Standby,
+}
+
+/// EXTI trigger edge.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub enum Edge {
+  Rising,
+  Falling,
+  Both,
+}
+
+/// Events that can bring the MCU out of low power modes.
+#[derive(Clone, Copy)]
+pub enum WakeSource {
+  /// Rising edge on the WKUP pin (PA0). Only wakes up from standby.
+  WakeupPin,
+  /// Edge on the given pin, through EXTI lines 0-15.
+  Pin(Port, u8, Edge),
+  /// Internal EXTI lines 16-22, e.g. 17 for RTC alarm or 22 for RTC wake up.
+  Line(u8, Edge),
+}
+
+/// Returns the interrupt number serving the given EXTI line.
+fn exti_irq(line: u8) -> usize {
+  match line {
+    0...4   => 6 + line as usize,
+    5...9   => 23,
+    10...15 => 40,
+    16      => 1,
+    17      => 41,
+    18      => 42,
+    21      => 2,
+    22      => 3,
+    _       => unsafe { abort() },
+  }
+}
+
+fn setup_exti(line: u8, edge: Edge) {
+  let line = line as usize;
+  let (rising, falling) = match edge {
+    Edge::Rising  => (true, false),
+    Edge::Falling => (false, true),
+    Edge::Both    => (true, true),
+  };
+  reg::EXTI.rtsr.set_tr(line, rising);
+  reg::EXTI.ftsr.set_tr(line, falling);
+  reg::EXTI.pr.clear_pr(line);
+  reg::EXTI.imr.set_mr(line, true);
+  nvic::enable_irq(exti_irq(line as u8));
+}
+
+/// Allows the given event to wake up the MCU.
+///
+/// For EXTI sources the interrupt is enabled as well, its handler must call
+/// `clear_pending` with the line number.
+pub fn enable_wake_source(source: WakeSource) {
+  PWRClock.enable();
+  match source {
+    WakeSource::WakeupPin => PWR.set_CSR(PWR.CSR() | CSR_EWUP),
+    WakeSource::Pin(port, pin, edge) => {
+      if pin > 15 {
+        unsafe { abort() };
+      }
+      SYSCFGClock.enable();
+      reg::SYSCFG.exticr[(pin / 4) as usize]
+        .set_exti((pin % 4) as usize, port as u32);
+      setup_exti(pin, edge);
+    },
+    WakeSource::Line(line, edge) => setup_exti(line, edge),
+  }
+}
+
+/// Stops the given event from waking up the MCU.
+pub fn disable_wake_source(source: WakeSource) {
+  match source {
+    WakeSource::WakeupPin => PWR.set_CSR(PWR.CSR() & !CSR_EWUP),
+    WakeSource::Pin(_, line, _) | WakeSource::Line(line, _) => {
+      reg::EXTI.imr.set_mr(line as usize, false);
+    },
+  }
+}
+
+/// Clears the pending flag of an EXTI line.
+pub fn clear_pending(line: u8) {
+  reg::EXTI.pr.clear_pr(line as usize);
+}
+
+/// Enters the given power mode until woken up.
+///
+/// For standby this never returns.
+pub fn enter(mode: Mode) {
+  match mode {
+    Mode::Sleep => wait_for_interrupt(CoreMode::Sleep),
+    Mode::Stop|Mode::StopLowPower => {
+      PWRClock.enable();
+      let cr = PWR.CR() & !(CR_PDDS | CR_LPDS);
+      let lpds = if mode == Mode::StopLowPower { CR_LPDS } else { 0 };
+      PWR.set_CR(cr | lpds);
+      wait_for_interrupt(CoreMode::DeepSleep);
+      restore_clock();
+    },
+    Mode::Standby => standby(),
+  }
+}
+
+/// Enters standby mode.
+pub fn standby() -> ! {
+  PWRClock.enable();
+  // A set wake up flag would wake the MCU up right away.
+  PWR.set_CR(PWR.CR() | CR_PDDS | CR_CWUF);
+  wait_for_interrupt(CoreMode::DeepSleep);
+  // Reset is the only way out, a spurious wake up shouldn't continue.
+  unsafe { abort() };
+}
+
+/// Returns true if the MCU was reset by waking up from standby, and clears
+/// the flag.
+pub fn woke_from_standby() -> bool {
+  PWRClock.enable();
+  let standby = PWR.CSR() & CSR_SBF != 0;
+  PWR.set_CR(PWR.CR() | CR_CSBF);
+  standby
+}
+
+/// Returns the cause of the last reset, and clears the reset flags.
+///
+/// The pin reset flag is set for every reset, so it's only reported when no
+/// other flag is set.
+pub fn reset_reason() -> ResetReason {
+  let csr = RCC.CSR();
+  RCC.set_CSR(csr | RCC_CSR_RMVF);
+
+  if csr & RCC_CSR_PORRSTF != 0 {
+    ResetReason::PowerOn
+  } else if csr & RCC_CSR_BORRSTF != 0 {
+    ResetReason::BrownOut
+  } else if csr & (RCC_CSR_IWDGRSTF | RCC_CSR_WWDGRSTF) != 0 {
+    ResetReason::Watchdog
+  } else if csr & RCC_CSR_SFTRSTF != 0 {
+    ResetReason::Software
+  } else if csr & RCC_CSR_LPWRRSTF != 0 {
+    ResetReason::LowPower
+  } else if csr & RCC_CSR_PINRSTF != 0 {
+    ResetReason::External
+  } else {
+    ResetReason::Unknown
+  }
+}
+
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(EXTI = {
+    0x00 => reg32 imr {        // interrupt mask
+      0..22 => mr[23],
+    }
+    0x04 => reg32 emr {        // event mask
+      0..22 => mr[23],
+    }
+    0x08 => reg32 rtsr {       // rising trigger selection
+      0..22 => tr[23],
+    }
+    0x0c => reg32 ftsr {       // falling trigger selection
+      0..22 => tr[23],
+    }
+    0x10 => reg32 swier {      // software interrupt event
+      0..22 => swier[23],
+    }
+    0x14 => reg32 pr {         // pending
+      0..22 => pr[23]: set_to_clear,
+    }
+  });
+
+  ioregs!(SYSCFG = {
+    0x08 => reg32 exticr[4] {  // EXTI line to port mapping
+      0..15 => exti[4],
+    }
+  });
+
+  extern {
+    #[link_name="stm32f4_iomem_EXTI"] pub static EXTI: EXTI;
+    #[link_name="stm32f4_iomem_SYSCFG"] pub static SYSCFG: SYSCFG;
+  }
+}
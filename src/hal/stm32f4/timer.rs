@@ -18,6 +18,7 @@
 //! This code supports only TIM2 at the moment.
 
 use super::peripheral_clock;
+use hal::clock::{ClockListener, PeripheralFrequency};
 use hal::timer;
 
 #[path="../../util/ioreg.rs"]
@@ -34,6 +35,8 @@ pub enum TimerPeripheral {
 #[derive(Clone, Copy)]
 pub struct Timer {
   reg: &'static reg::TIM2To5,
+  clock: peripheral_clock::PeripheralClock,
+  tick_frequency: u32,
 }
 
 impl Timer {
@@ -52,10 +55,20 @@ impl Timer {
 
     Timer {
       reg: reg,
+      clock: clock,
+      tick_frequency: clock.frequency() / counter,
     }
   }
 }
 
+impl ClockListener for Timer {
+  /// Keeps the counter running at the same rate.
+  fn clock_changed(&self) {
+    self.reg.set_PSC(self.clock.frequency() / self.tick_frequency - 1);
+    self.reg.set_EGR(1);
+  }
+}
+
 impl timer::Timer for Timer {
   #[inline(always)]
   fn get_counter(&self) -> u32 {
@@ -19,8 +19,12 @@
 //! This module includes code for setting up the clock, flash, access time and
 //! performing initial peripheral configuration.
 
+use hal::clock::ClockTree;
 use hal::mem_init::init_data;
 use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 #[path="../../util/ioreg.rs"]
 #[macro_use] mod ioreg;
@@ -102,16 +106,75 @@ pub fn apb_low_clock() -> u32 {
   unsafe { APBLowClock }
 }
 
+/// Clock tree of STM32F7.
+///
+/// Bus frequencies are derived from the system clock and the prescalers
+/// currently set in RCC.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+/// Returns the divisor encoded in AHB prescaler bits.
+fn ahb_divisor(hpre: u32) -> u32 {
+  match hpre {
+    0b0000...0b0111 => 1,
+    0b1000...0b1011 => 2 << (hpre & 0b11),
+    _               => 4 << (hpre & 0b111),
+  }
+}
+
+/// Returns the divisor encoded in APB prescaler bits.
+fn apb_divisor(ppre: u32) -> u32 {
+  match ppre {
+    0b000...0b011 => 1,
+    _             => 2 << (ppre & 0b11),
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 {
+    // The core runs at HCLK.
+    self.ahb_frequency()
+  }
+
+  fn ahb_frequency(&self) -> u32 {
+    system_clock() / ahb_divisor(reg::RCC.cfgr.ahb_prescaler())
+  }
+
+  fn apb1_frequency(&self) -> u32 {
+    self.ahb_frequency() / apb_divisor(reg::RCC.cfgr.apb1_prescaler())
+  }
+
+  fn apb2_frequency(&self) -> u32 {
+    self.ahb_frequency() / apb_divisor(reg::RCC.cfgr.apb2_prescaler())
+  }
+}
+
 impl SysConf {
   /// Performs the MCU initialization.
   pub fn setup(&self) {
+    match self.try_setup(&NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Performs the MCU initialization, giving up if an oscillator or the PLL
+  /// doesn't become ready before `deadline`.
+  pub fn try_setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     init_data();
-    self.clock.setup();
+    self.clock.setup(deadline)
   }
 }
 
 impl ClockConf {
-  fn setup(&self) {
+  fn setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     use self::SystemClockSource::*;
     use self::PLLClockSource::*;
 
@@ -129,7 +192,7 @@ impl ClockConf {
           // this code doesn't support wait states configuration for HSE
           unsafe { abort() };
         } else {
-          self.enable_hse();
+          try!(self.enable_hse(deadline));
           self.set_system_clock(reg::SystemClockSwitch::SystemClockHSE);
           unsafe {
             SystemClock = freq;
@@ -140,7 +203,7 @@ impl ClockConf {
       SystemClockPLL(pll_conf) => {
         // Init and switch to pll
         match pll_conf.source {
-          PLLClockHSE(_) => self.enable_hse(),
+          PLLClockHSE(_) => try!(self.enable_hse(deadline)),
           _ => (),
         }
         let sysfreq: u32 = match pll_conf.source {
@@ -155,7 +218,7 @@ impl ClockConf {
         // TODO(farcaller): this should be configureable via ClockConf
         let apb_low_divisor = 4;
         self.set_clock_divisors(1, apb_low_divisor, 2);
-        pll_conf.setup();
+        try!(pll_conf.setup(deadline));
 
         if sysfreq > 180_000_000 {
           // frequencies above 180 MHz require over-drive mode. So when that's
@@ -178,6 +241,7 @@ impl ClockConf {
     // whether we were started from DFU or from a power on reset.
 
     reg::RCC.dckcfgr2.set_val(0);
+    Ok(())
   }
 
   fn setup_flash(&self, freq: u32) {
@@ -197,9 +261,9 @@ impl ClockConf {
       });
   }
 
-  fn enable_hse(&self) {
+  fn enable_hse(&self, deadline: &Deadline) -> Result<(), Timeout> {
     reg::RCC.cr.set_hse_on(true);
-    wait_for!(reg::RCC.cr.hse_ready());
+    try_wait_for!(reg::RCC.cr.hse_ready(), deadline)
   }
 
   fn set_system_clock(&self, clock: reg::SystemClockSwitch) {
@@ -237,7 +301,7 @@ impl ClockConf {
 }
 
 impl PLLConf {
-  fn setup(&self) {
+  fn setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
     use self::PLLClockSource::*;
     reg::RCC.pllcfgr
       .set_pll_div_factor(self.m as u32)
@@ -255,12 +319,12 @@ impl PLLConf {
       })
       .set_usb_prescaler(self.q as u32);
 
-    self.enable_pll();
+    self.enable_pll(deadline)
   }
 
-  fn enable_pll(&self) {
+  fn enable_pll(&self, deadline: &Deadline) -> Result<(), Timeout> {
     reg::RCC.cr.set_pll_on(true);
-    wait_for!(reg::RCC.cr.pll_ready());
+    try_wait_for!(reg::RCC.cr.pll_ready(), deadline)
   }
 }
 
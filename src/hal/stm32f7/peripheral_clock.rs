@@ -20,8 +20,9 @@
 //!
 //! Note: this module is used as part of initial setup if PLL is used.
 
-use super::init::reg;
+use super::init::{reg, Clocks};
 use core::marker::Copy;
+use hal::clock::{Bus, ClockTree, PeripheralFrequency};
 
 use self::PeripheralClock::*;
 
@@ -219,6 +220,35 @@ impl PeripheralClock {
     }
   }
 
+  fn bus(self) -> Bus {
+    match self {
+      GPIOAClock|GPIOBClock|GPIOCClock|GPIODClock|GPIOEClock|GPIOFClock|
+      GPIOGClock|GPIOHClock|GPIOIClock|GPIOJClock|GPIOKClock|CRCClock|
+      BKPSRAMClock|DTCMClock|DMA1Clock|DMA2Clock|DMA2DClock|
+      ETHMACClock|ETHMACTxClock|ETHMACRxClock|
+      ETHMACPTPClock|OTGHSClock|OTGHSULPIClock|
+      DCMIClock|CRYPClock|HASHClock|RNGClock|OTGFSClock|
+      FSMCClock|QSPIClock => Bus::Ahb,
+      TIM2Clock|TIM3Clock|TIM4Clock|TIM5Clock|TIM6Clock|TIM7Clock|TIM12Clock|
+      TIM13Clock|TIM14Clock|LPTIM1Clock|WWDGClock|SPI2Clock|SPI3Clock|SPDIFClock|
+      USART2Clock|USART3Clock|UART4Clock|UART5Clock|I2C1Clock|I2C2Clock|I2C3Clock|
+      I2C4Clock|CAN1Clock|CAN2Clock|CECClock|PWRClock|DACClock|UART7Clock|
+      UART8Clock => Bus::Apb1,
+      TIM1Clock|TIM8Clock|USART1Clock|USART6Clock|ADC1Clock|ADC2Clock|ADC3Clock|
+      SDMMC1Clock|SPI1Clock|SYSCFGClock|TIM9Clock|TIM10Clock|TIM11Clock|
+      SPI5Clock|SPI6Clock|SAI1Clock|SAI2Clock|LTDCClock => Bus::Apb2,
+    }
+  }
+
+  fn is_timer(self) -> bool {
+    match self {
+      TIM1Clock|TIM2Clock|TIM3Clock|TIM4Clock|TIM5Clock|TIM6Clock|TIM7Clock|
+      TIM8Clock|TIM9Clock|TIM10Clock|TIM11Clock|TIM12Clock|TIM13Clock|
+      TIM14Clock => true,
+      _ => false,
+    }
+  }
+
   fn set_reg(self, enable: bool) {
     let reg_bit = self.to_reg_bit();
     let mask: u32 = !reg_bit;
@@ -251,3 +281,16 @@ impl PeripheralClock {
     }
   }
 }
+
+impl PeripheralFrequency for PeripheralClock {
+  fn frequency(&self) -> u32 {
+    let clocks = Clocks::new();
+    let bus = clocks.frequency(self.bus());
+    // Timers run at twice the APB clock if APB is prescaled.
+    if self.is_timer() && bus != clocks.ahb_frequency() {
+      bus * 2
+    } else {
+      bus
+    }
+  }
+}
@@ -23,6 +23,11 @@ use core::default;
 use core::intrinsics::abort;
 use core::option::Option;
 use core::marker::Copy;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::clock::{ClockTree, notify_will_change, notify_changed};
+use hal::time::{Deadline, NoDeadline, Timeout};
 
 use self::MsiSpeed::*;
 use self::SystemClockSource::*;
@@ -164,6 +169,35 @@ impl default::Default for ClockConfig {
   }
 }
 
+static mut CurrentConfig: Option<ClockConfig> = Option::None;
+
+/// Returns the configuration last set up on the hardware, or the reset
+/// configuration if `ClockConfig::setup` wasn't called.
+pub fn current_config() -> ClockConfig {
+  match unsafe { CurrentConfig } {
+    Option::Some(config) => config,
+    Option::None => ClockConfig::new_default(),
+  }
+}
+
+/// Clock tree of STM32L1, as set up by the last `ClockConfig::setup`.
+#[derive(Clone, Copy)]
+pub struct Clocks;
+
+impl Clocks {
+  /// Returns the clock tree.
+  pub fn new() -> Clocks {
+    Clocks
+  }
+}
+
+impl ClockTree for Clocks {
+  fn core_frequency(&self) -> u32 { current_config().get_ahb_frequency() }
+  fn ahb_frequency(&self) -> u32 { current_config().get_ahb_frequency() }
+  fn apb1_frequency(&self) -> u32 { current_config().get_apb1_frequency() }
+  fn apb2_frequency(&self) -> u32 { current_config().get_apb2_frequency() }
+}
+
 impl ClockConfig {
   /// Return the default clock configuration that hardware go to after reset.
   pub fn new_default() -> ClockConfig {
@@ -171,30 +205,75 @@ impl ClockConfig {
   }
 
   /// Set this configuration on the hardware.
+  ///
+  /// Can be called again at runtime to switch clocks, clock listeners are
+  /// notified before and after the switch.
   pub fn setup(&self) {
+    match self.try_setup(&NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Set this configuration on the hardware, giving up if a clock doesn't
+  /// become ready before `deadline`.
+  ///
+  /// The system runs from MSI while the PLL is reconfigured. Flash wait
+  /// states are raised before and lowered after any frequency change.
+  /// Listeners are notified of the change even on failure, as the system may
+  /// be left running from MSI.
+  pub fn try_setup(&self, deadline: &Deadline) -> Result<(), Timeout> {
+    notify_will_change();
+    let result = self.switch(deadline);
+    match result {
+      Ok(()) => unsafe { CurrentConfig = Option::Some(*self) },
+      Err(_) => (),
+    }
+    notify_changed();
+    result
+  }
+
+  fn switch(&self, deadline: &Deadline) -> Result<(), Timeout> {
     let r = &reg::RCC;
 
+    // MSI is the reset clock, the wait states set for the current clock are
+    // enough for it.
+    r.cr.set_msi_on(true);
+    try!(try_wait_for!(r.cr.msi_ready(), deadline));
+    r.cfgr.set_system_clock(0);
+    try!(try_wait_for!(r.cfgr.system_clock_status() == 0, deadline));
+
+    // The PLL can't be configured while it's running.
+    r.cr.set_pll_on(false);
+    try!(try_wait_for!(!r.cr.pll_ready(), deadline));
+
     let source_type = match self.source {
       SystemClockMSI(msi) => {
-        r.cr.set_msi_on(true);
-        wait_for!(r.cr.msi_ready());
         r.icscr.set_msi_range(msi as u32);
         0
       },
       SystemClockHSI => {
         r.cr.set_hsi_on(true);
-        wait_for!(r.cr.hsi_ready());
+        try!(try_wait_for!(r.cr.hsi_ready(), deadline));
         1
       },
       SystemClockHSE(_) => {
         r.cr.set_hse_on(true);
-        wait_for!(r.cr.hse_ready());
+        try!(try_wait_for!(r.cr.hse_ready(), deadline));
         //TODO(kvark): HSE config
         2
       },
       SystemClockPLL(pll_source, mul, div) => {
-        r.cr.set_pll_on(true);
-        wait_for!(r.cr.pll_ready());
+        match pll_source {
+          PllClockSource::PllSourceHSI => {
+            r.cr.set_hsi_on(true);
+            try!(try_wait_for!(r.cr.hsi_ready(), deadline));
+          },
+          PllClockSource::PllSourceHSE => {
+            r.cr.set_hse_on(true);
+            try!(try_wait_for!(r.cr.hse_ready(), deadline));
+          },
+        }
         r.cfgr.set_pll_clock_source(pll_source as usize != 0);
         let factor = match mul {
           3 => 0,
@@ -210,13 +289,14 @@ impl ClockConfig {
         };
         r.cfgr.set_pll_mul_factor(factor);
         r.cfgr.set_pll_output_div(div as u32);
+        r.cr.set_pll_on(true);
+        try!(try_wait_for!(r.cr.pll_ready(), deadline));
         3
       }
     };
 
-    r.cfgr.set_system_clock(source_type);
-    wait_for!(r.cfgr.system_clock_status() == source_type);
-
+    // Prescalers are set while running from MSI, so that the buses never run
+    // faster than configured.
     if self.ahb_shift > 9 || self.apb1_shift > 4 || self.apb2_shift > 4 {
       unsafe { abort() } // not supported
     }
@@ -224,6 +304,17 @@ impl ClockConfig {
     r.cfgr.set_apb1_prescaler(self.apb1_shift as u32);
     r.cfgr.set_apb2_prescaler(self.apb2_shift as u32);
 
+    let wait_state = self.needs_wait_state();
+    if wait_state {
+      set_flash_wait_state(true);
+    }
+    r.cfgr.set_system_clock(source_type);
+    try!(try_wait_for!(r.cfgr.system_clock_status() == source_type,
+        deadline));
+    if !wait_state {
+      set_flash_wait_state(false);
+    }
+
     match self.mco {
       Option::Some(mco) => {
         if mco.clock_shift > 4 {
@@ -236,6 +327,21 @@ impl ClockConfig {
         r.cfgr.set_mco(0);
       },
     }
+    Ok(())
+  }
+
+  /// Returns true if the AHB clock of this configuration needs a flash wait
+  /// state, assuming voltage range 1. The frequency of a PLL fed by HSE isn't
+  /// known, so it always gets one.
+  fn needs_wait_state(&self) -> bool {
+    let sysclk = match self.source {
+      SystemClockHSE(freq) => freq,
+      SystemClockPLL(PllClockSource::PllSourceHSI, mul, div) =>
+        16_000_000 / div as u32 * mul as u32,
+      SystemClockPLL(PllClockSource::PllSourceHSE, _, _) => return true,
+      _ => self.source.frequency(),
+    };
+    sysclk >> self.ahb_shift as usize > 16_000_000
   }
 
   /// Returns AHB clock frequency
@@ -254,6 +360,21 @@ impl ClockConfig {
   }
 }
 
+/// Sets or clears the flash wait state. 64-bit access has to be enabled
+/// before a wait state is set, and can only be disabled without one.
+fn set_flash_wait_state(enable: bool) {
+  let acr = &reg::FLASH.acr;
+  if enable {
+    acr.set_access_64bit(true);
+    acr.set_latency(true);
+    acr.set_prefetch(true);
+  } else {
+    acr.set_prefetch(false);
+    acr.set_latency(false);
+    acr.set_access_64bit(false);
+  }
+}
+
 // TODO(farcaller): this mod is pub as it's being used in peripheral_clock.rs.
 //                  This is not the best design solution and a good reason to
 //                  split RCC into distinct registers.
@@ -349,7 +470,11 @@ pub mod reg {
 
   ioregs!(FLASH = {
     0x00 => reg32 acr {     // access control
-      31..0 => access_control : rw,
+      0 => latency : rw,
+      1 => prefetch : rw,
+      2 => access_64bit : rw,
+      3 => sleep_power_down : rw,
+      4 => run_power_down : rw,
     },
     0x04 => reg32 pecr {    // program/erase control
       31..0 => program_control : rw,
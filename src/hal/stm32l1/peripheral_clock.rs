@@ -19,8 +19,9 @@
 //!
 //! Note: this module is used as part of initial setup if PLL is used.
 
-use super::init::{ClockConfig, reg};
+use super::init::{ClockConfig, current_config, reg};
 use core::marker::Copy;
+use hal::clock::PeripheralFrequency;
 
 pub use self::PeripheralClock::*;
 
@@ -226,3 +227,9 @@ impl PeripheralClock {
     }
   }
 }
+
+impl PeripheralFrequency for PeripheralClock {
+  fn frequency(&self) -> u32 {
+    PeripheralClock::frequency(*self, &current_config())
+  }
+}
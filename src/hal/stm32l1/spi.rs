@@ -19,6 +19,10 @@ use core::result::Result;
 use core::result::Result::{Ok, Err};
 use core::marker::Copy;
 
+use hal::clock::{ClockListener, DRAIN_SPINS, PeripheralFrequency};
+use hal::stm32l1::peripheral_clock::PeripheralClock;
+use hal::time::{Deadline, SpinDeadline, Timeout};
+
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
 
@@ -104,6 +108,9 @@ pub enum Error {
 #[derive(Clone, Copy)]
 pub struct Spi {
   reg: &'static reg::SPI,
+  clock: PeripheralClock,
+  /// Bit rate set up by `new`, kept across clock changes.
+  frequency: u32,
 }
 
 impl Spi {
@@ -157,6 +164,8 @@ impl Spi {
       reg.cr1.set_spi_enable(true);
       Ok(Spi {
         reg: reg,
+        clock: clock,
+        frequency: PeripheralFrequency::frequency(&clock) >> prescaler_shift,
       })
     }
   }
@@ -193,6 +202,25 @@ impl Spi {
   }
 }
 
+impl ClockListener for Spi {
+  /// Lets the current transfer complete.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(!self.reg.sr.busy_flag(), deadline);
+  }
+
+  /// Picks the prescaler giving the fastest bit rate not above the one set up
+  /// by `new`.
+  fn clock_changed(&self) {
+    let bus_clock = PeripheralFrequency::frequency(&self.clock);
+    let mut shift = 1;
+    while shift < 8 && bus_clock >> shift > self.frequency {
+      shift += 1;
+    }
+    self.reg.cr1.set_baud_rate(shift - 1);
+  }
+}
+
 impl ::hal::spi::Spi for Spi {
   fn write(&self, value: u8) {
     wait_for!(self.reg.sr.transmit_buffer_empty());
@@ -205,6 +233,19 @@ impl ::hal::spi::Spi for Spi {
   }
 }
 
+impl ::hal::spi::TrySpi for Spi {
+  fn try_write(&self, value: u8, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.reg.sr.transmit_buffer_empty(), deadline));
+    self.reg.dr.set_data(value as u16);
+    Ok(())
+  }
+
+  fn try_read(&self, deadline: &Deadline) -> Result<u8, Timeout> {
+    try!(try_wait_for!(self.reg.sr.receive_buffer_not_empty(), deadline));
+    Ok(self.reg.dr.data() as u8)
+  }
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
@@ -20,11 +20,15 @@ Universal synchronous asynchronous receiver transmitter (USART).
 
 use core::fmt;
 use core::result::Result;
+use core::result::Result::{Ok, Err};
 use core::intrinsics::abort;
 
 use drivers::chario::CharIO;
+use hal::clock::{ClockListener, DRAIN_SPINS, PeripheralFrequency};
+use hal::time::{Deadline, SpinDeadline, Timeout};
 use hal::uart;
 use hal::stm32l1::init;
+use hal::stm32l1::peripheral_clock::PeripheralClock;
 
 use self::UsartPeripheral::*;
 
@@ -70,6 +74,8 @@ pub enum StopBit {
 #[derive(Clone, Copy)]
 pub struct Usart {
   reg: &'static reg::USART,
+  clock: PeripheralClock,
+  baudrate: u32,
 }
 
 impl Usart {
@@ -77,7 +83,6 @@ impl Usart {
   pub fn new(peripheral: UsartPeripheral, baudrate: u32, word_len: WordLen,
              parity: uart::Parity, stop_bits: StopBit,
              config: &init::ClockConfig) -> Usart {
-    use hal::stm32l1::peripheral_clock::PeripheralClock;
     use hal::stm32l1::peripheral_clock as clock;
     use hal::uart::Parity::*;
 
@@ -94,14 +99,12 @@ impl Usart {
     reg.cr1.set_word_length(word_len as usize != 0);
     reg.cr2.set_stop_bits(stop_bits as u16);
 
-    // Standard USART baud rate:
-    // Tx/Rx baud = Fck / (8 * (2 - OVER8) * USARTDIV)
-
-    let bus_clock = clock.frequency(config);
-    let over8 = reg.cr1.oversample_8bit_enable() as usize;
-    let idiv = (bus_clock << 4) / (baudrate << (2 - over8));
-    reg.brr.set_fraction(((idiv & 0xF) >> over8) as u16);
-    reg.brr.set_mantissa((idiv >> 4) as u16);
+    let usart = Usart {
+      reg: reg,
+      clock: clock,
+      baudrate: baudrate,
+    };
+    usart.set_baud_rate(clock.frequency(config));
 
     let (pe_on, pe_select) = match parity {
         Disabled => (false, false),
@@ -118,9 +121,29 @@ impl Usart {
 
     reg.cr1.set_usart_enable(true);
 
-    Usart {
-      reg: reg,
-    }
+    usart
+  }
+
+  fn set_baud_rate(&self, bus_clock: u32) {
+    // Standard USART baud rate:
+    // Tx/Rx baud = Fck / (8 * (2 - OVER8) * USARTDIV)
+    let over8 = self.reg.cr1.oversample_8bit_enable() as usize;
+    let idiv = (bus_clock << 4) / (self.baudrate << (2 - over8));
+    self.reg.brr.set_fraction(((idiv & 0xF) >> over8) as u16);
+    self.reg.brr.set_mantissa((idiv >> 4) as u16);
+  }
+}
+
+impl ClockListener for Usart {
+  /// Lets the transmitter drain, so no character goes out at a wrong rate.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(self.reg.sr.transmission_complete(), deadline);
+  }
+
+  /// Recomputes the divisor for the configured baud rate.
+  fn clock_changed(&self) {
+    self.set_baud_rate(PeripheralFrequency::frequency(&self.clock));
   }
 }
 
@@ -131,6 +154,14 @@ impl CharIO for Usart {
   }
 }
 
+impl uart::TryCharIO for Usart {
+  fn try_putc(&self, value: char, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.reg.sr.transmit_data_empty(), deadline));
+    self.reg.dr.set_data(value as u16);
+    Ok(())
+  }
+}
+
 impl fmt::Write for Usart {
   fn write_str(&mut self, s: &str) -> fmt::Result {
     use core::str::StrExt;
@@ -0,0 +1,277 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+System tick and uptime.
+
+SysTick is set up to interrupt at a fixed rate derived from the core clock,
+and the interrupt handler counts ticks in a 64-bit counter, which doesn't wrap
+for the lifetime of the device. Uptime is computed from the tick count and the
+current SysTick value, so it has a resolution of a core clock cycle.
+
+`SysTick` implements `hal::timer::Timer`, which gives every Cortex-M target
+portable delays without using a hardware timer. Tick hooks are called from the
+SysTick interrupt after each tick; they should be short.
+
+The application's `isr_systick` has to call `tick`. The `systick_isr` feature
+defines an `isr_systick` that does only that, for applications that don't need
+their own handler. With the `multitasking` feature, the scheduler owns the
+SysTick interrupt and calls `tick` itself.
+
+To save power while nothing needs to run, `sleep_for` suppresses SysTick
+interrupts for a number of ticks and counts the ticks that elapsed on wake up,
+keeping the phase of the tick. `suspend` and `resume` stop SysTick altogether,
+for a sleep mode that stops the core clock; the time slept has to be measured
+by another timer then.
+*/
+
+use core::intrinsics::{abort, volatile_load};
+use core::option::Option::{self, Some, None};
+
+use hal::cortex_common::{scb, systick};
+use hal::time::Instant;
+use hal::timer::Timer;
+
+/// Maximum number of tick hooks.
+pub const MAX_HOOKS: usize = 4;
+
+static mut Ticks: u64 = 0;
+static mut Rate: u32 = 0;
+static mut CoreFrequency: u32 = 0;
+static mut Running: bool = false;
+static mut Hooks: [Option<fn(u64)>; MAX_HOOKS] = [None; MAX_HOOKS];
+
+/// System tick configuration.
+#[derive(Clone, Copy)]
+pub struct TickConf {
+  /// Core clock frequency in Hz.
+  pub core_frequency: u32,
+  /// Tick rate in Hz; 1000 gives a tick every millisecond. The tick period is
+  /// rounded to a whole number of core clock cycles.
+  pub rate: u32,
+}
+
+/// SysTick-based timer, counting microseconds of uptime.
+#[derive(Clone, Copy)]
+pub struct SysTick;
+
+impl TickConf {
+  /// Configures and starts SysTick.
+  pub fn setup(&self) -> SysTick {
+    unsafe { Rate = self.rate };
+    set_core_frequency(self.core_frequency);
+    systick::enable_irq();
+    systick::enable();
+    unsafe { Running = true };
+    SysTick
+  }
+}
+
+/// Updates the SysTick reload value for a new core clock frequency.
+///
+/// Call when the core clock changes; the tick in progress is restarted.
+pub fn set_core_frequency(frequency: u32) {
+  let rate = unsafe { Rate };
+  if rate == 0 || frequency / rate == 0 || frequency / rate > 0x100_0000 {
+    unsafe { abort() };
+  }
+  unsafe { CoreFrequency = frequency };
+
+  systick::setup(frequency / rate - 1);
+  if unsafe { Running } {
+    systick::enable_irq();
+    systick::enable();
+  }
+}
+
+/// Adds a function called with the tick count on every tick.
+///
+/// Returns false if all `MAX_HOOKS` slots are taken.
+pub fn add_hook(hook: fn(u64)) -> bool {
+  unsafe {
+    for slot in Hooks.iter_mut() {
+      if slot.is_none() {
+        *slot = Some(hook);
+        return true;
+      }
+    }
+  }
+  false
+}
+
+/// Counts a tick and runs the hooks.
+///
+/// Called from the SysTick interrupt.
+pub fn tick() {
+  let ticks = unsafe {
+    Ticks += 1;
+    Ticks
+  };
+  for hook in unsafe { Hooks.iter() } {
+    match *hook {
+      Some(f) => f(ticks),
+      None => (),
+    }
+  }
+}
+
+#[cfg(all(feature = "systick_isr", not(feature = "multitasking")))]
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn isr_systick() {
+  tick();
+}
+
+/// Counts `ticks` ticks at once, running the hooks a single time.
+fn step(ticks: u32) {
+  if ticks == 0 {
+    return;
+  }
+  unsafe { Ticks += ticks as u64 - 1 };
+  tick();
+}
+
+/// Returns the most ticks `sleep_for` can sleep in one go.
+pub fn max_sleep_ticks() -> u32 {
+  0x100_0000 / (systick::get_reload() + 1)
+}
+
+/// Sleeps with `sleep` (e.g. `wfi`) for at most `ticks` ticks, with SysTick
+/// interrupts suppressed until the last of them, and counts the ticks that
+/// elapsed when woken up.
+///
+/// Interrupts must be disabled; an interrupt still ends the sleep, and its
+/// handler runs once they are enabled again. Returns the number of ticks
+/// counted; the tick ending the sleep, if it was reached, is left to the
+/// SysTick interrupt.
+pub fn sleep_for(ticks: u32, sleep: fn()) -> u32 {
+  let max = max_sleep_ticks();
+  let ticks = if ticks > max { max } else { ticks };
+  if ticks < 2 {
+    sleep();
+    return 0;
+  }
+
+  let period = systick::get_reload() + 1;
+  systick::disable();
+  // Cycles left in the current tick; a wrap just now is left to the
+  // interrupt.
+  let current = systick::get_current();
+  if scb::is_systick_pending() || current == 0 {
+    systick::enable();
+    return 0;
+  }
+
+  // Ticks end when the counter reaches 0, period cycles apart.
+  let reload = current + period * (ticks - 1);
+  systick::set_reload(reload);
+  systick::clear_current();
+  systick::enable();
+  systick::set_reload(period - 1);
+
+  sleep();
+
+  // Reading COUNTFLAG clears it, and so does disabling the counter, which
+  // reads and writes CSR; check it first.
+  let counted = if systick::tick() {
+    // Slept through: the counter reloaded with the regular period, so it's
+    // still in phase, and the interrupt counts the last tick.
+    ticks - 1
+  } else {
+    // Woken up early, with `left` cycles to the last tick boundary. If that
+    // boundary passes before the counter stops, `left` is nearly a period and
+    // the pending interrupt counts the last tick.
+    systick::disable();
+    let left = systick::get_current();
+    let ahead = if left == 0 { 1 } else { (left - 1) / period + 1 };
+    let next = left - (ahead - 1) * period;
+    // A reload value of 0 would stop the interrupt.
+    systick::set_reload(if next < 2 { 1 } else { next - 1 });
+    systick::clear_current();
+    systick::enable();
+    systick::set_reload(period - 1);
+    ticks - ahead
+  };
+
+  step(counted);
+  counted
+}
+
+/// Stops SysTick, for a sleep it can't time.
+pub fn suspend() {
+  systick::disable();
+}
+
+/// Restarts SysTick after `suspend`, counting the `ticks` slept meanwhile.
+pub fn resume(ticks: u32) {
+  systick::clear_current();
+  systick::enable();
+  step(ticks);
+}
+
+/// Returns the tick rate in Hz.
+pub fn rate() -> u32 {
+  unsafe { Rate }
+}
+
+/// Returns the number of ticks since `setup`.
+pub fn ticks() -> u64 {
+  // The interrupt may update the counter between the reads of its halves.
+  // The reads are volatile so that they aren't merged into one.
+  loop {
+    let first = unsafe { volatile_load(&Ticks) };
+    let second = unsafe { volatile_load(&Ticks) };
+    if first == second {
+      return first;
+    }
+  }
+}
+
+/// Returns the uptime in microseconds.
+pub fn uptime_us() -> u64 {
+  loop {
+    let start = ticks();
+    let mut ticks = start;
+    let mut current = systick::get_current();
+    // The counter wrapped, but the interrupt couldn't run yet.
+    if scb::is_systick_pending() {
+      ticks += 1;
+      current = systick::get_current();
+    }
+    // Retry if the interrupt ran in the meantime.
+    if self::ticks() == start {
+      let cycles = (systick::get_reload() - current) as u64;
+      let (rate, frequency) = unsafe { (Rate as u64, CoreFrequency as u64) };
+      return ticks * 1_000_000 / rate + cycles * 1_000_000 / frequency;
+    }
+  }
+}
+
+/// Returns the current time, measured from `setup`.
+pub fn now() -> Instant {
+  Instant::from_us(uptime_us())
+}
+
+/// Returns the uptime in milliseconds.
+pub fn uptime_ms() -> u64 {
+  uptime_us() / 1000
+}
+
+impl Timer for SysTick {
+  /// Returns the low 32 bits of the uptime in microseconds.
+  fn get_counter(&self) -> u32 {
+    uptime_us() as u32
+  }
+}
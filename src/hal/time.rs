@@ -0,0 +1,356 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Monotonic time.
+
+`Duration` and `Instant` are 64-bit microsecond counts, so they don't wrap for
+the lifetime of the device, and their arithmetic saturates instead of
+overflowing. `Monotonic` extends the 32-bit counter of a `Timer` to 64 bits and
+converts between timer ticks and real time.
+
+Blocking HAL operations take a `Deadline` in their `try_` variants and return
+`Err(Timeout)` once it expires. `SpinDeadline` doesn't need a timer, so it can
+bound waits that happen before any timer runs, like clock setup.
+*/
+
+use core::cell::Cell;
+use core::cmp::{PartialOrd, Ord, Ordering};
+use core::ops::{Add, Sub};
+use core::option::Option::{self, Some};
+
+use hal::timer::Timer;
+
+/// Span of time, with microsecond resolution.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Duration {
+  us: u64,
+}
+
+impl Duration {
+  /// Returns a duration of `us` microseconds.
+  pub fn from_us(us: u64) -> Duration {
+    Duration { us: us }
+  }
+
+  /// Returns a duration of `ms` milliseconds.
+  pub fn from_ms(ms: u64) -> Duration {
+    Duration { us: ms.saturating_mul(1000) }
+  }
+
+  /// Returns a duration of `s` seconds.
+  pub fn from_secs(s: u64) -> Duration {
+    Duration { us: s.saturating_mul(1_000_000) }
+  }
+
+  /// Returns the duration in microseconds.
+  pub fn as_us(&self) -> u64 {
+    self.us
+  }
+
+  /// Returns the duration in whole milliseconds.
+  pub fn as_ms(&self) -> u64 {
+    self.us / 1000
+  }
+
+  /// Returns the duration in whole seconds.
+  pub fn as_secs(&self) -> u64 {
+    self.us / 1_000_000
+  }
+
+  /// Returns the longest duration.
+  pub fn max_value() -> Duration {
+    Duration { us: 0xffff_ffff_ffff_ffff }
+  }
+}
+
+impl Add for Duration {
+  type Output = Duration;
+  fn add(self, other: Duration) -> Duration {
+    Duration { us: self.us.saturating_add(other.us) }
+  }
+}
+
+/// Saturates at zero.
+impl Sub for Duration {
+  type Output = Duration;
+  fn sub(self, other: Duration) -> Duration {
+    Duration { us: self.us.saturating_sub(other.us) }
+  }
+}
+
+/// Point in time, in microseconds since an arbitrary start.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Instant {
+  us: u64,
+}
+
+impl Instant {
+  /// Returns the instant `us` microseconds after the start.
+  pub fn from_us(us: u64) -> Instant {
+    Instant { us: us }
+  }
+
+  /// Returns the microseconds since the start.
+  pub fn as_us(&self) -> u64 {
+    self.us
+  }
+
+  /// Returns the time from `earlier` to this instant, or zero if `earlier` is
+  /// later.
+  pub fn duration_since(&self, earlier: Instant) -> Duration {
+    Duration { us: self.us.saturating_sub(earlier.us) }
+  }
+}
+
+impl PartialOrd for Instant {
+  fn partial_cmp(&self, other: &Instant) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Instant {
+  fn cmp(&self, other: &Instant) -> Ordering {
+    self.us.cmp(&other.us)
+  }
+}
+
+impl Add<Duration> for Instant {
+  type Output = Instant;
+  fn add(self, d: Duration) -> Instant {
+    Instant { us: self.us.saturating_add(d.us) }
+  }
+}
+
+impl Sub<Duration> for Instant {
+  type Output = Instant;
+  fn sub(self, d: Duration) -> Instant {
+    Instant { us: self.us.saturating_sub(d.us) }
+  }
+}
+
+/// Error returned when a deadline passes before the awaited condition.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Timeout;
+
+/// Bound on a blocking wait.
+pub trait Deadline {
+  /// Returns true once the deadline has passed.
+  fn is_expired(&self) -> bool;
+}
+
+/// Deadline that never expires.
+#[derive(Clone, Copy)]
+pub struct NoDeadline;
+
+impl Deadline for NoDeadline {
+  fn is_expired(&self) -> bool { false }
+}
+
+/// Deadline expiring after being checked a number of times.
+///
+/// The time this represents depends on the core clock and the loop it's used
+/// in, so it's only meant as a safety net against waiting forever.
+pub struct SpinDeadline {
+  left: Cell<u32>,
+}
+
+impl SpinDeadline {
+  /// Creates a deadline expiring on the `checks`-th check.
+  pub fn new(checks: u32) -> SpinDeadline {
+    SpinDeadline { left: Cell::new(checks) }
+  }
+}
+
+impl Deadline for SpinDeadline {
+  fn is_expired(&self) -> bool {
+    match self.left.get() {
+      0 => true,
+      left => {
+        self.left.set(left - 1);
+        false
+      },
+    }
+  }
+}
+
+/// Deadline a number of microseconds after its creation.
+pub struct TimerDeadline<'a, T: 'a> {
+  timer: &'a T,
+  start: u32,
+  us: u32,
+}
+
+impl<'a, T: Timer> TimerDeadline<'a, T> {
+  /// Creates a deadline `us` microseconds from now, as counted by `timer`.
+  pub fn new(timer: &'a T, us: u32) -> TimerDeadline<'a, T> {
+    TimerDeadline {
+      timer: timer,
+      start: timer.get_counter(),
+      us: us,
+    }
+  }
+}
+
+impl<'a, T: Timer> Deadline for TimerDeadline<'a, T> {
+  fn is_expired(&self) -> bool {
+    self.timer.get_counter().wrapping_sub(self.start) >= self.us
+  }
+}
+
+/// 64-bit monotonic clock on top of a `Timer`.
+///
+/// The timer counter is extended by counting its wraps, which are detected on
+/// reads, so `now` must be called at least once per wrap period (71 minutes
+/// for a 1MHz counter).
+pub struct Monotonic<'a, T: 'a> {
+  timer: &'a T,
+  rate: u32,
+  last: Cell<u32>,
+  high: Cell<u32>,
+}
+
+impl<'a, T: Timer> Monotonic<'a, T> {
+  /// Creates a clock on `timer`, which counts at `rate` Hz.
+  pub fn new(timer: &'a T, rate: u32) -> Monotonic<'a, T> {
+    Monotonic {
+      timer: timer,
+      rate: rate,
+      last: Cell::new(timer.get_counter()),
+      high: Cell::new(0),
+    }
+  }
+
+  /// Returns the 64-bit tick count.
+  pub fn ticks(&self) -> u64 {
+    let counter = self.timer.get_counter();
+    if counter < self.last.get() {
+      self.high.set(self.high.get() + 1);
+    }
+    self.last.set(counter);
+    (self.high.get() as u64) << 32 | counter as u64
+  }
+
+  /// Converts timer ticks to time.
+  pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+    // Split to avoid overflowing the intermediate product.
+    let rate = self.rate as u64;
+    Duration::from_us((ticks / rate).saturating_mul(1_000_000) +
+        (ticks % rate) * 1_000_000 / rate)
+  }
+
+  /// Converts time to timer ticks, rounding up.
+  pub fn duration_to_ticks(&self, d: Duration) -> u64 {
+    let rate = self.rate as u64;
+    let us = d.as_us();
+    (us / 1_000_000).saturating_mul(rate) +
+        ((us % 1_000_000) * rate + 999_999) / 1_000_000
+  }
+
+  /// Returns the current time.
+  pub fn now(&self) -> Instant {
+    Instant::from_us(self.ticks_to_duration(self.ticks()).as_us())
+  }
+
+  /// Returns the time since `since`.
+  pub fn elapsed(&self, since: Instant) -> Duration {
+    self.now().duration_since(since)
+  }
+
+  /// Returns the instant `d` from now.
+  pub fn deadline(&self, d: Duration) -> Instant {
+    self.now() + d
+  }
+
+  /// Returns true once `deadline` has passed.
+  pub fn is_expired(&self, deadline: Instant) -> bool {
+    self.now() >= deadline
+  }
+
+  /// Waits until `deadline`.
+  pub fn wait_until(&self, deadline: Instant) {
+    while !self.is_expired(deadline) {}
+  }
+
+  /// Waits for `d`.
+  pub fn wait(&self, d: Duration) {
+    let deadline = self.deadline(d);
+    self.wait_until(deadline);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::cell::Cell;
+
+  use hal::timer::Timer;
+  use super::{Duration, Instant, Monotonic, Deadline, SpinDeadline, TimerDeadline};
+
+  struct SetTimer {
+    counter: Cell<u32>,
+  }
+
+  impl Timer for SetTimer {
+    fn get_counter(&self) -> u32 {
+      self.counter.get()
+    }
+  }
+
+  #[test]
+  fn saturates() {
+    let max = Duration::max_value();
+    assert!(max + Duration::from_us(1) == max);
+    assert!(Duration::from_ms(1) - Duration::from_ms(2) == Duration::from_us(0));
+    assert!(Instant::from_us(5).duration_since(Instant::from_us(10)).as_us() == 0);
+    assert!(Duration::from_secs(0xffff_ffff_ffff) == max);
+  }
+
+  #[test]
+  fn extends_counter_over_wraps() {
+    let timer = SetTimer { counter: Cell::new(0xffff_ff00) };
+    let clock = Monotonic::new(&timer, 1_000_000);
+    let start = clock.now();
+
+    timer.counter.set(0x100);
+    assert!(clock.ticks() == 0x1_0000_0100);
+    timer.counter.set(0x200);
+    assert!(clock.elapsed(start).as_us() == 0x1_0000_0200 - 0xffff_ff00);
+  }
+
+  #[test]
+  fn converts_ticks() {
+    let timer = SetTimer { counter: Cell::new(0) };
+    let clock = Monotonic::new(&timer, 32_768);
+    assert!(clock.ticks_to_duration(32_768 * 3).as_secs() == 3);
+    assert!(clock.duration_to_ticks(Duration::from_secs(2)) == 65_536);
+    // A single microsecond still takes a tick.
+    assert!(clock.duration_to_ticks(Duration::from_us(1)) == 1);
+  }
+
+  #[test]
+  fn expires_deadlines() {
+    let spins = SpinDeadline::new(2);
+    assert!(!spins.is_expired());
+    assert!(!spins.is_expired());
+    assert!(spins.is_expired());
+
+    let timer = SetTimer { counter: Cell::new(0xffff_fff0) };
+    let deadline = TimerDeadline::new(&timer, 0x20);
+    timer.counter.set(0x0f);
+    assert!(!deadline.is_expired());
+    timer.counter.set(0x10);
+    assert!(deadline.is_expired());
+  }
+}
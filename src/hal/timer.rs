@@ -33,18 +33,22 @@ pub trait Timer {
   /// Waits for specified number of microseconds.
   fn wait_us(&self, us: u32) {
     let start = self.get_counter();
-    wait_for!((self.get_counter() - start) >= us);
+    wait_for!(self.get_counter().wrapping_sub(start) >= us);
   }
 
   #[inline(always)]
   /// Waits for specified number of milliseconds.
   fn wait_ms(&self, ms: u32) {
-    self.wait_us(ms * 1000);
+    // Waits in whole seconds first, as `ms * 1000` may not fit in u32.
+    self.wait(ms / 1000);
+    self.wait_us((ms % 1000) * 1000);
   }
 
   #[inline(always)]
   /// Waits for specified number of seconds.
   fn wait(&self, s: u32) {
-    self.wait_us(s * 1000000);
+    for _ in 0..s {
+      self.wait_us(1_000_000);
+    }
   }
 }
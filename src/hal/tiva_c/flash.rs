@@ -0,0 +1,194 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Flash memory controller (FMC)
+
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::flash;
+use hal::flash::Error;
+use hal::time::{Deadline, NoDeadline};
+use util::support::get_reg_ref;
+
+#[path="../../util/wait_for.rs"]
+#[macro_use] mod wait_for;
+
+const FLASH_END: u32 = 0x0004_0000;
+const PAGE_SIZE: u32 = 0x400;
+
+fn fmc_get() -> &'static reg::Fmc {
+  get_reg_ref(reg::FMC)
+}
+
+fn prot_get() -> &'static reg::FlashProt {
+  get_reg_ref(reg::FLASH_PROT)
+}
+
+/// Flash controller.
+#[derive(Clone, Copy)]
+pub struct Flash;
+
+impl Flash {
+  /// Returns the flash controller.
+  pub fn new() -> Flash {
+    Flash
+  }
+
+  /// The write key depends on the BOOTCFG KEY bit.
+  fn write_key(&self) -> u32 {
+    if prot_get().bootcfg.key() { 0xA442 } else { 0x71D5 }
+  }
+
+  /// Checks and clears the error status of the last operation.
+  fn status(&self) -> Result<(), Error> {
+    let fmc = fmc_get();
+    let ris = fmc.fcris.get();
+    let result = if ris.aris() {
+      Err(Error::Protected)
+    } else if ris.invdris() {
+      Err(Error::InvalidAddress)
+    } else if ris.errris() || ris.progris() || ris.voltris() {
+      Err(Error::Failed)
+    } else {
+      Ok(())
+    };
+
+    fmc.fcmisc
+      .set_amisc(true)
+      .set_voltmisc(true)
+      .set_invdmisc(true)
+      .set_errmisc(true)
+      .set_progmisc(true);
+    result
+  }
+}
+
+impl flash::Flash for Flash {
+  fn sector_size(&self, _: u32) -> u32 {
+    PAGE_SIZE
+  }
+
+  fn erase_sector(&self, addr: u32) -> Result<(), Error> {
+    flash::TryFlash::try_erase_sector(self, addr, &NoDeadline)
+  }
+
+  fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+    flash::TryFlash::try_program(self, addr, data, &NoDeadline)
+  }
+
+  fn is_read_protected(&self) -> bool {
+    let prot = prot_get();
+    (0..4).any(|i| prot.fmpre[i].read_enable() != 0xffff_ffff)
+  }
+}
+
+impl flash::TryFlash for Flash {
+  fn try_erase_sector(&self, addr: u32, deadline: &Deadline)
+      -> Result<(), Error> {
+    if addr >= FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+    let fmc = fmc_get();
+
+    fmc.fma.set_offset(addr & !(PAGE_SIZE - 1));
+    fmc.fmc.ignoring_state()
+      .set_wrkey(self.write_key())
+      .set_erase(true);
+    match try_wait_for!(!fmc.fmc.erase(), deadline) {
+      Ok(()) => (),
+      Err(_) => return Err(Error::Timeout),
+    }
+
+    self.status()
+  }
+
+  fn try_program(&self, addr: u32, data: &[u32], deadline: &Deadline)
+      -> Result<(), Error> {
+    let end = addr + data.len() as u32 * 4;
+    if addr & 3 != 0 || end > FLASH_END {
+      return Err(Error::InvalidAddress);
+    }
+    let fmc = fmc_get();
+    let key = self.write_key();
+
+    for i in 0..data.len() {
+      fmc.fma.set_offset(addr + i as u32 * 4);
+      fmc.fmd.set_data(data[i]);
+      fmc.fmc.ignoring_state()
+        .set_wrkey(key)
+        .set_write(true);
+      match try_wait_for!(!fmc.fmc.write(), deadline) {
+        Ok(()) => (),
+        Err(_) => return Err(Error::Timeout),
+      }
+
+      try!(self.status());
+    }
+    Ok(())
+  }
+}
+
+pub mod reg {
+  //! Flash controller registers definition
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(Fmc = {
+    0x000 => reg32 fma {
+      0..17 => offset,       //= Address offset
+    }
+    0x004 => reg32 fmd {
+      0..31 => data,         //= Data value for write operation
+    }
+    0x008 => reg32 fmc {
+      0      => write,       //= Write a word into flash memory
+      1      => erase,       //= Erase a page of flash memory
+      2      => merase,      //= Mass erase flash memory
+      3      => comt,        //= Commit register value
+      16..31 => wrkey: wo,   //= Flash memory write key
+    }
+    0x00c => reg32 fcris {
+      0  => aris: ro,        //= Access raw interrupt status
+      1  => pris: ro,        //= Programming raw interrupt status
+      9  => voltris: ro,     //= Pump voltage raw interrupt status
+      10 => invdris: ro,     //= Invalid data raw interrupt status
+      11 => errris: ro,      //= Erase verify error raw interrupt status
+      13 => progris: ro,     //= Program verify error raw interrupt status
+    }
+    0x010 => reg32 fcmisc {
+      0  => amisc,           //= Access masked interrupt status and clear
+      1  => pmisc,           //= Programming masked interrupt status and clear
+      9  => voltmisc,        //= Pump voltage masked interrupt status and clear
+      10 => invdmisc,        //= Invalid data masked interrupt status and clear
+      11 => errmisc,         //= Erase verify error masked interrupt status and clear
+      13 => progmisc,        //= Program verify error masked interrupt status and clear
+    }
+  });
+
+  ioregs!(FlashProt = {
+    0x000 => reg32 bootcfg {
+      4 => key: ro,          //= Flash write key selection
+    }
+    0x030 => reg32 fmpre[4] {
+      0..31 => read_enable,  //= Flash memory read enable, one bit per 2kB
+    }
+  });
+
+  #[allow(missing_docs)]
+  pub const FMC: *const Fmc = 0x400FD000 as *const Fmc;
+  #[allow(missing_docs)]
+  pub const FLASH_PROT: *const FlashProt = 0x400FE1D0 as *const FlashProt;
+}
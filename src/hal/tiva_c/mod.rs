@@ -17,6 +17,7 @@
 /// This MCU is used on the TI stellaris and Tiva C launchpad development boards.
 
 pub mod sysctl;
+pub mod flash;
 pub mod pin;
 pub mod timer;
 pub mod uart;
@@ -19,7 +19,11 @@
 /// modules in TM4C microcontrollers
 
 use core::intrinsics::abort;
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+use hal::clock::{ClockListener, DRAIN_SPINS};
 use hal::tiva_c::sysctl;
+use hal::time::{Deadline, SpinDeadline, Timeout};
 use util::support::get_reg_ref;
 
 #[path="../../util/ioreg.rs"]
@@ -144,6 +148,8 @@ fn run() {
 pub struct Spi {
   /// SSI registers
   regs: &'static reg::Ssi,
+  /// Configured bus frequency, kept across clock changes
+  frequency: u32,
 }
 
 impl Spi {
@@ -157,7 +163,10 @@ impl Spi {
       SpiId::Spi3 => (sysctl::periph::ssi::SSI_3, reg::SSI_3),
     };
 
-    let spi = Spi { regs: get_reg_ref(regs) };
+    let spi = Spi {
+      regs: get_reg_ref(regs),
+      frequency: config.frequency,
+    };
 
     // Make sure peripheral clock gating is enabled
     periph.ensure_enabled();
@@ -240,6 +249,21 @@ impl Spi {
   }
 }
 
+impl ClockListener for Spi {
+  /// Lets the current transfer complete.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(!self.regs.ssisr.bsy(), deadline);
+  }
+
+  /// Recomputes the clock prescaler for the configured bus frequency.
+  fn clock_changed(&self) {
+    self.regs.ssicr1.set_sse(false);
+    self.set_frequency(self.frequency);
+    self.regs.ssicr1.set_sse(true);
+  }
+}
+
 impl ::hal::spi::Spi for Spi {
   fn write(&self, value: u8) {
     wait_for!(self.writeable());
@@ -254,6 +278,19 @@ impl ::hal::spi::Spi for Spi {
   }
 }
 
+impl ::hal::spi::TrySpi for Spi {
+  fn try_write(&self, value: u8, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(self.writeable(), deadline));
+    self.regs.ssidr.set_data(value as u16);
+    Ok(())
+  }
+
+  fn try_read(&self, deadline: &Deadline) -> Result<u8, Timeout> {
+    try!(try_wait_for!(self.readable(), deadline));
+    Ok(self.regs.ssidr.data() as u8)
+  }
+}
+
 #[allow(missing_docs)]
 pub mod reg {
   //! SSI registers definition
@@ -27,8 +27,14 @@ fn sysctl_get() -> &'static reg::SysCtl {
 
 pub mod clock {
   //! Clock tree configuration
+  use core::intrinsics::abort;
   use core::option::Option;
   use core::option::Option::{Some, None};
+  use core::result::Result;
+  use core::result::Result::{Ok, Err};
+
+  use hal::clock::{ClockTree, notify_will_change, notify_changed};
+  use hal::time::{Deadline, NoDeadline, Timeout};
 
   /// Clock sources available on the system. The values are the RCC/RCC2 OSCSRC
   /// field encoding.
@@ -101,10 +107,40 @@ pub mod clock {
   }
 
   /// Configure the System Clock by setting the clock source and divisors.
+  ///
+  /// Can be called again at runtime to switch clocks, clock listeners are
+  /// notified before and after the switch.
   pub fn sysclk_configure(source:      ClockSource,
                           mosc_source: Option<MOSCFreq>,
                           use_pll:     bool,
                           div:         Option<usize>) {
+    match try_sysclk_configure(source, mosc_source, use_pll, div, &NoDeadline) {
+      Ok(()) => (),
+      Err(_) => unsafe { abort() },
+    }
+  }
+
+  /// Configure the System Clock, giving up if the Main Oscillator or the PLL
+  /// doesn't lock before `deadline`.
+  ///
+  /// Listeners are notified of the change even on failure, as the system may
+  /// be left running with the PLL bypassed.
+  pub fn try_sysclk_configure(source:      ClockSource,
+                              mosc_source: Option<MOSCFreq>,
+                              use_pll:     bool,
+                              div:         Option<usize>,
+                              deadline:    &Deadline) -> Result<(), Timeout> {
+    notify_will_change();
+    let result = switch(source, mosc_source, use_pll, div, deadline);
+    notify_changed();
+    result
+  }
+
+  fn switch(source:      ClockSource,
+            mosc_source: Option<MOSCFreq>,
+            use_pll:     bool,
+            div:         Option<usize>,
+            deadline:    &Deadline) -> Result<(), Timeout> {
 
     let sysctl = super::sysctl_get();
 
@@ -129,7 +165,7 @@ pub mod clock {
       sysctl.rcc.set_mosdis(false);
 
       // Loop till the MOSC has locked
-      wait_for!(sysctl.ris.moscpupris());
+      try!(try_wait_for!(sysctl.ris.moscpupris(), deadline));
     }
 
     sysctl.rcc2.set_oscsrc2(source as u32);
@@ -165,12 +201,13 @@ pub mod clock {
       sysctl.misc.set_plllmis(true);
 
       // Wait till PLL is locked
-      wait_for!(sysctl.pllstat.lock());
+      try!(try_wait_for!(sysctl.pllstat.lock(), deadline));
 
       // Remove PLL bypass
       sysctl.rcc.set_bypass(false);
       sysctl.rcc2.set_bypass2(false);
     }
+    Ok(())
   }
 
   /// Retrieve the current sysclk frequency
@@ -277,6 +314,27 @@ pub mod clock {
 
     div_freq / sysdiv as usize
   }
+
+  /// Clock tree of Tiva C.
+  ///
+  /// There are no bus prescalers, all buses and peripherals run at the
+  /// system clock.
+  #[derive(Clone, Copy)]
+  pub struct Clocks;
+
+  impl Clocks {
+    /// Returns the clock tree.
+    pub fn new() -> Clocks {
+      Clocks
+    }
+  }
+
+  impl ClockTree for Clocks {
+    fn core_frequency(&self) -> u32 { sysclk_get() as u32 }
+    fn ahb_frequency(&self) -> u32 { sysclk_get() as u32 }
+    fn apb1_frequency(&self) -> u32 { sysclk_get() as u32 }
+    fn apb2_frequency(&self) -> u32 { sysclk_get() as u32 }
+  }
 }
 
 impl Copy for clock::ClockSource {}
@@ -284,6 +342,8 @@ impl Copy for clock::ClockSource {}
 pub mod periph {
   //! peripheral system control
 
+  use hal::clock::PeripheralFrequency;
+
   /// Sysctl can reset/clock gate each module, as well as set various sleep and
   /// deep-sleep mode behaviour.
   #[derive(Clone, Copy)]
@@ -339,6 +399,12 @@ pub mod periph {
     }
   }
 
+  impl PeripheralFrequency for PeripheralClock {
+    fn frequency(&self) -> u32 {
+      super::clock::sysclk_get() as u32
+    }
+  }
+
   #[allow(missing_docs)]
   pub mod gpio {
     //! GPIO system control peripherals. Split into ports of 8 GPIO each.
@@ -15,10 +15,15 @@
 
 //! UART configuration
 
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
 use hal::tiva_c::sysctl;
 use util::support::get_reg_ref;
 
 use drivers::chario::CharIO;
+use hal::clock::{ClockListener, DRAIN_SPINS};
+use hal::time::{Deadline, SpinDeadline, Timeout};
 use hal::uart;
 
 #[path="../../util/ioreg.rs"]
@@ -45,6 +50,8 @@ pub enum UartId {
 pub struct Uart {
   /// UART register interface
   regs: &'static reg::Uart,
+  /// Configured baud rate, kept across clock changes
+  baudrate: usize,
 }
 
 impl Uart {
@@ -66,7 +73,7 @@ impl Uart {
       UartId::Uart7 => (sysctl::periph::uart::UART_7, reg::UART_7),
     };
 
-    let uart = Uart { regs: get_reg_ref(regs) };
+    let uart = Uart { regs: get_reg_ref(regs), baudrate: baudrate };
 
     periph.ensure_enabled();
 
@@ -81,11 +88,6 @@ impl Uart {
                word_len:  u8,
                parity:    uart::Parity,
                stop_bits: u8) {
-    let sysclk = sysctl::clock::sysclk_get();
-
-    // compute the baud rate divisor rounded to the nearest
-    let brd = ((((sysclk / 16) << 6) + baudrate / 2) / baudrate) as u32;
-
     self.regs.ctl
       // Disable the UART before configuration
       .set_uarten(false)
@@ -96,8 +98,7 @@ impl Uart {
       // Disable High-Speed
       .set_hse(false);
 
-    self.regs.ibrd.set_divint(brd >> 6);
-    self.regs.fbrd.set_divfrac(brd & ((1 << 6) - 1));
+    self.set_divisor(baudrate);
 
     let ( parity_en, even_parity, sticky_parity ) = match parity {
       uart::Parity::Disabled => (false, false, false),
@@ -125,6 +126,35 @@ impl Uart {
     // Enable the UART
     self.regs.ctl.set_uarten(true);
   }
+
+  /// Set the baud rate divisor. It takes effect on the next write to `crh`.
+  fn set_divisor(&self, baudrate: usize) {
+    let sysclk = sysctl::clock::sysclk_get();
+
+    // compute the baud rate divisor rounded to the nearest
+    let brd = ((((sysclk / 16) << 6) + baudrate / 2) / baudrate) as u32;
+
+    self.regs.ibrd.set_divint(brd >> 6);
+    self.regs.fbrd.set_divfrac(brd & ((1 << 6) - 1));
+  }
+}
+
+impl ClockListener for Uart {
+  /// Lets the transmitter drain, so no character goes out at a wrong rate.
+  fn clock_will_change(&self) {
+    let deadline = SpinDeadline::new(DRAIN_SPINS);
+    let _ = try_wait_for!(!self.regs.fr.busy(), deadline);
+  }
+
+  /// Recomputes the divisor for the configured baud rate.
+  fn clock_changed(&self) {
+    self.regs.ctl.set_uarten(false);
+    self.set_divisor(self.baudrate);
+    // Latch the new divisor
+    let stp2 = self.regs.crh.stp2();
+    self.regs.crh.set_stp2(stp2);
+    self.regs.ctl.set_uarten(true);
+  }
 }
 
 impl CharIO for Uart {
@@ -136,6 +166,14 @@ impl CharIO for Uart {
   }
 }
 
+impl uart::TryCharIO for Uart {
+  fn try_putc(&self, value: char, deadline: &Deadline) -> Result<(), Timeout> {
+    try!(try_wait_for!(!self.regs.fr.txff(), deadline));
+    self.regs.data.set_data(value as u32);
+    Ok(())
+  }
+}
+
 pub mod reg {
   //! Uart registers definition
   use volatile_cell::VolatileCell;
@@ -18,9 +18,15 @@ UART interface.
 
 UARTConf is a MCU-specific struct.
 
-UART objects implement CharIO trait to perform actual data transmission.
+UART objects implement CharIO trait to perform actual data transmission, and
+`TryCharIO` to do it with a deadline.
 */
 
+use core::result::Result;
+use core::result::Result::Ok;
+
+use hal::time::{Deadline, Timeout};
+
 /// UART parity mode.
 #[derive(Clone, Copy)]
 pub enum Parity {
@@ -35,3 +41,18 @@ pub enum Parity {
   /// Partity bit forced to 0.
   Forced0,
 }
+
+/// Character output that gives up when the transmitter doesn't become ready
+/// before a deadline, e.g. because of hardware flow control.
+pub trait TryCharIO {
+  /// Outputs a character.
+  fn try_putc(&self, value: char, deadline: &Deadline) -> Result<(), Timeout>;
+
+  /// Outputs a string, the deadline covering the whole string.
+  fn try_puts(&self, s: &str, deadline: &Deadline) -> Result<(), Timeout> {
+    for &c in s.as_bytes().iter() {
+      try!(self.try_putc(c as char, deadline));
+    }
+    Ok(())
+  }
+}
@@ -14,86 +14,89 @@
 // limitations under the License.
 
 //! Condition variables
+//!
+//! Waiting on a condition variable unlocks a mutex and blocks the task until
+//! another task, or an interrupt handler, notifies it; the mutex is locked
+//! again before `wait` returns. Wake ups can't be missed between the unlock
+//! and the wait, but the condition should still be checked in a loop, as
+//! another task may have changed it by the time the waiter runs.
 
 pub use os::cond_var::internal::{CondVar, COND_VAR_INIT};
 
-#[cfg(multitasking)]
+#[cfg(feature = "multitasking")]
 mod internal {
-  use core::option::{None, Some};
-  use core::ty::Unsafe;
   use core::marker::Sync;
 
-  use hal::cortex_m3::sched::NoInterrupts;
-  use util::queue::{Queue, Node};
-  use os::task::{TaskDescriptor, Tasks};
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+  use core::option::Option::{self, Some, None};
+  use os::mutex::Guard;
+  use os::task::{self, WaitQueue};
 
   /// A condition variable
   pub struct CondVar {
-    waiting: Queue<*mut TaskDescriptor>
+    waiting: WaitQueue,
   }
 
   /// Static initializer
-  pub const COND_VAR_INIT: CondVar = CondVar {
-    waiting: Queue {
-      head: Unsafe { value: 0 as *mut Node<*mut TaskDescriptor>, marker1: marker::InvariantType },
-      tail: Unsafe { value: 0 as *mut Node<*mut TaskDescriptor>, marker1: marker::InvariantType },
-    }
-  };
+  pub const COND_VAR_INIT: CondVar = CondVar::new();
 
   impl CondVar {
     /// Create a new condition variable
-    pub fn new() -> CondVar {
-      CondVar { waiting: Queue::new() }
+    pub const fn new() -> CondVar {
+      CondVar { waiting: WaitQueue::new() }
     }
 
-    /// Wait on a condition variable.
-    pub fn wait(&self) {
-      /*
-       * The signalling thread is responsible for removing the waiting
-       * thread which ensures that a signal wakes up exactly one thread
-       * whenever there is one waiting.
-       */
-      unsafe {
-        let crit = NoInterrupts::new();
-        let mut waiting = Node::new(Tasks.current_task() as *mut TaskDescriptor);
-        self.waiting.push(&mut waiting, &crit);
-        Tasks.current_task().block(crit);
-      }
+    /// Unlocks `guard` and waits for a notification, locking the mutex again
+    /// afterwards.
+    pub fn wait<'a, T>(&self, guard: Guard<'a, T>) -> Guard<'a, T> {
+      let (guard, _) = self.wait_until(guard, None);
+      guard
     }
 
-    /// Wake up a thread waiting on a condition variable.
-    pub fn signal(&self) {
-      unsafe {
-        let crit = NoInterrupts::new();
-        match self.waiting.pop(&crit) {
-          None => { },
-          Some(task) => (*(*task).data).unblock(&crit)
-        }
-      }
+    /// Like `wait`, giving up after `ticks` system ticks. Returns false along
+    /// with the guard on timeout.
+    pub fn wait_timeout<'a, T>(&self, guard: Guard<'a, T>, ticks: u32)
+        -> (Guard<'a, T>, bool) {
+      self.wait_until(guard, Some(task::ticks() + ticks as u64))
     }
 
-    /// Wake up all threads waiting on a condition variable.
-    pub fn broadcast(&self) {
-      unsafe {
-        let crit = NoInterrupts::new();
-        loop {
-          match self.waiting.pop(&crit) {
-            None => break,
-            Some(task) => (*(*task).data).unblock(&crit)
-          }
-        }
-      }
+    fn wait_until<'a, T>(&self, guard: Guard<'a, T>, wake_at: Option<u64>)
+        -> (Guard<'a, T>, bool) {
+      let crit = NoInterrupts::new();
+      let mutex = guard.unlock_in(&crit);
+      let notified = self.waiting.wait(wake_at, crit);
+      (mutex.lock(), notified)
+    }
+
+    /// Wakes up the most urgent task waiting on the condition variable.
+    pub fn notify_one(&self) {
+      let crit = NoInterrupts::new();
+      self.waiting.wake_one(&crit);
+    }
+
+    /// Wakes up all tasks waiting on the condition variable.
+    pub fn notify_all(&self) {
+      let crit = NoInterrupts::new();
+      self.waiting.wake_all(&crit);
     }
   }
 
-  impl Sync for CondVar {}
+  unsafe impl Sync for CondVar {}
 }
 
-#[cfg(not(multitasking))]
+#[cfg(not(feature = "multitasking"))]
 mod internal {
   use core::marker::Sync;
   use core::cell::UnsafeCell;
 
+  use os::mutex::Guard;
   use util::support::wfi;
 
   /// A condition variable
@@ -102,20 +105,20 @@ mod internal {
   }
 
   /// Static initializer
-  pub const COND_VAR_INIT: CondVar = CondVar {
-    waiting: UnsafeCell::new(false),
-  };
+  pub const COND_VAR_INIT: CondVar = CondVar::new();
 
   impl CondVar {
     /// Create a new condition variable
-    pub fn new() -> CondVar {
+    pub const fn new() -> CondVar {
       CondVar {
         waiting: UnsafeCell::new(false),
       }
     }
 
-    /// Wait on a condition variable.
-    pub fn wait(&self) {
+    /// Unlocks `guard` and waits for a notification from an interrupt
+    /// handler, locking the mutex again afterwards.
+    pub fn wait<'a, T>(&self, guard: Guard<'a, T>) -> Guard<'a, T> {
+      let mutex = guard.unlock_in();
       unsafe {
         // TODO(bgamari): There is a race condition here
         *self.waiting.get() = true;
@@ -123,18 +126,41 @@ mod internal {
           wfi();
         }
       }
+      mutex.lock()
+    }
+
+    /// Like `wait`, giving up after `ticks` ticks of `hal::tick`. Returns
+    /// false along with the guard on timeout.
+    #[cfg(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7"))]
+    pub fn wait_timeout<'a, T>(&self, guard: Guard<'a, T>, ticks: u32)
+        -> (Guard<'a, T>, bool) {
+      use hal::tick;
+
+      let mutex = guard.unlock_in();
+      let wake_at = tick::ticks() + ticks as u64;
+      let notified = unsafe {
+        *self.waiting.get() = true;
+        while *self.waiting.get() && tick::ticks() < wake_at {
+          wfi();
+        }
+        !*self.waiting.get()
+      };
+      (mutex.lock(), notified)
     }
 
     /// Wake up a thread waiting on a condition variable.
-    pub fn signal(&self) {
+    pub fn notify_one(&self) {
       unsafe {
         *self.waiting.get() = false;
       }
     }
 
     /// Wake up all threads waiting on a condition variable.
-    pub fn broadcast(&self) {
-      self.signal();
+    pub fn notify_all(&self) {
+      self.notify_one();
     }
   }
 
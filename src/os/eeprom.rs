@@ -0,0 +1,367 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+EEPROM emulation on top of on-chip flash.
+
+Values are stored as a log of `(key, value)` records in one of two flash
+sectors. Updating a key appends a new record, so a sector is erased only when
+it fills up; at that point the latest value of every key is copied over to the
+other sector, and the full one is erased. This spreads the wear evenly across
+both sectors.
+
+Each sector starts with a header of four words. The first three are programmed
+(from the erased state) one by one as the sector goes through receiving, valid
+and obsolete states, so that an interrupted transfer can be recovered on the
+next boot.
+*/
+
+use core::cell::Cell;
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+use hal::flash::{Flash, Error, storage_start, storage_end};
+
+const ERASED: u32 = 0xffff_ffff;
+const MARK: u32 = 0x0000_0000;
+
+const RECEIVING_OFFSET: u32 = 0;
+const VALID_OFFSET: u32 = 4;
+const OBSOLETE_OFFSET: u32 = 8;
+const HEADER_SIZE: u32 = 16;
+
+/// Each record is a key word followed by a value word.
+const RECORD_SIZE: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+  Erased,
+  Receiving,
+  Valid,
+  Obsolete,
+  Corrupted,
+}
+
+/// Key/value storage in two flash sectors.
+pub struct Eeprom<'a, F: 'a> {
+  flash: &'a F,
+  sectors: [u32; 2],
+  sector_size: u32,
+  active: Cell<usize>,
+  next_free: Cell<u32>,
+}
+
+impl<'a, F: Flash> Eeprom<'a, F> {
+  /// Creates EEPROM emulation in the reserved flash storage region, using its
+  /// first two sectors.
+  pub fn from_storage(flash: &'a F) -> Result<Eeprom<'a, F>, Error> {
+    let first = storage_start();
+    let size = flash.sector_size(first);
+    if first + 2 * size > storage_end() {
+      return Err(Error::InvalidAddress);
+    }
+    Eeprom::new(flash, first, first + size)
+  }
+
+  /// Creates EEPROM emulation using the two given sectors.
+  ///
+  /// The sectors are checked and formatted if required, recovering from an
+  /// interrupted transfer.
+  pub fn new(flash: &'a F, first: u32, second: u32)
+      -> Result<Eeprom<'a, F>, Error> {
+    let eeprom = Eeprom {
+      flash: flash,
+      sectors: [first, second],
+      sector_size: flash.sector_size(first),
+      active: Cell::new(0),
+      next_free: Cell::new(first + HEADER_SIZE),
+    };
+    try!(eeprom.init());
+    Ok(eeprom)
+  }
+
+  /// Returns the latest value stored for `key`.
+  pub fn read(&self, key: u16) -> Option<u32> {
+    let base = self.sectors[self.active.get()];
+    self.find(base, self.next_free.get(), key)
+  }
+
+  /// Stores `value` for `key`.
+  ///
+  /// Nothing is written if the value didn't change.
+  pub fn write(&self, key: u16, value: u32) -> Result<(), Error> {
+    match self.read(key) {
+      Some(v) if v == value => return Ok(()),
+      _ => (),
+    }
+
+    let base = self.sectors[self.active.get()];
+    if self.next_free.get() + RECORD_SIZE > base + self.sector_size {
+      self.transfer(key, value)
+    } else {
+      let slot = self.next_free.get();
+      try!(self.write_record(slot, key, value));
+      self.next_free.set(slot + RECORD_SIZE);
+      Ok(())
+    }
+  }
+
+  /// Returns the number of free record slots left before a transfer.
+  pub fn free_slots(&self) -> u32 {
+    let end = self.sectors[self.active.get()] + self.sector_size;
+    (end - self.next_free.get()) / RECORD_SIZE
+  }
+
+  fn init(&self) -> Result<(), Error> {
+    let s0 = self.state(0);
+    let s1 = self.state(1);
+
+    let active = match (s0, s1) {
+      (State::Valid, State::Erased) => 0,
+      (State::Erased, State::Valid) => 1,
+      (State::Valid, _) => { try!(self.erase(1)); 0 },
+      (_, State::Valid) => { try!(self.erase(0)); 1 },
+      // Copy has completed, but the new sector wasn't marked valid yet.
+      (State::Obsolete, State::Receiving) => { try!(self.finish(0, 1)); 1 },
+      (State::Receiving, State::Obsolete) => { try!(self.finish(1, 0)); 0 },
+      _ => {
+        try!(self.erase(0));
+        try!(self.erase(1));
+        try!(self.mark(0, VALID_OFFSET));
+        0
+      },
+    };
+
+    self.active.set(active);
+    self.next_free.set(self.scan_free(self.sectors[active]));
+    Ok(())
+  }
+
+  fn state(&self, idx: usize) -> State {
+    let base = self.sectors[idx];
+    let receiving = self.flash.read(base + RECEIVING_OFFSET) != ERASED;
+    let valid = self.flash.read(base + VALID_OFFSET) != ERASED;
+    let obsolete = self.flash.read(base + OBSOLETE_OFFSET) != ERASED;
+
+    match (receiving, valid, obsolete) {
+      (false, false, false) => {
+        if self.scan_free(base) == base + HEADER_SIZE {
+          State::Erased
+        } else {
+          State::Corrupted
+        }
+      },
+      (true, false, false) => State::Receiving,
+      (_, true, false) => State::Valid,
+      (_, _, true) => State::Obsolete,
+      _ => State::Corrupted,
+    }
+  }
+
+  fn erase(&self, idx: usize) -> Result<(), Error> {
+    self.flash.erase_sector(self.sectors[idx])
+  }
+
+  fn mark(&self, idx: usize, offset: u32) -> Result<(), Error> {
+    self.flash.program_verified(self.sectors[idx] + offset, &[MARK])
+  }
+
+  /// Marks `to` as valid and erases `from`.
+  fn finish(&self, from: usize, to: usize) -> Result<(), Error> {
+    try!(self.mark(to, VALID_OFFSET));
+    self.erase(from)
+  }
+
+  /// Returns the address of the first slot after the last used one.
+  fn scan_free(&self, base: u32) -> u32 {
+    let mut addr = base + self.sector_size;
+    while addr > base + HEADER_SIZE {
+      let prev = addr - RECORD_SIZE;
+      if self.flash.read(prev) != ERASED || self.flash.read(prev + 4) != ERASED {
+        break;
+      }
+      addr = prev;
+    }
+    addr
+  }
+
+  /// Looks up the latest record for `key` between sector start and `end`.
+  fn find(&self, base: u32, end: u32, key: u16) -> Option<u32> {
+    let mut addr = end;
+    while addr > base + HEADER_SIZE {
+      addr -= RECORD_SIZE;
+      if self.flash.read(addr) == key as u32 {
+        return Some(self.flash.read(addr + 4));
+      }
+    }
+    None
+  }
+
+  /// The value is programmed first, so a record interrupted half-way has an
+  /// erased key and is never matched.
+  fn write_record(&self, addr: u32, key: u16, value: u32) -> Result<(), Error> {
+    try!(self.flash.program_verified(addr + 4, &[value]));
+    self.flash.program_verified(addr, &[key as u32])
+  }
+
+  /// Moves latest values to the other sector, storing `key` with the new
+  /// `value` on the way.
+  fn transfer(&self, key: u16, value: u32) -> Result<(), Error> {
+    let from = self.active.get();
+    let to = 1 - from;
+    let from_base = self.sectors[from];
+    let from_end = self.next_free.get();
+    let to_base = self.sectors[to];
+    let to_end = to_base + self.sector_size;
+
+    try!(self.erase(to));
+    try!(self.mark(to, RECEIVING_OFFSET));
+
+    let mut slot = to_base + HEADER_SIZE;
+    try!(self.write_record(slot, key, value));
+    slot += RECORD_SIZE;
+
+    let mut addr = from_base + HEADER_SIZE;
+    while addr < from_end {
+      let k = self.flash.read(addr);
+      addr += RECORD_SIZE;
+      if k == ERASED || self.find(to_base, slot, k as u16).is_some() {
+        continue;
+      }
+      if slot + RECORD_SIZE > to_end {
+        return Err(Error::Failed);
+      }
+      let v = self.find(from_base, from_end, k as u16).unwrap();
+      try!(self.write_record(slot, k as u16, v));
+      slot += RECORD_SIZE;
+    }
+
+    try!(self.mark(from, OBSOLETE_OFFSET));
+    try!(self.finish(from, to));
+
+    self.active.set(to);
+    self.next_free.set(slot);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::cell::RefCell;
+  use core::result::Result;
+  use core::result::Result::Ok;
+
+  use hal::flash::{Flash, Error};
+  use super::Eeprom;
+
+  /// Two sectors of 64 bytes make 32 words.
+  const SECTOR: u32 = 64;
+
+  struct RamFlash {
+    words: RefCell<[u32; 32]>,
+    erases: RefCell<usize>,
+  }
+
+  impl RamFlash {
+    fn new() -> RamFlash {
+      RamFlash {
+        words: RefCell::new([0xffff_ffff; 32]),
+        erases: RefCell::new(0),
+      }
+    }
+  }
+
+  impl Flash for RamFlash {
+    fn sector_size(&self, _: u32) -> u32 { SECTOR }
+
+    fn erase_sector(&self, addr: u32) -> Result<(), Error> {
+      let start = (addr / SECTOR * SECTOR / 4) as usize;
+      for w in self.words.borrow_mut()[start..start + (SECTOR / 4) as usize].iter_mut() {
+        *w = 0xffff_ffff;
+      }
+      *self.erases.borrow_mut() += 1;
+      Ok(())
+    }
+
+    fn program(&self, addr: u32, data: &[u32]) -> Result<(), Error> {
+      for i in 0..data.len() {
+        self.words.borrow_mut()[(addr / 4) as usize + i] &= data[i];
+      }
+      Ok(())
+    }
+
+    fn is_read_protected(&self) -> bool { false }
+
+    fn read(&self, addr: u32) -> u32 {
+      self.words.borrow()[(addr / 4) as usize]
+    }
+  }
+
+  #[test]
+  fn formats_blank_flash() {
+    let flash = RamFlash::new();
+    let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+    assert!(eeprom.read(1).is_none());
+    assert!(eeprom.free_slots() == 6);
+  }
+
+  #[test]
+  fn returns_latest_value() {
+    let flash = RamFlash::new();
+    let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+    eeprom.write(1, 10).ok().unwrap();
+    eeprom.write(2, 20).ok().unwrap();
+    eeprom.write(1, 11).ok().unwrap();
+    assert!(eeprom.read(1) == Some(11));
+    assert!(eeprom.read(2) == Some(20));
+  }
+
+  #[test]
+  fn skips_unchanged_values() {
+    let flash = RamFlash::new();
+    let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+    eeprom.write(1, 10).ok().unwrap();
+    eeprom.write(1, 10).ok().unwrap();
+    assert!(eeprom.free_slots() == 5);
+  }
+
+  #[test]
+  fn transfers_to_other_sector_when_full() {
+    let flash = RamFlash::new();
+    let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+    for i in 0..20 {
+      eeprom.write((i % 3) as u16, i).ok().unwrap();
+    }
+    assert!(eeprom.read(0) == Some(18));
+    assert!(eeprom.read(1) == Some(19));
+    assert!(eeprom.read(2) == Some(17));
+    assert!(*flash.erases.borrow() > 2);
+  }
+
+  #[test]
+  fn survives_reboot() {
+    let flash = RamFlash::new();
+    {
+      let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+      for i in 0..10 {
+        eeprom.write((i % 2) as u16, i).ok().unwrap();
+      }
+    }
+    let eeprom = Eeprom::new(&flash, 0, SECTOR).ok().unwrap();
+    assert!(eeprom.read(0) == Some(8));
+    assert!(eeprom.read(1) == Some(9));
+  }
+}
@@ -0,0 +1,148 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+/*!
+Event flag groups.
+
+A group holds 32 flags, which tasks and interrupt handlers set and clear, and
+tasks wait for: either any or all of a set of flags. Setting flags wakes up
+all the waiting tasks, each of them checking its own condition again.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{self, Tasks, Waiter};
+use util::queue::{Node, Queue};
+
+/// Condition to wait for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wait {
+  /// Any of the flags is set.
+  Any,
+  /// All of the flags are set.
+  All,
+}
+
+/// A group of event flags.
+pub struct EventFlags {
+  flags: UnsafeCell<u32>,
+  waiting: Queue<Waiter>,
+}
+
+impl EventFlags {
+  /// Creates a group with all flags cleared.
+  pub const fn new() -> EventFlags {
+    EventFlags {
+      flags: UnsafeCell::new(0),
+      waiting: Queue::new(),
+    }
+  }
+
+  /// Sets `bits`, waking up waiting tasks.
+  pub fn set(&self, bits: u32) {
+    let crit = NoInterrupts::new();
+    unsafe {
+      *self.flags.get() |= bits;
+      loop {
+        match self.waiting.pop(&crit) {
+          Some(node) => Tasks.unblock((*node).index, &crit),
+          None => break,
+        }
+      }
+    }
+  }
+
+  /// Clears `bits`.
+  pub fn clear(&self, bits: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.flags.get() &= !bits };
+  }
+
+  /// Returns the flags currently set.
+  pub fn get(&self) -> u32 {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.flags.get() }
+  }
+
+  /// Blocks until the flags in `bits` satisfy `mode`.
+  ///
+  /// Returns the flags set at that point; if `clear` is true, the flags in
+  /// `bits` are then cleared.
+  pub fn wait(&self, bits: u32, mode: Wait, clear: bool) -> u32 {
+    match self.wait_until(bits, mode, clear, None) {
+      Some(flags) => flags,
+      None => 0,
+    }
+  }
+
+  /// Like `wait`, giving up after `ticks` system ticks. Returns None on
+  /// timeout.
+  pub fn wait_timeout(&self, bits: u32, mode: Wait, clear: bool, ticks: u32)
+      -> Option<u32> {
+    self.wait_until(bits, mode, clear, Some(task::ticks() + ticks as u64))
+  }
+
+  fn wait_until(&self, bits: u32, mode: Wait, clear: bool,
+      wake_at: Option<u64>) -> Option<u32> {
+    loop {
+      let crit = NoInterrupts::new();
+      match self.consume(bits, mode, clear, &crit) {
+        Some(flags) => return Some(flags),
+        None => (),
+      }
+      let mut node = Node::new(Waiter::current(&crit));
+      unsafe {
+        self.waiting.insert(&mut node, &crit);
+        Tasks.block_until(wake_at, crit);
+
+        // `set` takes the node off the queue; it's still queued if the wait
+        // timed out, and flags may have been set right as it did.
+        let crit = NoInterrupts::new();
+        if self.waiting.remove(&mut node, &crit) {
+          return self.consume(bits, mode, clear, &crit);
+        }
+      }
+    }
+  }
+
+  /// Returns the flags if they satisfy the condition, clearing `bits` if
+  /// requested.
+  fn consume(&self, bits: u32, mode: Wait, clear: bool, _: &NoInterrupts)
+      -> Option<u32> {
+    let flags = unsafe { *self.flags.get() };
+    let satisfied = match mode {
+      Wait::Any => flags & bits != 0,
+      Wait::All => flags & bits == bits,
+    };
+    if !satisfied {
+      return None;
+    }
+    if clear {
+      unsafe { *self.flags.get() &= !bits };
+    }
+    Some(flags)
+  }
+}
+
+unsafe impl Sync for EventFlags {}
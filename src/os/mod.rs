@@ -25,4 +25,10 @@ pub mod syscall;
 #[cfg(feature = "multitasking")] pub mod task;
 pub mod mutex;
 pub mod cond_var;
+#[cfg(feature = "multitasking")] pub mod semaphore;
+#[cfg(feature = "multitasking")] pub mod event_flags;
+#[cfg(feature = "multitasking")] pub mod queue;
+#[cfg(feature = "multitasking")] pub mod svc;
 pub mod debug;
+pub mod eeprom;
+pub mod timer;
@@ -14,161 +14,393 @@
 // limitations under the License.
 
 //! Mutexes
+//!
+//! A `Mutex<T>`, created with `Mutex::with_data`, owns the data it protects,
+//! which is only reachable through the `Guard` returned by locking it. A plain
+//! `Mutex`, from `Mutex::new()` or `MUTEX_INIT`, guards no data, just the code
+//! run while holding it.
+//!
+//! With the `multitasking` feature, a task locking a taken mutex blocks until
+//! it's unlocked. Meanwhile the owner inherits the priority of the most urgent
+//! waiting task, so that a task of medium priority can't keep it from
+//! unlocking, and passes it on if it's blocked on another mutex itself. Each
+//! task keeps a list of the mutexes it holds, and its priority is recomputed
+//! from all their waiters whenever one is unlocked or a waiter times out.
+//! Without multitasking, contention can only come from an interrupt handler
+//! and would never resolve, so `lock` aborts.
 
 pub use os::mutex::internal::{MUTEX_INIT, Mutex, Guard};
+#[cfg(feature = "multitasking")]
+pub use os::mutex::internal::RawMutex;
 
-#[cfg(multitasking)]
+#[cfg(feature = "multitasking")]
 mod internal {
-  use core::marker;
-  use core::ty::Unsafe;
-  use core::kinds::Sync;
-  use core::option::Option::{None, Some};
-  use core::ops::Drop;
+  use core::cell::UnsafeCell;
+  use core::intrinsics::abort;
+  use core::marker::{Send, Sync};
+  use core::mem::forget;
+  use core::ops::{Deref, DerefMut, Drop};
+  use core::option::Option::{self, None, Some};
 
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
   use hal::cortex_m3::irq::NoInterrupts;
-  use os::task::{TaskDescriptor, Tasks};
-  use util::queue::{Queue, Node};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+  use os::task::{self, Tasks, WaitQueue};
+
+  /// The state of a mutex, apart from the data it guards. The mutexes a task
+  /// holds are linked through it, so that the task's priority can be
+  /// recomputed from all their waiters.
+  pub struct RawMutex {
+    owner: UnsafeCell<Option<usize>>,
+    waiting: WaitQueue,
+    next_held: UnsafeCell<Option<*const RawMutex>>,
+  }
 
   /// A mutex
-  pub struct Mutex {
-    owner: UnsafeCell<Option<*mut TaskDescriptor>>,
-    waiting: Queue<*mut TaskDescriptor>
+  pub struct Mutex<T = ()> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
   }
 
   /// Static initializer
-  pub const MUTEX_INIT: Mutex = Mutex {
-    owner: UnsafeCell::new(None),
-    waiting: Queue {
-      head: UnsafeCell::new(0 as *mut Node<*mut TaskDescriptor>),
-      tail: UnsafeCell::new(0 as *mut Node<*mut TaskDescriptor>),
-    }
-  };
+  pub const MUTEX_INIT: Mutex = Mutex::new();
 
   /// A mutex lock
   #[must_use]
-  pub struct Guard<'a> {
-    mutex: &'a Mutex
+  pub struct Guard<'a, T: 'a = ()> {
+    mutex: &'a Mutex<T>,
   }
 
-  impl Mutex {
+  impl Mutex<()> {
     /// Create a new mutex
-    pub fn new() -> Mutex {
+    pub const fn new() -> Mutex {
+      Mutex::with_data(())
+    }
+  }
+
+  impl<T> Mutex<T> {
+    /// Create a new mutex owning `data`
+    pub const fn with_data(data: T) -> Mutex<T> {
       Mutex {
-        owner: UnsafeCell::new(None),
-        waiting: Queue::new(),
+        raw: RawMutex::new(),
+        data: UnsafeCell::new(data),
       }
     }
 
-    /// Take a mutex lock
-    pub fn lock<'a>(&'a self) -> Guard<'a> {
-      /*
-       * This is a bit subtle: We need to add ourselves to the mutex's
-       * waiting list. To do this we allocate a list item on the local
-       * stack, append it to the waiting list, and block. When the task
-       * before us unlocks the mutex, they will wake us up. Finally, when
-       * we are executing again we remove our entry from the list.
-       */
-      unsafe {
-        // we need the critical section until the end of this function
-        let _crit = match *self.owner.get() {
-          None    => NoInterrupts::new(),
-          Some(_) => {
-            let crit = NoInterrupts::new();
-            let mut waiting = Node::new(Tasks.current_task() as *mut TaskDescriptor);
-            self.waiting.push(&mut waiting, &crit);
-            Tasks.current_task().block(crit); // drops crit
-
-            /*
-             * Note that there is a small window here between being
-             * awoken by the unlocking thread and reentering a critical
-             * section. An interrupt could fire within this window but
-             * since the unlocking thread retains ownership of the mutex
-             * there is no risk of a third-party sneaking in.
-             */
-            let crit = NoInterrupts::new();
-            self.waiting.pop(&crit);
-            crit
-          }
-        };
-
-        *self.owner.get() = Some(Tasks.current_task() as *mut TaskDescriptor);
+    /// Take a mutex lock, blocking until it's available.
+    ///
+    /// Locking a mutex the task already holds is a deadlock, and aborts.
+    pub fn lock<'a>(&'a self) -> Guard<'a, T> {
+      if self.raw.acquire(None) {
         Guard { mutex: self }
+      } else {
+        unsafe { abort() }
+      }
+    }
+
+    /// Take a mutex lock, waiting at most `ticks` system ticks for it.
+    pub fn lock_timeout<'a>(&'a self, ticks: u32) -> Option<Guard<'a, T>> {
+      if self.raw.acquire(Some(task::ticks() + ticks as u64)) {
+        Some(Guard { mutex: self })
+      } else {
+        None
       }
     }
 
     /// Try to take a mutex lock, returning `None` is unsuccessful
-    pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
+    pub fn try_lock<'a>(&'a self) -> Option<Guard<'a, T>> {
+      let _crit = NoInterrupts::new();
       unsafe {
-        match *self.owner.get() {
+        match *self.raw.owner.get() {
           None => {
-            let _crit = NoInterrupts::new();
-            *self.owner.get() = Some(Tasks.current_task() as *mut TaskDescriptor);
+            self.raw.take(Tasks.current_task);
             Some(Guard { mutex: self })
-          }
-          _ => None
+          },
+          Some(_) => None,
         }
       }
     }
 
+    /// Returns true if the running task holds the mutex.
+    pub fn held_by_current(&self) -> bool {
+      self.held_by(unsafe { Tasks.current_task })
+    }
+
+    /// Returns true if the task `index` holds the mutex.
+    pub fn held_by(&self, index: usize) -> bool {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.raw.owner.get() == Some(index) }
+    }
+
+    /// Unlocks a mutex locked by a task that forgot the guard, as system
+    /// calls do for unprivileged tasks.
+    ///
+    /// The caller must make sure the mutex is held, and that its owner won't
+    /// touch the data anymore.
+    pub unsafe fn unlock_forgotten(&self) {
+      let crit = NoInterrupts::new();
+      self.raw.unlock(&crit);
+    }
+  }
+
+  impl RawMutex {
+    const fn new() -> RawMutex {
+      RawMutex {
+        owner: UnsafeCell::new(None),
+        waiting: WaitQueue::new(),
+        next_held: UnsafeCell::new(None),
+      }
+    }
+
+    /// Takes the mutex, blocking until it's handed over or, if `wake_at` is
+    /// given, until that tick. Returns false on timeout.
+    fn acquire(&self, wake_at: Option<u64>) -> bool {
+      let crit = NoInterrupts::new();
+      unsafe {
+        let current = Tasks.current_task;
+        match *self.owner.get() {
+          None => {
+            self.take(current);
+            return true;
+          },
+          Some(owner) if owner == current => abort(),
+          Some(owner) => {
+            Tasks.task(current).blocked_on = Some(self as *const RawMutex);
+            raise(owner, Tasks.task(current).priority, &crit);
+          },
+        }
+
+        // The unlocking task hands the mutex over, so it's ours when woken up.
+        if self.waiting.wait(wake_at, crit) {
+          return true;
+        }
+
+        // The owner may have inherited a priority no task waits with anymore.
+        let crit = NoInterrupts::new();
+        Tasks.task(current).blocked_on = None;
+        match *self.owner.get() {
+          Some(owner) => update_priority(owner, &crit),
+          None => (),
+        }
+        false
+      }
+    }
+
+    unsafe fn take(&self, index: usize) {
+      *self.owner.get() = Some(index);
+      *self.next_held.get() = Tasks.task(index).held_mutexes;
+      Tasks.task(index).held_mutexes = Some(self as *const RawMutex);
+    }
+
+    /// Unlinks the mutex from the list of mutexes task `index` holds.
+    unsafe fn release(&self, index: usize) {
+      let this = self as *const RawMutex;
+      let mut prev: Option<*const RawMutex> = None;
+      let mut next = Tasks.task(index).held_mutexes;
+      loop {
+        let m = match next {
+          Some(m) => m,
+          None => return,
+        };
+        if m == this {
+          break;
+        }
+        prev = next;
+        next = *(*m).next_held.get();
+      }
+      let after = *self.next_held.get();
+      match prev {
+        Some(p) => *(*p).next_held.get() = after,
+        None => Tasks.task(index).held_mutexes = after,
+      }
+      *self.next_held.get() = None;
+    }
+
     /*
-     * Here we release ownership of the mutex only if there is no one
-     * waiting on it. Otherwise we retain to ensure there is no race
-     * between waking up the waiting thread and it claiming ownership.
+     * Ownership passes directly to the most urgent waiting task, so that no
+     * other task can sneak in before it runs.
      */
-    fn unlock(&self) {
+    fn unlock(&self, crit: &NoInterrupts) {
       unsafe {
-        let crit = NoInterrupts::new();
-        match self.waiting.peek() {
+        let owner = match *self.owner.get() {
+          Some(owner) => owner,
+          None => abort(),
+        };
+        self.release(owner);
+
+        let next = self.waiting.pop(crit);
+        match next {
           None => *self.owner.get() = None,
-          Some(nextTask) => {
-            let mut task = *(*nextTask).data;
-            task.unblock(&crit);
-          }
+          Some(next) => {
+            Tasks.task(next).blocked_on = None;
+            self.take(next);
+            update_priority(next, crit);
+          },
         }
+
+        // Drop what the owner inherited through this mutex before the next
+        // owner gets a chance to preempt it.
+        update_priority(owner, crit);
+        match next {
+          Some(next) => Tasks.wake(next, crit),
+          None => (),
+        }
+      }
+    }
+  }
+
+  unsafe impl Sync for RawMutex { }
+
+  /// Returns the priority task `index` should run with: its own, or that of
+  /// the most urgent task waiting for any of the mutexes it holds.
+  unsafe fn inherited_priority(index: usize, crit: &NoInterrupts) -> u8 {
+    let mut priority = Tasks.task(index).base_priority;
+    let mut held = Tasks.task(index).held_mutexes;
+    loop {
+      let mutex = match held {
+        Some(m) => &*m,
+        None => return priority,
+      };
+      match mutex.waiting.first(crit) {
+        Some(w) if Tasks.task(w).priority > priority => {
+          priority = Tasks.task(w).priority;
+        },
+        _ => (),
+      }
+      held = *mutex.next_held.get();
+    }
+  }
+
+  /*
+   * Priorities propagate along the ownership chain: a task blocked on a mutex
+   * is moved to its new place among the waiters, and the owner of that mutex
+   * is updated in turn. A deadlock cycle stops once priorities settle.
+   */
+
+  /// Raises the priority of task `index` to at least `priority`.
+  unsafe fn raise(mut index: usize, priority: u8, crit: &NoInterrupts) {
+    loop {
+      if Tasks.task(index).priority >= priority {
+        return;
+      }
+      Tasks.task(index).priority = priority;
+      index = match reposition(index, crit) {
+        Some(owner) => owner,
+        None => return,
+      };
+    }
+  }
+
+  /// Recomputes the priority of task `index` from the mutexes it holds.
+  unsafe fn update_priority(mut index: usize, crit: &NoInterrupts) {
+    loop {
+      let priority = inherited_priority(index, crit);
+      if Tasks.task(index).priority == priority {
+        return;
       }
+      Tasks.task(index).priority = priority;
+      index = match reposition(index, crit) {
+        Some(owner) => owner,
+        None => return,
+      };
+    }
+  }
+
+  /// Moves task `index` to its place in the queue of the mutex it's blocked
+  /// on, returning the owner of that mutex.
+  unsafe fn reposition(index: usize, crit: &NoInterrupts) -> Option<usize> {
+    match Tasks.task(index).blocked_on {
+      Some(m) => {
+        let mutex = &*m;
+        mutex.waiting.reposition(index, crit);
+        *mutex.owner.get()
+      },
+      None => None,
+    }
+  }
+
+  impl<'a, T> Guard<'a, T> {
+    /// Unlocks the mutex within a critical section, returning it so that it
+    /// can be locked again; used to wait on a condition variable.
+    pub fn unlock_in(self, crit: &NoInterrupts) -> &'a Mutex<T> {
+      let mutex = self.mutex;
+      forget(self);
+      mutex.raw.unlock(crit);
+      mutex
+    }
+  }
+
+  impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+      unsafe { &*self.mutex.data.get() }
+    }
+  }
+
+  impl<'a, T> DerefMut for Guard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+      unsafe { &mut *self.mutex.data.get() }
     }
   }
 
-  impl<'a> Drop for Guard<'a> {
+  impl<'a, T> Drop for Guard<'a, T> {
     #[inline]
     fn drop(&mut self) {
-      self.mutex.unlock();
+      let crit = NoInterrupts::new();
+      self.mutex.raw.unlock(&crit);
     }
   }
 
-  impl Sync for Mutex { }
+  unsafe impl<T: Send> Sync for Mutex<T> { }
 }
 
-#[cfg(not(multitasking))]
+#[cfg(not(feature = "multitasking"))]
 mod internal {
-  use core::marker::Sync;
+  use core::marker::{Send, Sync};
+  use core::mem::forget;
   use core::option::Option::{self, None, Some};
-  use core::ops::Drop;
+  use core::ops::{Deref, DerefMut, Drop};
   use core::intrinsics::abort;
   use core::cell::UnsafeCell;
 
   /// A mutex
-  pub struct Mutex {
+  pub struct Mutex<T = ()> {
     taken: UnsafeCell<bool>,
+    data: UnsafeCell<T>,
   }
 
   /// Static initializer
-  pub const MUTEX_INIT: Mutex = Mutex { taken: UnsafeCell::new(false) };
+  pub const MUTEX_INIT: Mutex = Mutex::new();
 
   /// A mutex lock
   #[must_use]
-  pub struct Guard<'a> {
-    mutex: &'a Mutex,
+  pub struct Guard<'a, T: 'a = ()> {
+    mutex: &'a Mutex<T>,
   }
 
-  impl Mutex {
+  impl Mutex<()> {
     /// Create a new mutex
-    pub fn new() -> Mutex {
-      Mutex { taken: UnsafeCell::new(false) }
+    pub const fn new() -> Mutex {
+      Mutex::with_data(())
+    }
+  }
+
+  impl<T> Mutex<T> {
+    /// Create a new mutex owning `data`
+    pub const fn with_data(data: T) -> Mutex<T> {
+      Mutex {
+        taken: UnsafeCell::new(false),
+        data: UnsafeCell::new(data),
+      }
     }
 
     /// Take a mutex lock
-    pub fn lock<'a>(&'a self) -> Guard<'a> {
+    pub fn lock<'a>(&'a self) -> Guard<'a, T> {
       unsafe {
         if *self.taken.get() {
           abort();
@@ -180,8 +412,14 @@ mod internal {
       }
     }
 
+    /// Take a mutex lock if it's available. Nothing could unlock it while
+    /// waiting, so `ticks` is ignored.
+    pub fn lock_timeout<'a>(&'a self, _ticks: u32) -> Option<Guard<'a, T>> {
+      self.try_lock()
+    }
+
     /// Try to take a mutex lock, returning `None` is unsuccessful
-    pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
+    pub fn try_lock<'a>(&'a self) -> Option<Guard<'a, T>> {
       unsafe {
         if *self.taken.get() {
           None
@@ -192,7 +430,31 @@ mod internal {
     }
   }
 
-  impl<'a> Drop for Guard<'a> {
+  impl<'a, T> Guard<'a, T> {
+    /// Unlocks the mutex, returning it so that it can be locked again; used to
+    /// wait on a condition variable.
+    pub fn unlock_in(self) -> &'a Mutex<T> {
+      let mutex = self.mutex;
+      forget(self);
+      unsafe { *mutex.taken.get() = false };
+      mutex
+    }
+  }
+
+  impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+      unsafe { &*self.mutex.data.get() }
+    }
+  }
+
+  impl<'a, T> DerefMut for Guard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+      unsafe { &mut *self.mutex.data.get() }
+    }
+  }
+
+  impl<'a, T> Drop for Guard<'a, T> {
     #[inline]
     fn drop(&mut self) {
       unsafe {
@@ -201,5 +463,31 @@ mod internal {
     }
   }
 
-  unsafe impl Sync for Mutex { }
+  unsafe impl<T: Send> Sync for Mutex<T> { }
+}
+
+#[cfg(all(test, not(feature = "multitasking")))]
+mod test {
+  use super::Mutex;
+
+  #[test]
+  fn guards_data() {
+    let mutex = Mutex::with_data(1u32);
+    {
+      let mut guard = mutex.lock();
+      *guard += 1;
+      assert!(mutex.try_lock().is_none());
+    }
+    assert!(*mutex.try_lock().unwrap() == 2);
+  }
+
+  #[test]
+  fn locks_without_data() {
+    let mutex = Mutex::new();
+    {
+      let _guard = mutex.lock();
+      assert!(mutex.try_lock().is_none());
+    }
+    assert!(mutex.try_lock().is_some());
+  }
 }
@@ -0,0 +1,190 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+/*!
+Message queues.
+
+A `Queue` passes messages by value between tasks and interrupt handlers,
+first in first out. Its storage is an array given on creation, so a queue can
+be a `static` without any allocation:
+
+```
+static FRAMES: Queue<Frame, [Frame; 8]> = Queue::new([EMPTY_FRAME; 8]);
+```
+
+`send` and `receive` block the calling task while the queue is full or
+empty, optionally with a timeout; `try_send` and `try_receive` never block
+and are the ones to use from interrupt handlers. A `Mailbox` is a queue
+holding a single message.
+*/
+
+use core::cell::UnsafeCell;
+use core::convert::AsMut;
+use core::marker::{Copy, PhantomData, Send, Sync};
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{self, WaitQueue};
+
+/// A fixed-capacity message queue, storing messages of type `T` in `A`.
+pub struct Queue<T, A> {
+  buf: UnsafeCell<A>,
+  head: UnsafeCell<usize>,
+  len: UnsafeCell<usize>,
+  senders: WaitQueue,
+  receivers: WaitQueue,
+  _marker: PhantomData<T>,
+}
+
+/// A queue for a single message.
+pub type Mailbox<T> = Queue<T, [T; 1]>;
+
+impl<T, A> Queue<T, A> {
+  /// Creates an empty queue, with `storage` holding the messages.
+  pub const fn new(storage: A) -> Queue<T, A> {
+    Queue {
+      buf: UnsafeCell::new(storage),
+      head: UnsafeCell::new(0),
+      len: UnsafeCell::new(0),
+      senders: WaitQueue::new(),
+      receivers: WaitQueue::new(),
+      _marker: PhantomData,
+    }
+  }
+}
+
+impl<T: Copy, A: AsMut<[T]>> Queue<T, A> {
+  /// Returns the number of messages the queue can hold.
+  pub fn capacity(&self) -> usize {
+    unsafe { (*self.buf.get()).as_mut().len() }
+  }
+
+  /// Returns the number of queued messages.
+  pub fn len(&self) -> usize {
+    unsafe { *self.len.get() }
+  }
+
+  /// Returns true if there is no message queued.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Sends a message, blocking while the queue is full.
+  pub fn send(&self, msg: T) {
+    let _ = self.send_until(msg, None);
+  }
+
+  /// Sends a message, waiting at most `ticks` system ticks for space.
+  ///
+  /// Returns the message back on timeout.
+  pub fn send_timeout(&self, msg: T, ticks: u32) -> Result<(), T> {
+    self.send_until(msg, Some(task::ticks() + ticks as u64))
+  }
+
+  /// Sends a message if there is space for it, returning it back otherwise.
+  pub fn try_send(&self, msg: T) -> Result<(), T> {
+    let crit = NoInterrupts::new();
+    self.push(msg, &crit)
+  }
+
+  /// Receives a message, blocking while the queue is empty.
+  pub fn receive(&self) -> T {
+    loop {
+      match self.receive_until(None) {
+        Some(msg) => return msg,
+        None => (),
+      }
+    }
+  }
+
+  /// Receives a message, waiting at most `ticks` system ticks for one.
+  pub fn receive_timeout(&self, ticks: u32) -> Option<T> {
+    self.receive_until(Some(task::ticks() + ticks as u64))
+  }
+
+  /// Receives a message if there is one.
+  pub fn try_receive(&self) -> Option<T> {
+    let crit = NoInterrupts::new();
+    self.pop(&crit)
+  }
+
+  fn send_until(&self, msg: T, wake_at: Option<u64>) -> Result<(), T> {
+    loop {
+      let crit = NoInterrupts::new();
+      match self.push(msg, &crit) {
+        Ok(()) => return Ok(()),
+        Err(_) => (),
+      }
+      if !self.senders.wait(wake_at, crit) {
+        let crit = NoInterrupts::new();
+        return self.push(msg, &crit);
+      }
+    }
+  }
+
+  fn receive_until(&self, wake_at: Option<u64>) -> Option<T> {
+    loop {
+      let crit = NoInterrupts::new();
+      match self.pop(&crit) {
+        Some(msg) => return Some(msg),
+        None => (),
+      }
+      if !self.receivers.wait(wake_at, crit) {
+        let crit = NoInterrupts::new();
+        return self.pop(&crit);
+      }
+    }
+  }
+
+  fn push(&self, msg: T, crit: &NoInterrupts) -> Result<(), T> {
+    unsafe {
+      let buf = (*self.buf.get()).as_mut();
+      let len = *self.len.get();
+      if len == buf.len() {
+        return Err(msg);
+      }
+      buf[(*self.head.get() + len) % buf.len()] = msg;
+      *self.len.get() = len + 1;
+    }
+    self.receivers.wake_one(crit);
+    Ok(())
+  }
+
+  fn pop(&self, crit: &NoInterrupts) -> Option<T> {
+    let msg = unsafe {
+      let buf = (*self.buf.get()).as_mut();
+      let len = *self.len.get();
+      if len == 0 {
+        return None;
+      }
+      let head = *self.head.get();
+      *self.head.get() = (head + 1) % buf.len();
+      *self.len.get() = len - 1;
+      buf[head]
+    };
+    self.senders.wake_one(crit);
+    Some(msg)
+  }
+}
+
+unsafe impl<T: Send, A: Send> Sync for Queue<T, A> {}
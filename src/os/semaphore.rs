@@ -0,0 +1,130 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+/*!
+Counting semaphores.
+
+A semaphore counts available units of a resource, up to a maximum; with a
+maximum of one it's a binary semaphore, the usual way for an interrupt
+handler to signal a task. `give` never blocks and can be called from
+interrupt handlers. `take` blocks the calling task while the count is zero;
+a unit given while tasks wait goes straight to the most urgent of them.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{self, Tasks, Waiter};
+use util::queue::{Node, Queue};
+
+/// A counting semaphore.
+pub struct Semaphore {
+  count: UnsafeCell<u32>,
+  max: u32,
+  waiting: Queue<Waiter>,
+}
+
+impl Semaphore {
+  /// Creates a semaphore with `initial` units available, counting up to
+  /// `max`.
+  pub const fn new(initial: u32, max: u32) -> Semaphore {
+    Semaphore {
+      count: UnsafeCell::new(initial),
+      max: max,
+      waiting: Queue::new(),
+    }
+  }
+
+  /// Releases a unit, waking up a waiting task.
+  ///
+  /// Returns false if the count was already at its maximum.
+  pub fn give(&self) -> bool {
+    let crit = NoInterrupts::new();
+    unsafe {
+      match self.waiting.pop(&crit) {
+        Some(node) => {
+          Tasks.unblock((*node).index, &crit);
+          return true;
+        },
+        None => (),
+      }
+      if *self.count.get() >= self.max {
+        false
+      } else {
+        *self.count.get() += 1;
+        true
+      }
+    }
+  }
+
+  /// Takes a unit, blocking until one is available.
+  pub fn take(&self) {
+    self.take_until(None);
+  }
+
+  /// Takes a unit, waiting at most `ticks` system ticks for it.
+  ///
+  /// Returns false on timeout.
+  pub fn take_timeout(&self, ticks: u32) -> bool {
+    self.take_until(Some(task::ticks() + ticks as u64))
+  }
+
+  /// Takes a unit if one is available.
+  pub fn try_take(&self) -> bool {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if *self.count.get() > 0 {
+        *self.count.get() -= 1;
+        true
+      } else {
+        false
+      }
+    }
+  }
+
+  /// Returns the number of available units.
+  pub fn count(&self) -> u32 {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.count.get() }
+  }
+
+  fn take_until(&self, wake_at: Option<u64>) -> bool {
+    let crit = NoInterrupts::new();
+    let mut node = Node::new(Waiter::current(&crit));
+    unsafe {
+      if *self.count.get() > 0 {
+        *self.count.get() -= 1;
+        return true;
+      }
+      self.waiting.insert(&mut node, &crit);
+      Tasks.block_until(wake_at, crit);
+
+      // `give` hands the unit over without counting it, taking the node off
+      // the queue; it's still queued if the wait timed out.
+      let crit = NoInterrupts::new();
+      !self.waiting.remove(&mut node, &crit)
+    }
+  }
+}
+
+unsafe impl Sync for Semaphore {}
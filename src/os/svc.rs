@@ -0,0 +1,473 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+/*!
+Numbered system calls.
+
+Unprivileged tasks can't touch kernel state, so they call into the kernel with
+`svc #n`. `svc_dispatch` checks the arguments in SVCall, then makes the task
+privileged and returns to the kernel function for the call, still on the task
+stack; that can block like any other task code. The kernel function returns
+to a trampoline, whose `svc #255` drops privilege again and returns to the
+caller with the result.
+
+Mutexes and message queues are passed by handle, from `register_mutex` and
+`register_port`; message buffers must lie in memory the task can access. A
+call with invalid arguments fails with `Error::Invalid`, while an unknown call
+kills the task.
+
+When a task is killed, the registered mutexes it holds are unlocked.
+
+Call 0 is the raw `os::syscall::syscall`, run in SVCall itself; it kills an
+unprivileged caller.
+
+```
+static RX: Queue<Frame, [Frame; 4]> = Queue::new([EMPTY_FRAME; 4]);
+
+let rx = svc::register_port(&RX).unwrap();
+...
+let mut frame = EMPTY_FRAME;
+match svc::receive(rx, as_bytes_mut(&mut frame), None) { ... }
+```
+*/
+
+use core::intrinsics::{copy_nonoverlapping, transmute};
+use core::marker::{Copy, Send, Sync};
+use core::mem::{forget, size_of, uninitialized};
+use core::option::Option::{self, Some, None};
+use core::result::Result;
+use core::result::Result::{Ok, Err};
+use core::convert::AsMut;
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::{irq, sched};
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::{irq, sched};
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::{irq, sched};
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::{irq, sched};
+use os::mutex::Mutex;
+use os::queue::Queue;
+use os::syscall::syscall;
+use os::task::{self, Tasks, kill_current_task};
+
+/// Maximum number of registered kernel objects.
+pub const MAX_OBJECTS: usize = 16;
+
+/// Raw call of a function, for privileged code.
+pub const SVC_RAW: u8 = 0;
+/// Yields the CPU to another task.
+pub const SVC_YIELD: u8 = 1;
+/// Sleeps for a number of ticks.
+pub const SVC_SLEEP: u8 = 2;
+/// Kills the calling task.
+pub const SVC_EXIT: u8 = 3;
+/// Locks a mutex.
+pub const SVC_LOCK: u8 = 4;
+/// Unlocks a mutex.
+pub const SVC_UNLOCK: u8 = 5;
+/// Sends a message to a port.
+pub const SVC_SEND: u8 = 6;
+/// Receives a message from a port.
+pub const SVC_RECEIVE: u8 = 7;
+/// Returns from a kernel function, only valid from the trampoline.
+const SVC_RETURN: u8 = 255;
+
+const RESULT_OK: u32 = 0;
+const RESULT_TIMEOUT: u32 = 1;
+const RESULT_INVALID: u32 = 2;
+
+/// Timeout argument for waiting forever.
+const FOREVER: u32 = 0xffff_ffff;
+
+/// System call errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// The handle, the buffer or the state of the object is invalid.
+  Invalid,
+  /// The timeout expired.
+  Timeout,
+}
+
+/// Handle of a registered kernel object.
+pub type Handle = u32;
+
+/// A message queue usable through system calls, with messages as bytes.
+pub trait Port: Sync {
+  /// Returns the size of a message.
+  fn message_size(&self) -> usize;
+
+  /// Sends the message at `msg`, waiting at most `ticks` for space, or
+  /// forever if None. Returns false on timeout.
+  unsafe fn send_raw(&self, msg: *const u8, ticks: Option<u32>) -> bool;
+
+  /// Receives a message to `buf`, waiting at most `ticks` for one, or
+  /// forever if None. Returns false on timeout.
+  unsafe fn receive_raw(&self, buf: *mut u8, ticks: Option<u32>) -> bool;
+}
+
+impl<T: Copy + Send, A: AsMut<[T]> + Send> Port for Queue<T, A> {
+  fn message_size(&self) -> usize {
+    size_of::<T>()
+  }
+
+  unsafe fn send_raw(&self, msg: *const u8, ticks: Option<u32>) -> bool {
+    // The buffer may not be aligned for T.
+    let mut m: T = uninitialized();
+    copy_nonoverlapping(msg, &mut m as *mut T as *mut u8, size_of::<T>());
+    match ticks {
+      None => { self.send(m); true },
+      Some(t) => self.send_timeout(m, t).is_ok(),
+    }
+  }
+
+  unsafe fn receive_raw(&self, buf: *mut u8, ticks: Option<u32>) -> bool {
+    let m = match ticks {
+      None => Some(self.receive()),
+      Some(t) => self.receive_timeout(t),
+    };
+    match m {
+      Some(m) => {
+        copy_nonoverlapping(&m as *const T as *const u8, buf, size_of::<T>());
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Object {
+  Empty,
+  Mutex(&'static Mutex),
+  Port(&'static Port),
+}
+
+static mut Objects: [Object; MAX_OBJECTS] = [Object::Empty; MAX_OBJECTS];
+
+fn register(object: Object) -> Option<Handle> {
+  let _crit = irq::NoInterrupts::new();
+  unsafe {
+    for i in 0..MAX_OBJECTS {
+      match Objects[i] {
+        Object::Empty => {
+          Objects[i] = object;
+          return Some(i as Handle);
+        },
+        _ => (),
+      }
+    }
+  }
+  None
+}
+
+/// Makes a mutex available to system calls, returning its handle, or None if
+/// there's no room left.
+pub fn register_mutex(mutex: &'static Mutex) -> Option<Handle> {
+  register(Object::Mutex(mutex))
+}
+
+/// Makes a message queue available to system calls, returning its handle, or
+/// None if there's no room left.
+pub fn register_port(port: &'static Port) -> Option<Handle> {
+  register(Object::Port(port))
+}
+
+/// Unlocks the registered mutexes held by the task `index`, which was killed.
+pub fn release_mutexes(index: usize) {
+  for i in 0..MAX_OBJECTS {
+    match unsafe { Objects[i] } {
+      Object::Mutex(m) if m.held_by(index) => unsafe { m.unlock_forgotten() },
+      _ => (),
+    }
+  }
+}
+
+fn object(handle: u32) -> Object {
+  if (handle as usize) < MAX_OBJECTS {
+    unsafe { Objects[handle as usize] }
+  } else {
+    Object::Empty
+  }
+}
+
+fn result(r: u32) -> Result<(), Error> {
+  match r {
+    RESULT_OK => Ok(()),
+    RESULT_TIMEOUT => Err(Error::Timeout),
+    _ => Err(Error::Invalid),
+  }
+}
+
+fn timeout_arg(timeout: Option<u32>) -> u32 {
+  match timeout {
+    Some(t) if t < FOREVER => t,
+    _ => FOREVER,
+  }
+}
+
+/// Yields the CPU to another task.
+pub fn yield_now() {
+  unsafe { svc_yield() };
+}
+
+/// Sleeps for `ticks` system ticks.
+pub fn sleep(ticks: u32) {
+  unsafe { svc_sleep(ticks) };
+}
+
+/// Ends the calling task.
+pub fn exit() -> ! {
+  unsafe { svc_exit() };
+  loop {}
+}
+
+/// Locks the mutex `handle`, blocking until it's available.
+pub fn lock(handle: Handle) -> Result<(), Error> {
+  result(unsafe { svc_lock(handle) })
+}
+
+/// Unlocks the mutex `handle`, which the task must hold.
+pub fn unlock(handle: Handle) -> Result<(), Error> {
+  result(unsafe { svc_unlock(handle) })
+}
+
+/// Sends `msg` to the port `handle`, waiting at most `timeout` ticks for
+/// space, or forever if None. `msg` must be exactly a message long.
+pub fn send(handle: Handle, msg: &[u8], timeout: Option<u32>)
+    -> Result<(), Error> {
+  result(unsafe {
+    svc_send(handle, msg.as_ptr(), msg.len() as u32, timeout_arg(timeout))
+  })
+}
+
+/// Receives a message from the port `handle` to `buf`, waiting at most
+/// `timeout` ticks for one, or forever if None. `buf` must be exactly a
+/// message long.
+pub fn receive(handle: Handle, buf: &mut [u8], timeout: Option<u32>)
+    -> Result<(), Error> {
+  result(unsafe {
+    svc_receive(handle, buf.as_mut_ptr(), buf.len() as u32,
+        timeout_arg(timeout))
+  })
+}
+
+extern {
+  fn svc_yield() -> u32;
+  fn svc_sleep(ticks: u32) -> u32;
+  fn svc_exit() -> u32;
+  fn svc_lock(handle: u32) -> u32;
+  fn svc_unlock(handle: u32) -> u32;
+  fn svc_send(handle: u32, msg: *const u8, len: u32, ticks: u32) -> u32;
+  fn svc_receive(handle: u32, buf: *mut u8, len: u32, ticks: u32) -> u32;
+  fn svc_return_trampoline();
+}
+
+/// System call stubs, and the trampoline kernel functions return to.
+#[cfg(target_os = "none")]
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn svc_stubs_wrapper() {
+  asm!(".global svc_yield, svc_sleep, svc_exit, svc_lock, svc_unlock
+      .global svc_send, svc_receive, svc_return_trampoline
+
+      .thumb_func
+      svc_yield:
+      svc 1
+      bx lr
+
+      .thumb_func
+      svc_sleep:
+      svc 2
+      bx lr
+
+      .thumb_func
+      svc_exit:
+      svc 3
+      bx lr
+
+      .thumb_func
+      svc_lock:
+      svc 4
+      bx lr
+
+      .thumb_func
+      svc_unlock:
+      svc 5
+      bx lr
+
+      .thumb_func
+      svc_send:
+      svc 6
+      bx lr
+
+      .thumb_func
+      svc_receive:
+      svc 7
+      bx lr
+
+      .thumb_func
+      svc_return_trampoline:
+      svc 255" :::: "volatile");
+}
+
+type KernelFn = extern "C" fn(u32, u32, u32, u32) -> u32;
+
+extern "C" fn call_yield(_: u32, _: u32, _: u32, _: u32) -> u32 {
+  task::yield_now();
+  RESULT_OK
+}
+
+extern "C" fn call_sleep(ticks: u32, _: u32, _: u32, _: u32) -> u32 {
+  task::sleep_ticks(ticks);
+  RESULT_OK
+}
+
+extern "C" fn call_exit(_: u32, _: u32, _: u32, _: u32) -> u32 {
+  unsafe { syscall(kill_current_task, 0) };
+  loop {}
+}
+
+extern "C" fn call_lock(handle: u32, _: u32, _: u32, _: u32) -> u32 {
+  match object(handle) {
+    Object::Mutex(m) => { forget(m.lock()); RESULT_OK },
+    _ => RESULT_INVALID,
+  }
+}
+
+extern "C" fn call_unlock(handle: u32, _: u32, _: u32, _: u32) -> u32 {
+  match object(handle) {
+    Object::Mutex(m) => { unsafe { m.unlock_forgotten() }; RESULT_OK },
+    _ => RESULT_INVALID,
+  }
+}
+
+fn ticks_arg(ticks: u32) -> Option<u32> {
+  if ticks == FOREVER { None } else { Some(ticks) }
+}
+
+extern "C" fn call_send(handle: u32, msg: u32, _: u32, ticks: u32) -> u32 {
+  match object(handle) {
+    Object::Port(p) => {
+      match unsafe { p.send_raw(msg as *const u8, ticks_arg(ticks)) } {
+        true => RESULT_OK,
+        false => RESULT_TIMEOUT,
+      }
+    },
+    _ => RESULT_INVALID,
+  }
+}
+
+extern "C" fn call_receive(handle: u32, buf: u32, _: u32, ticks: u32) -> u32 {
+  match object(handle) {
+    Object::Port(p) => {
+      match unsafe { p.receive_raw(buf as *mut u8, ticks_arg(ticks)) } {
+        true => RESULT_OK,
+        false => RESULT_TIMEOUT,
+      }
+    },
+    _ => RESULT_INVALID,
+  }
+}
+
+/// Checks the arguments of call `number`; the kernel function then only has
+/// to deal with timeouts.
+unsafe fn valid_args(number: u8, frame: &sched::SavedState) -> bool {
+  let task = Tasks.current_task();
+  match number {
+    SVC_YIELD | SVC_SLEEP | SVC_EXIT => true,
+    SVC_LOCK => match object(frame.r0) {
+      Object::Mutex(m) => !m.held_by_current(),
+      _ => false,
+    },
+    SVC_UNLOCK => match object(frame.r0) {
+      Object::Mutex(m) => m.held_by_current(),
+      _ => false,
+    },
+    SVC_SEND | SVC_RECEIVE => match object(frame.r0) {
+      Object::Port(p) => frame.r2 as usize == p.message_size() &&
+          task.can_access(frame.r1, frame.r2, number == SVC_RECEIVE),
+      _ => false,
+    },
+    _ => false,
+  }
+}
+
+fn kernel_fn(number: u8) -> KernelFn {
+  match number {
+    SVC_YIELD => call_yield,
+    SVC_SLEEP => call_sleep,
+    SVC_EXIT => call_exit,
+    SVC_LOCK => call_lock,
+    SVC_UNLOCK => call_unlock,
+    SVC_SEND => call_send,
+    _ => call_receive,
+  }
+}
+
+/// SVCall handler, given the frame stacked by hardware and EXC_RETURN.
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn svc_dispatch(frame: &mut sched::SavedState,
+    exc_return: u32) {
+  let number = *((frame.pc - 2) as *const u8);
+  // Handlers and the code before `task::setup` use the main stack.
+  let from_task = exc_return & 4 != 0;
+  if number == SVC_RAW {
+    if !from_task || sched::thread_privileged() {
+      let f: fn(u32) = transmute(frame.r0);
+      f(frame.r1);
+    } else {
+      kill_current_task(0);
+    }
+    return;
+  }
+
+  if !from_task || !Tasks.started() {
+    frame.r0 = RESULT_INVALID;
+    return;
+  }
+
+  if number == SVC_RETURN {
+    let trampoline = svc_return_trampoline as u32 & !1;
+    let task = Tasks.current_task();
+    if !task.in_syscall || frame.pc - 2 != trampoline {
+      kill_current_task(0);
+      return;
+    }
+    task.in_syscall = false;
+    frame.pc = task.syscall_return;
+    frame.lr = task.syscall_lr;
+    sched::set_thread_privileged(!task.unprivileged);
+    return;
+  }
+
+  if number > SVC_RECEIVE || Tasks.current_task().in_syscall {
+    kill_current_task(0);
+    return;
+  }
+  if !valid_args(number, frame) {
+    frame.r0 = RESULT_INVALID;
+    return;
+  }
+
+  let task = Tasks.current_task();
+  task.in_syscall = true;
+  task.syscall_return = frame.pc;
+  task.syscall_lr = frame.lr;
+  frame.pc = kernel_fn(number) as u32 & !1;
+  frame.lr = svc_return_trampoline as u32;
+  sched::set_thread_privileged(true);
+}
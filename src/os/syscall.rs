@@ -17,7 +17,12 @@
 Syscall interface.
 
 This module provides syscall interface that is implemented in assembly due to
-current rust restrictions (see hal/cortex_m3/sched.S for actual implementation).
+current rust restrictions (see hal/cortex_common/sched.rs for actual
+implementation).
+
+`syscall` runs `f` in the SVCall handler, so it's only available to privileged
+code; an unprivileged task calling it is killed. Tasks use the numbered calls
+in `os::svc` instead.
 */
 
 extern {
@@ -13,15 +13,88 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Basic multitasking interface.
+/*!
+Preemptive multitasking.
 
-use core::mem::size_of;
-use core::intrinsics::abort;
+Tasks have fixed priorities, higher values being more urgent. The scheduler
+always runs the most urgent runnable task; tasks of equal priority share the
+CPU in round robin, switching every time slice. When no task can run, the
+idle task sleeps until the next interrupt.
 
-use hal::cortex_m3::{sched, systick};
-use hal::cortex_m3::sched::NoInterrupts;
-use os::syscall::syscall;
+Mutexes, condition variables and other blocking objects queue their waiting
+tasks in a `WaitQueue`, optionally with a timeout. Semaphores and event flags
+instead queue a `Waiter` node from the waiting task's stack in a
+`util::queue::Queue`, and pair `TasksCollection::block_until` with `unblock`.
+
+A task can sleep for a number of system ticks; sleeping tasks are kept in a
+queue ordered by wake up tick, which is checked on every tick. `delay_until`
+wakes up relative to the previous wake up rather than to the current time, so
+periodic loops don't drift:
+
+```
+let mut wake = task::ticks();
+loop {
+  control();
+  task::delay_until(&mut wake, task::ms_to_ticks(10));
+}
+```
+
+Task stacks are painted with a fill pattern when defined, and the lowest word
+of each holds a canary. The scheduler checks the canary every time it switches
+away from a task, killing the task if it was overwritten; `stack_usage` and
+`stack_report` use the remaining paint to find how deep each stack has been
+used.
+
+The scheduler counts how many times each task was switched in, and the core
+clock cycles it ran for, from the DWT cycle counter (from SysTick on
+Cortex-M0, which has no DWT). `task_stats` and
+`cpu_load` give those numbers, and `top` prints them as a table. Run time is
+measured between context switches, which must be less than 2^32 cycles apart.
+
+Tasks defined with `define_unprivileged_task` run in unprivileged thread
+mode, and can only reach the kernel through the system calls in `os::svc`.
+While such a task runs, MPU regions from `FIRST_TASK_REGION` on give it access
+to its own stack and to the regions it was defined with; memory shared by all
+tasks, such as flash, is left to lower numbered regions, and the stack guard,
+if any, should use the region after the task ones.
+
+With tickless idle, the idle task stops SysTick interrupts until the first
+tick a sleeping task or a timer of `os::timer` needs, and catches up with the
+ticks missed when woken up; a low power sleep function can take over, for
+sleep modes that stop SysTick.
+
+SysTick provides both the time slice and the system tick of `hal::tick`.
+Context switches are done in PendSV, see `hal::cortex_common::sched`.
+*/
+
+use core::cell::UnsafeCell;
+use core::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+use core::fmt;
+use core::marker::Sync;
+use core::mem::{size_of, drop};
+use core::intrinsics::{abort, volatile_load, volatile_store};
+use core::option::Option::{self, Some, None};
+use core::result::Result::Ok;
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::{irq, sched, scb, systick};
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::{dwt, irq, mpu, sched};
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::{dwt, irq, mpu, sched};
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::{dwt, irq, mpu, sched};
+use drivers::chario::CharIO;
+use hal::power;
 use hal::stack;
+use hal::tick;
+use os::mutex::RawMutex;
+use os::svc;
+use os::syscall::syscall;
+use os::timer;
+use util::strconv;
+
+use self::Status::*;
 
 /// Task takes one argument, which is u32.
 pub type Task = fn(u32);
@@ -42,81 +115,642 @@ mod current_stack_offset {
 /// Bytes to reserve in privileged stack based on stack size at the time of task::setup() call.
 static ReservedPivilegedStackSize: u32 = 256;
 
-/// Maximum number of tasks.
-static MaxTasksCount: usize = 4;
+/// Fill pattern of unused task stack.
+const STACK_PAINT: u32 = 0xa5a5_a5a5;
 
-mod defined_tasks_count {
-  use core::intrinsics::abort;
+/// Value of the lowest word of a task stack, overwritten on overflow.
+const STACK_CANARY: u32 = 0xdead_c0de;
 
-  /// Total defined tasks count.
-  static mut DefinedTasksCount: usize = 0;
+/// First MPU region used for unprivileged tasks; it covers the task stack.
+pub const FIRST_TASK_REGION: u8 = 3;
 
-  pub fn get() -> usize {
-    unsafe { DefinedTasksCount }
-  }
+/// Maximum number of MPU regions an unprivileged task can be defined with,
+/// in addition to its stack.
+pub const MAX_TASK_REGIONS: usize = 3;
 
-  pub fn increase() {
-    unsafe {
-      DefinedTasksCount += 1;
-      if DefinedTasksCount > super::MaxTasksCount {
-        abort();
-      }
-    }
-  }
+/// MPU regions an unprivileged task can access.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+pub type TaskRegions = &'static [mpu::Region];
+
+/// Cortex-M0 has no MPU, nor unprivileged tasks.
+#[cfg(feature = "cpu_cortex-m0")]
+pub type TaskRegions = &'static [()];
+
+/// Scheduler configuration.
+#[derive(Clone, Copy)]
+pub struct SchedulerConf {
+  /// Core clock frequency in Hz, SysTick is derived from it.
+  pub core_frequency: u32,
+  /// System tick rate in Hz.
+  pub tick_rate: u32,
+  /// Number of ticks a task runs before the next task of the same priority
+  /// gets its turn.
+  pub time_slice: u32,
+  /// Stack size of the idle task, it must fit an interrupt frame.
+  pub idle_stack_size: u32,
+  /// Stops SysTick interrupts while idle, until a task or timer needs to run.
+  pub tickless_idle: bool,
+  /// Sleeps, with tickless idle, for at most the given number of ticks in a
+  /// low power mode, e.g. deep sleep with a wake up timer, and returns the
+  /// number of whole ticks slept. SysTick is stopped meanwhile. When None,
+  /// the idle hook from `hal::power` runs, woken up by SysTick.
+  pub low_power_sleep: Option<fn(u32) -> u32>,
 }
 
+/// Task state.
+#[derive(Clone, Copy, PartialEq)]
+#[allow(missing_docs)]
 pub enum Status {
   Runnable,
-  Blocked
+  Blocked,
+  Sleeping,
 }
 
 /// Task descriptor, provides task stack pointer.
+#[derive(Clone, Copy)]
+#[allow(missing_docs)]
 pub struct TaskDescriptor {
+  pub name: &'static str,
   pub stack_start: u32,
   pub stack_end: u32,
-  pub status: Status
+  /// Top of the stack, where the initial frame is.
+  pub stack_base: u32,
+  /// Usable stack size, in bytes.
+  pub stack_size: u32,
+  /// Set if the task was killed for overwriting its stack canary.
+  pub overflowed: bool,
+  pub status: Status,
+  /// Effective priority, raised while holding a mutex a more urgent task
+  /// waits for.
+  pub priority: u8,
+  /// Priority the task was defined with.
+  pub base_priority: u8,
+  /// First of the mutexes held, linked through the mutexes themselves.
+  pub held_mutexes: Option<*const RawMutex>,
+  /// Mutex the task is blocked on, if any.
+  pub blocked_on: Option<*const RawMutex>,
+  /// Tick to wake up on, when sleeping.
+  pub wake_at: u64,
+  /// Next task in the sleep queue.
+  next_sleeper: Option<usize>,
+  /// Next task in the wait queue the task is blocked on.
+  next_waiter: Option<usize>,
+  /// Number of times the task was switched in.
+  pub run_count: u32,
+  /// Core clock cycles the task ran for.
+  pub run_time: u64,
+  /// Runs in unprivileged thread mode.
+  pub unprivileged: bool,
+  /// Regions accessible to an unprivileged task, besides its stack.
+  pub regions: TaskRegions,
+  /// Set while the task runs a system call, privileged.
+  pub in_syscall: bool,
+  /// Where to return to from the system call.
+  pub syscall_return: u32,
+  /// Link register of the system call caller.
+  pub syscall_lr: u32,
 }
 
+/// An unused task slot, to initialize the task storage passed to `setup`.
+pub const EMPTY_TASK: TaskDescriptor = TaskDescriptor {
+  name: "",
+  stack_start: 0,
+  stack_end: 0,
+  stack_base: 0,
+  stack_size: 0,
+  overflowed: false,
+  status: Blocked,
+  priority: 0,
+  base_priority: 0,
+  held_mutexes: None,
+  blocked_on: None,
+  wake_at: 0,
+  next_sleeper: None,
+  next_waiter: None,
+  run_count: 0,
+  run_time: 0,
+  unprivileged: false,
+  regions: &[],
+  in_syscall: false,
+  syscall_return: 0,
+  syscall_lr: 0,
+};
+
 impl TaskDescriptor {
-  pub fn block(&mut self, _: NoInterrupts) {
+  /// Blocks the task and switches to another one when the critical section
+  /// ends.
+  pub fn block(&mut self, _: irq::NoInterrupts) {
     self.status = Blocked;
     sched::switch_context();
   }
-  pub fn unblock(&mut self, _: &NoInterrupts) { self.status = Runnable; }
+
+  /// Makes the task runnable again. It doesn't preempt the current task, see
+  /// `TasksCollection::wake` for that.
+  pub fn unblock(&mut self, _: &irq::NoInterrupts) { self.status = Runnable; }
 }
 
-struct TasksCollection {
+/// All the tasks, and the state of the scheduler.
+pub struct TasksCollection {
+  /// Index of the running task.
   pub current_task: usize,
-  pub tasks: [TaskDescriptor; MaxTasksCount],
+  tasks: Option<&'static mut [TaskDescriptor]>,
+  count: usize,
+  idle: usize,
+  time_slice: u32,
+  slice_left: u32,
+  started: bool,
+  sleepers: Option<usize>,
+  switched_at: u32,
+  protected: bool,
+  tickless: bool,
+  low_power_sleep: Option<fn(u32) -> u32>,
 }
 
+#[allow(missing_docs)]
 pub static mut Tasks: TasksCollection = TasksCollection {
   current_task: 0,
-  tasks: [TaskDescriptor { stack_start: 0, stack_end: 0, status: Runnable }; MaxTasksCount]
+  tasks: None,
+  count: 0,
+  idle: 0,
+  time_slice: 1,
+  slice_left: 1,
+  started: false,
+  sleepers: None,
+  switched_at: 0,
+  protected: false,
+  tickless: false,
+  low_power_sleep: None,
 };
 
 impl TasksCollection {
+  /// Returns true once the scheduler runs.
+  pub fn started(&self) -> bool {
+    self.started
+  }
+
+  /// Returns the descriptor of the running task.
   pub fn current_task<'a>(&'a mut self) -> &'a mut TaskDescriptor {
-    &mut self.tasks[self.current_task]
+    let current = self.current_task;
+    self.task(current)
   }
 
+  /// Returns the descriptor of task `index`.
+  pub fn task<'a>(&'a mut self, index: usize) -> &'a mut TaskDescriptor {
+    match self.tasks {
+      Some(ref mut tasks) => &mut tasks[index],
+      None => unsafe { abort() },
+    }
+  }
+
+  /// Makes task `index` runnable, switching to it when the critical section
+  /// ends if it's more urgent than the running one.
+  pub fn wake(&mut self, index: usize, crit: &irq::NoInterrupts) {
+    self.task(index).unblock(crit);
+    let priority = self.task(index).priority;
+    if self.current_task == self.idle || priority > self.current_task().priority {
+      sched::switch_context();
+    }
+  }
+
+  /// Blocks the running task until `unblock` is called for it or, if
+  /// `wake_at` is given, until that tick, switching to another task when the
+  /// critical section ends.
+  pub fn block_until(&mut self, wake_at: Option<u64>,
+      crit: irq::NoInterrupts) {
+    match wake_at {
+      Some(tick) => {
+        let current = self.current_task;
+        self.enqueue_sleeper(current, tick);
+      },
+      None => (),
+    }
+    self.current_task().block(crit);
+  }
+
+  /// Wakes up task `index` blocked by `block_until`, cancelling its timeout.
+  pub fn unblock(&mut self, index: usize, crit: &irq::NoInterrupts) {
+    self.remove_sleeper(index);
+    self.wake(index, crit);
+  }
+
+  /// Puts the running task to sleep until tick `wake_at`, switching to another
+  /// task when the critical section ends.
+  fn sleep(&mut self, wake_at: u64, _crit: irq::NoInterrupts) {
+    let current = self.current_task;
+    self.current_task().status = Sleeping;
+    self.enqueue_sleeper(current, wake_at);
+    sched::switch_context();
+  }
+
+  /// Adds task `index` to the sleep queue, to be woken up on tick `wake_at`.
+  fn enqueue_sleeper(&mut self, index: usize, wake_at: u64) {
+    self.task(index).wake_at = wake_at;
+
+    // Keep the queue ordered, tasks waking on the same tick in FIFO order.
+    let mut prev: Option<usize> = None;
+    let mut next = self.sleepers;
+    loop {
+      let i = match next {
+        Some(i) => i,
+        None => break,
+      };
+      if self.task(i).wake_at > wake_at {
+        break;
+      }
+      prev = next;
+      next = self.task(i).next_sleeper;
+    }
+    self.task(index).next_sleeper = next;
+    match prev {
+      Some(p) => self.task(p).next_sleeper = Some(index),
+      None => self.sleepers = Some(index),
+    }
+  }
+
+  /// Removes task `index` from the sleep queue, if it's there.
+  fn remove_sleeper(&mut self, index: usize) {
+    let mut prev: Option<usize> = None;
+    let mut next = self.sleepers;
+    loop {
+      let i = match next {
+        Some(i) => i,
+        None => return,
+      };
+      if i == index {
+        break;
+      }
+      prev = next;
+      next = self.task(i).next_sleeper;
+    }
+    let after = self.task(index).next_sleeper;
+    match prev {
+      Some(p) => self.task(p).next_sleeper = after,
+      None => self.sleepers = after,
+    }
+    self.task(index).next_sleeper = None;
+  }
+
+  /// Wakes up tasks whose sleep ends on tick `now`.
+  fn wake_sleepers(&mut self, now: u64, crit: &irq::NoInterrupts) {
+    loop {
+      let i = match self.sleepers {
+        Some(i) => i,
+        None => break,
+      };
+      if self.task(i).wake_at > now {
+        break;
+      }
+      self.sleepers = self.task(i).next_sleeper;
+      self.task(i).next_sleeper = None;
+      self.wake(i, crit);
+    }
+  }
+
+  /// Returns the first tick a sleeping task or a timer needs.
+  fn next_wake(&mut self) -> Option<u64> {
+    let sleeper = match self.sleepers {
+      Some(i) => Some(self.task(i).wake_at),
+      None => None,
+    };
+    match (sleeper, timer::next_expiry()) {
+      (Some(s), Some(t)) => Some(if s < t { s } else { t }),
+      (Some(s), None) => Some(s),
+      (None, t) => t,
+    }
+  }
+
+  /// Returns true if a task other than idle can run.
+  fn any_runnable(&mut self) -> bool {
+    for i in 0..self.count {
+      let task = *self.task(i);
+      if i != self.idle && task.valid() && task.status == Runnable {
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Picks the most urgent runnable task, starting the search after the
+  /// current one so that tasks of the same priority take turns.
   fn next_task(&mut self) {
+    let count = self.count;
+    let current = self.current_task;
+    let idle = self.idle;
+    let mut best: Option<usize> = None;
+    let mut best_priority = 0;
+
+    for n in 1..count + 1 {
+      let i = (current + n) % count;
+      let task = *self.task(i);
+      if i == idle || !task.valid() || task.status != Runnable {
+        continue;
+      }
+      if best.is_none() || task.priority > best_priority {
+        best = Some(i);
+        best_priority = task.priority;
+      }
+    }
+
+    self.current_task = match best {
+      Some(i) => i,
+      None => idle,
+    };
+    self.slice_left = self.time_slice;
+  }
+
+  fn add_task(&mut self, t: TaskDescriptor) -> Option<usize> {
+    let index = self.count;
+    let capacity = match self.tasks {
+      Some(ref tasks) => tasks.len(),
+      None => 0,
+    };
+    if index == capacity {
+      return None;
+    }
+    *self.task(index) = t;
+    self.count += 1;
+    Some(index)
+  }
+
+  /// Wakes up sleeping tasks and counts a tick of the running task's time
+  /// slice.
+  fn tick(&mut self, now: u64) {
+    if !self.started {
+      return;
+    }
+    {
+      let crit = irq::NoInterrupts::new();
+      self.wake_sleepers(now, &crit);
+    }
+    if self.slice_left > 1 {
+      self.slice_left -= 1;
+    } else {
+      self.slice_left = self.time_slice;
+      sched::switch_context();
+    }
+  }
+}
+
+/// A task blocked in a `util::queue::Queue`. Waiters compare by priority, so
+/// that `Queue::insert` keeps the most urgent first.
+#[derive(Clone, Copy)]
+pub struct Waiter {
+  /// Index of the waiting task.
+  pub index: usize,
+  /// Priority the task waits with.
+  pub priority: u8,
+}
+
+impl Waiter {
+  /// Returns the waiter for the running task.
+  pub fn current(_: &irq::NoInterrupts) -> Waiter {
+    unsafe {
+      Waiter {
+        index: Tasks.current_task,
+        priority: Tasks.current_task().priority,
+      }
+    }
+  }
+}
+
+impl PartialEq for Waiter {
+  fn eq(&self, other: &Waiter) -> bool {
+    self.priority == other.priority
+  }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+  fn partial_cmp(&self, other: &Waiter) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Waiter {
+  fn cmp(&self, other: &Waiter) -> Ordering {
+    self.priority.cmp(&other.priority)
+  }
+}
+
+/// Tasks blocked on a synchronization object, most urgent first and in FIFO
+/// order among equal priorities.
+pub struct WaitQueue {
+  head: UnsafeCell<Option<usize>>,
+}
+
+impl WaitQueue {
+  /// Creates an empty queue.
+  pub const fn new() -> WaitQueue {
+    WaitQueue { head: UnsafeCell::new(None) }
+  }
+
+  /// Returns the most urgent waiting task.
+  pub fn first(&self, _: &irq::NoInterrupts) -> Option<usize> {
+    unsafe { *self.head.get() }
+  }
+
+  /// Blocks the running task until it's woken up from the queue or, if
+  /// `wake_at` is given, until that tick.
+  ///
+  /// The critical section ends once the task is queued, so a wake up can't be
+  /// missed. Returns false on timeout.
+  pub fn wait(&self, wake_at: Option<u64>, crit: irq::NoInterrupts) -> bool {
+    unsafe {
+      let current = Tasks.current_task;
+      self.insert(current);
+      Tasks.current_task().status = Blocked;
+      match wake_at {
+        Some(tick) => Tasks.enqueue_sleeper(current, tick),
+        None => (),
+      }
+      sched::switch_context();
+    }
+    drop(crit);
+
+    // Still in the queue if the sleep queue woke us up.
+    let _crit = irq::NoInterrupts::new();
+    unsafe { !self.remove(Tasks.current_task) }
+  }
+
+  /// Removes the most urgent task from the queue, without waking it up.
+  pub fn pop(&self, _: &irq::NoInterrupts) -> Option<usize> {
+    unsafe {
+      let first = *self.head.get();
+      match first {
+        Some(i) => {
+          *self.head.get() = Tasks.task(i).next_waiter;
+          Tasks.task(i).next_waiter = None;
+          Tasks.remove_sleeper(i);
+        },
+        None => (),
+      }
+      first
+    }
+  }
+
+  /// Wakes up the most urgent waiting task. Returns false if there was none.
+  pub fn wake_one(&self, crit: &irq::NoInterrupts) -> bool {
+    match self.pop(crit) {
+      Some(i) => {
+        unsafe { Tasks.wake(i, crit) };
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// Wakes up all waiting tasks.
+  pub fn wake_all(&self, crit: &irq::NoInterrupts) {
+    while self.wake_one(crit) {}
+  }
+
+  /// Moves task `index` to its place in the queue after its priority changed.
+  pub fn reposition(&self, index: usize, _: &irq::NoInterrupts) {
+    unsafe {
+      if self.remove(index) {
+        self.insert(index);
+      }
+    }
+  }
+
+  unsafe fn insert(&self, index: usize) {
+    let priority = Tasks.task(index).priority;
+    let mut prev: Option<usize> = None;
+    let mut next = *self.head.get();
     loop {
-      self.current_task += 1;
-      if self.current_task == defined_tasks_count::get() {
-        self.current_task = 0;
+      let i = match next {
+        Some(i) => i,
+        None => break,
+      };
+      if Tasks.task(i).priority < priority {
+        break;
       }
-      match self.current_task() {
-        &task if !task.valid()                 => {}
-        &TaskDescriptor {status: Runnable, ..} => break,
-        _                                      => {}
+      prev = next;
+      next = Tasks.task(i).next_waiter;
+    }
+    Tasks.task(index).next_waiter = next;
+    match prev {
+      Some(p) => Tasks.task(p).next_waiter = Some(index),
+      None => *self.head.get() = Some(index),
+    }
+  }
+
+  unsafe fn remove(&self, index: usize) -> bool {
+    let mut prev: Option<usize> = None;
+    let mut next = *self.head.get();
+    loop {
+      let i = match next {
+        Some(i) => i,
+        None => return false,
+      };
+      if i == index {
+        break;
       }
+      prev = next;
+      next = Tasks.task(i).next_waiter;
+    }
+    let after = Tasks.task(index).next_waiter;
+    match prev {
+      Some(p) => Tasks.task(p).next_waiter = after,
+      None => *self.head.get() = after,
     }
+    Tasks.task(index).next_waiter = None;
+    true
   }
+}
+
+unsafe impl Sync for WaitQueue {}
 
-  fn add_task(&mut self, t: TaskDescriptor) {
-    self.tasks[defined_tasks_count::get()] = t;
-    defined_tasks_count::increase();
+/// Returns the index of the running task.
+pub fn current() -> usize {
+  unsafe { Tasks.current_task }
+}
+
+/// Gives the rest of the time slice to the next task of the same priority.
+pub fn yield_now() {
+  sched::switch_context();
+}
+
+/// Returns the number of system ticks since the scheduler started.
+pub fn ticks() -> u64 {
+  tick::ticks()
+}
+
+/// Converts milliseconds to system ticks, rounding up.
+pub fn ms_to_ticks(ms: u32) -> u32 {
+  ((ms as u64 * tick::rate() as u64 + 999) / 1000) as u32
+}
+
+/// Sleeps until the system tick count reaches `wake_at`.
+///
+/// Returns immediately if that tick has already passed.
+pub fn sleep_until(wake_at: u64) {
+  let crit = irq::NoInterrupts::new();
+  if wake_at > tick::ticks() {
+    unsafe { Tasks.sleep(wake_at, crit) };
+  }
+}
+
+/// Sleeps for `ticks` system ticks.
+///
+/// The current tick is already partly over, so the sleep may be shorter by up
+/// to a tick.
+pub fn sleep_ticks(ticks: u32) {
+  sleep_until(tick::ticks() + ticks as u64);
+}
+
+/// Sleeps for at least `ms` milliseconds.
+pub fn sleep_ms(ms: u32) {
+  // One more tick covers the part of the current tick already elapsed.
+  sleep_ticks(ms_to_ticks(ms) + 1);
+}
+
+/// Sleeps until `period` ticks after `*last_wake`, then advances `*last_wake`
+/// by `period`.
+///
+/// If the task fell behind, it doesn't sleep, so that it can catch up.
+pub fn delay_until(last_wake: &mut u64, period: u32) {
+  *last_wake += period as u64;
+  sleep_until(*last_wake);
+}
+
+fn idle(_: u32) {
+  loop {
+    if unsafe { Tasks.tickless } {
+      idle_tickless();
+    } else {
+      power::idle();
+    }
+  }
+}
+
+/// Sleeps with SysTick interrupts stopped until the next tick a task or timer
+/// needs, or any other interrupt.
+fn idle_tickless() {
+  let crit = irq::NoInterrupts::new();
+  unsafe {
+    if Tasks.any_runnable() {
+      return;
+    }
+    let now = tick::ticks();
+    let ticks = match Tasks.next_wake() {
+      Some(t) if t <= now + 1 => 1,
+      Some(t) if t - now < 0xffff_ffff => (t - now) as u32,
+      _ => 0xffff_ffff,
+    };
+
+    match Tasks.low_power_sleep {
+      Some(sleep) if ticks > 1 => {
+        tick::suspend();
+        let slept = sleep(ticks);
+        tick::resume(slept);
+      },
+      _ => {
+        tick::sleep_for(ticks, power::idle);
+      },
+    }
+    Tasks.wake_sleepers(tick::ticks(), &crit);
   }
 }
 
@@ -125,10 +759,18 @@ impl TasksCollection {
 /// This function keeps main stack intact. It starts the task scheduler and
 /// never returns.
 ///
-/// t should point to initial task.
+/// `tasks` holds descriptors for all the tasks, including `t` (the initial
+/// task) and the idle task, so its length sets the task limit.
 #[inline(never)]
-pub fn setup(t: Task, stack_size: u32) {
-  systick::setup(::hal::cortex_m3::systick::CALIBRATED, true);
+pub fn setup(conf: &SchedulerConf, tasks: &'static mut [TaskDescriptor],
+    t: Task, name: &'static str, priority: u8, stack_size: u32) {
+  unsafe {
+    Tasks.tasks = Some(tasks);
+    Tasks.time_slice = if conf.time_slice == 0 { 1 } else { conf.time_slice };
+    Tasks.slice_left = Tasks.time_slice;
+    Tasks.tickless = conf.tickless_idle;
+    Tasks.low_power_sleep = conf.low_power_sleep;
+  }
 
   let current_stack = sched::get_current_stack_pointer();
   // User tasks start at this current stack size + reserved size aligned by 4
@@ -136,86 +778,561 @@ pub fn setup(t: Task, stack_size: u32) {
   let task_stack_base: u32 = (current_stack as u32 - ReservedPivilegedStackSize) & !3;
   current_stack_offset::set(task_stack_base);
 
-  let td = define_task(t, 0, stack_size, true);
+  let initial = match add_task(t, 0, name, priority, stack_size) {
+    Some(i) => i,
+    None => unsafe { abort() },
+  };
+  match add_task(idle, 0, "idle", 0, conf.idle_stack_size) {
+    Some(i) => unsafe { Tasks.idle = i },
+    None => unsafe { abort() },
+  }
 
-  td.load();
+  unsafe {
+    Tasks.current_task = initial;
+    Tasks.current_task().load();
+    Tasks.current_task().run_count = 1;
+    Tasks.started = true;
+  }
 
-  systick::enable();
+  sched::set_priorities();
+  tick::TickConf {
+    core_frequency: conf.core_frequency,
+    rate: conf.tick_rate,
+  }.setup();
+  sched::enable_fpu();
+  start_cycle_count();
+  unsafe { Tasks.switched_at = cycle_count() };
   sched::switch_context();
 
   unsafe { abort() };
 }
 
+/// Defines a new task, which can run as soon as the critical section ends.
+///
+/// Returns the task index, or None if the task storage is full.
 #[inline(never)]
-pub fn define_task(t: Task, arg: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
-  systick::disable_irq();
+pub fn define_task(t: Task, arg: u32, name: &'static str, priority: u8,
+    stack_size: u32) -> Option<usize> {
+  let crit = irq::NoInterrupts::new();
+  let index = add_task(t, arg, name, priority, stack_size);
+  match index {
+    Some(i) => unsafe { Tasks.wake(i, &crit) },
+    None => (),
+  }
+  index
+}
+
+fn add_task(t: Task, arg: u32, name: &'static str, priority: u8,
+    stack_size: u32) -> Option<usize> {
   let task_base = current_stack_offset::get();
   let task_stack_size: u32 = (
     stack_size +
     8*4 +  // hw saved regs
-    8*4 +  // sw saved regs
+    sched::SOFTWARE_FRAME_SIZE +
     8*4    // scratch pad for __morestack failure. see note on morestack below.
   ) & !0b1111;
-  current_stack_offset::set(task_base - task_stack_size);
 
-  let td = TaskDescriptor::new(t, arg, task_base, stack_size, initial);
-  unsafe { Tasks.add_task(td) };
-
-  systick::enable_irq();
-  td
+  let td = TaskDescriptor::new(t, arg, name, task_base, stack_size, priority);
+  let index = unsafe { Tasks.add_task(td) };
+  if index.is_some() {
+    current_stack_offset::set(task_base - task_stack_size);
+  }
+  index
 }
 
 impl TaskDescriptor {
   /// Creates a new TaskDescriptor for given task, arg and stack base.
   ///
-  /// This function initializes task stack with hw saved registers.
+  /// This function initializes task stack with hw and sw saved registers.
   #[inline(never)]
-  pub fn new(t: Task, arg: u32, stack_base: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
+  pub fn new(t: Task, arg: u32, name: &'static str, stack_base: u32,
+      stack_size: u32, priority: u8) -> TaskDescriptor {
     let state = sched::SavedState::new(t, arg);
 
     let mut stack_top: u32 = stack_base - size_of::<sched::SavedState>() as u32;
-    unsafe { *(stack_top as *mut sched::SavedState) = state };
-    if !initial {
-      stack_top -= 8*4;
+    paint_stack(stack_base - stack_size, stack_top);
+    unsafe {
+      *(stack_top as *mut sched::SavedState) = state;
+      stack_top = sched::push_software_frame(stack_top);
     }
 
     TaskDescriptor {
+      name: name,
       stack_start: stack_top,
       stack_end: stack_base - stack_size,
+      stack_base: stack_base,
+      stack_size: stack_size,
+      overflowed: false,
       status: Runnable,
+      priority: priority,
+      base_priority: priority,
+      held_mutexes: None,
+      blocked_on: None,
+  blocked_on: None,
+      wake_at: 0,
+      next_sleeper: None,
+      next_waiter: None,
+      run_count: 0,
+      run_time: 0,
+      unprivileged: false,
+      regions: &[],
+      in_syscall: false,
+      syscall_return: 0,
+      syscall_lr: 0,
     }
   }
 
+  /// Makes this the task running on PSP.
   pub fn load(&self) {
     sched::set_task_stack_pointer(self.stack_start);
     stack::set_stack_limit(self.stack_end);
+    if unsafe { Tasks.protected } {
+      load_task_regions(self);
+      sched::set_thread_privileged(!self.unprivileged || self.in_syscall);
+    }
+    move_stack_guard(self.stack_end);
   }
 
+  /// Saves the stack pointer of a preempted task.
   pub fn save(&mut self) {
     self.stack_start = sched::get_task_stack_pointer();
   }
 
+  /// Returns false if the task was killed.
   pub fn valid(&self) -> bool {
     self.stack_end != 0
   }
 
+  /// Marks the task killed.
   pub fn invalidate(&mut self) {
     self.stack_end = 0;
   }
+
+  /// Returns true if the task can access `len` bytes at `addr`, for writing
+  /// if `write` is set.
+  #[cfg(not(feature = "cpu_cortex-m0"))]
+  pub fn can_access(&self, addr: u32, len: u32, write: bool) -> bool {
+    if !self.unprivileged {
+      return true;
+    }
+    let end = match addr.checked_add(len) {
+      Some(e) => e,
+      None => return false,
+    };
+    if addr >= self.stack_end && end <= self.stack_base {
+      return true;
+    }
+    for region in self.regions.iter() {
+      let allowed = match region.access {
+        mpu::Access::ReadWrite => true,
+        mpu::Access::UnprivilegedReadOnly | mpu::Access::ReadOnly => !write,
+        _ => false,
+      };
+      let region_end = region.base as u64 +
+          if region.size == 0 { 1 << 32 } else { region.size as u64 };
+      if allowed && region.subregion_disable == 0 && addr >= region.base &&
+          end as u64 <= region_end {
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Returns true if the task can access `len` bytes at `addr`; Cortex-M0
+  /// tasks are all privileged.
+  #[cfg(feature = "cpu_cortex-m0")]
+  pub fn can_access(&self, _: u32, _: u32, _: bool) -> bool {
+    true
+  }
+
+  /// Returns false if the stack canary was overwritten.
+  pub fn stack_intact(&self) -> bool {
+    !self.valid() ||
+        unsafe { volatile_load(self.stack_end as *const u32) } == STACK_CANARY
+  }
+
+  /// Returns the deepest stack usage so far, in bytes.
+  pub fn stack_high_water_mark(&self) -> u32 {
+    let bottom = self.stack_base - self.stack_size;
+    // The canary is never part of the painted area.
+    let mut addr = bottom + 4;
+    while addr < self.stack_base &&
+        unsafe { volatile_load(addr as *const u32) } == STACK_PAINT {
+      addr += 4;
+    }
+    if unsafe { volatile_load(bottom as *const u32) } != STACK_CANARY {
+      self.stack_size
+    } else {
+      self.stack_base - addr
+    }
+  }
+}
+
+/// Defines a new task running in unprivileged thread mode, which can run as
+/// soon as the critical section ends.
+///
+/// `stack_size` covers the saved registers too, and must be a power of two of
+/// at least 256 bytes; the stack is aligned to it so that a single MPU region
+/// covers it. The task can also access the given `regions`, at most
+/// `MAX_TASK_REGIONS` of them.
+///
+/// The MPU is enabled if it isn't yet, with the default memory map as
+/// background for privileged code.
+///
+/// Returns the task index, or None if the task storage is full, the stack
+/// size or regions are invalid, or the MPU has too few regions.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+#[inline(never)]
+pub fn define_unprivileged_task(t: Task, arg: u32, name: &'static str,
+    priority: u8, stack_size: u32, regions: &'static [mpu::Region])
+    -> Option<usize> {
+  if stack_size < 256 || stack_size & (stack_size - 1) != 0 ||
+      regions.len() > MAX_TASK_REGIONS ||
+      mpu::regions() < FIRST_TASK_REGION + MAX_TASK_REGIONS as u8 + 1 {
+    return None;
+  }
+
+  let crit = irq::NoInterrupts::new();
+  let task_base = current_stack_offset::get() & !(stack_size - 1);
+  // The morestack scratch pad stays within the region.
+  let mut td = TaskDescriptor::new(t, arg, name, task_base, stack_size - 8*4,
+      priority);
+  td.unprivileged = true;
+  td.regions = regions;
+
+  let index = unsafe { Tasks.add_task(td) };
+  match index {
+    Some(i) => unsafe {
+      current_stack_offset::set(task_base - stack_size);
+      if !mpu::is_enabled() {
+        mpu::enable(true, false);
+      }
+      Tasks.protected = true;
+      Tasks.wake(i, &crit);
+    },
+    None => (),
+  }
+  index
 }
 
+/// Gives an unprivileged task access to its stack and regions, and disables
+/// the task regions for a privileged one, which uses the default memory map.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn load_task_regions(task: &TaskDescriptor) {
+  if !task.unprivileged {
+    for n in 0..MAX_TASK_REGIONS + 1 {
+      mpu::disable_region(FIRST_TASK_REGION + n as u8);
+    }
+    return;
+  }
+
+  // Covers the morestack scratch pad below `stack_end` too.
+  let size = task.stack_size + 8*4;
+  let _ = mpu::set_region(FIRST_TASK_REGION, &mpu::Region {
+    base: task.stack_base - size,
+    size: size,
+    subregion_disable: 0,
+    access: mpu::Access::ReadWrite,
+    executable: false,
+    attributes: mpu::Attributes::WriteBack,
+    shareable: false,
+  });
+  for n in 0..MAX_TASK_REGIONS {
+    let number = FIRST_TASK_REGION + 1 + n as u8;
+    if n < task.regions.len() {
+      let _ = mpu::set_region(number, &task.regions[n]);
+    } else {
+      mpu::disable_region(number);
+    }
+  }
+}
+
+#[cfg(feature = "cpu_cortex-m0")]
+fn load_task_regions(_: &TaskDescriptor) {}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn move_stack_guard(addr: u32) {
+  mpu::move_stack_guard(addr);
+}
+
+/// Cortex-M0 has no MPU.
+#[cfg(feature = "cpu_cortex-m0")]
+fn move_stack_guard(_: u32) {}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn start_cycle_count() {
+  dwt::enable_cycle_counter();
+}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn cycle_count() -> u32 {
+  dwt::cycle_count()
+}
+
+#[cfg(feature = "cpu_cortex-m0")]
+fn start_cycle_count() {}
+
+/// Counts core clock cycles from system ticks and the SysTick counter, which
+/// must be running on the core clock.
+///
+/// A tick that hasn't been handled yet is counted if SysTick is pending.
+#[cfg(feature = "cpu_cortex-m0")]
+fn cycle_count() -> u32 {
+  let period = systick::get_reload() + 1;
+  let mut ticks = tick::ticks() as u32;
+  let mut current = systick::get_current();
+  if scb::is_systick_pending() {
+    ticks = ticks.wrapping_add(1);
+    current = systick::get_current();
+  }
+  ticks.wrapping_mul(period).wrapping_add(period - 1 - current)
+}
+
+/// Fills the stack between `bottom` and `top` with the paint pattern, and puts
+/// the canary at `bottom`.
+fn paint_stack(bottom: u32, top: u32) {
+  let mut addr = bottom + 4;
+  while addr < top {
+    unsafe { volatile_store(addr as *mut u32, STACK_PAINT) };
+    addr += 4;
+  }
+  unsafe { volatile_store(bottom as *mut u32, STACK_CANARY) };
+}
+
+/// Stack usage of a task.
+#[derive(Clone, Copy)]
+pub struct StackUsage {
+  /// Usable stack size, in bytes.
+  pub size: u32,
+  /// Deepest usage so far, in bytes.
+  pub high_water_mark: u32,
+  /// True if the task was killed for overflowing its stack.
+  pub overflowed: bool,
+}
+
+/// Returns the stack usage of task `index`, or None if there's no such task.
+pub fn stack_usage(index: usize) -> Option<StackUsage> {
+  let _crit = irq::NoInterrupts::new();
+  unsafe {
+    if index >= Tasks.count {
+      return None;
+    }
+    let task = Tasks.task(index);
+    Some(StackUsage {
+      size: task.stack_size,
+      high_water_mark: task.stack_high_water_mark(),
+      overflowed: task.overflowed,
+    })
+  }
+}
+
+/// Writes the stack size and high water mark of every task, a line per task.
+pub fn stack_report(out: &mut fmt::Write) -> fmt::Result {
+  let mut index = 0;
+  loop {
+    let usage = match stack_usage(index) {
+      Some(u) => u,
+      None => return Ok(()),
+    };
+    try!(write!(out, "task {}: {}/{} bytes{}\n", index, usage.high_water_mark,
+        usage.size, if usage.overflowed { " OVERFLOW" } else { "" }));
+    index += 1;
+  }
+}
+
+/// Scheduling statistics of a task.
+#[derive(Clone, Copy)]
+pub struct TaskStats {
+  /// Name the task was defined with.
+  pub name: &'static str,
+  /// Effective priority.
+  pub priority: u8,
+  /// Current state.
+  pub status: Status,
+  /// False if the task was killed.
+  pub alive: bool,
+  /// Number of times the task was switched in.
+  pub run_count: u32,
+  /// Core clock cycles the task ran for.
+  pub run_time: u64,
+}
+
+/// Returns the statistics of task `index`, or None if there's no such task.
+pub fn task_stats(index: usize) -> Option<TaskStats> {
+  let _crit = irq::NoInterrupts::new();
+  unsafe {
+    if index >= Tasks.count {
+      return None;
+    }
+    let task = Tasks.task(index);
+    Some(TaskStats {
+      name: task.name,
+      priority: task.priority,
+      status: task.status,
+      alive: task.valid(),
+      run_count: task.run_count,
+      run_time: task.run_time,
+    })
+  }
+}
+
+/// Returns the number of defined tasks, including the idle task.
+pub fn task_count() -> usize {
+  unsafe { Tasks.count }
+}
+
+/// Returns the total core clock cycles counted for all tasks.
+fn total_run_time() -> u64 {
+  let mut total = 0;
+  for i in 0..task_count() {
+    match task_stats(i) {
+      Some(stats) => total += stats.run_time,
+      None => (),
+    }
+  }
+  total
+}
+
+/// Returns the share of time the idle task ran, in percent.
+pub fn idle_percent() -> u32 {
+  let total = total_run_time();
+  if total == 0 {
+    return 100;
+  }
+  let idle = match task_stats(unsafe { Tasks.idle }) {
+    Some(stats) => stats.run_time,
+    None => 0,
+  };
+  (idle * 100 / total) as u32
+}
+
+/// Returns the share of time tasks other than idle ran, in percent.
+pub fn cpu_load() -> u32 {
+  100 - idle_percent()
+}
+
+/// Clears run counts and run times of all tasks.
+pub fn reset_stats() {
+  let _crit = irq::NoInterrupts::new();
+  unsafe {
+    for i in 0..Tasks.count {
+      Tasks.task(i).run_count = 0;
+      Tasks.task(i).run_time = 0;
+    }
+    Tasks.switched_at = cycle_count();
+  }
+}
+
+/// Prints a table of all tasks with their priority, state, run count, share
+/// of CPU time and stack usage, followed by the CPU load.
+pub fn top(out: &CharIO) {
+  let total = total_run_time();
+  let current = unsafe { Tasks.current_task };
+
+  out.puts("  #  NAME            PRI  STATE         RUNS  CPU%  STACK\n");
+  for i in 0..task_count() {
+    let stats = match task_stats(i) {
+      Some(s) => s,
+      None => break,
+    };
+    let state = if !stats.alive {
+      "dead"
+    } else if i == current {
+      "running"
+    } else {
+      match stats.status {
+        Runnable => "ready",
+        Blocked => "blocked",
+        Sleeping => "sleeping",
+      }
+    };
+    let percent = if total == 0 { 0 } else { stats.run_time * 100 / total };
+
+    put_right(out, i as u32, 3);
+    out.puts("  ");
+    put_left(out, stats.name, 15);
+    put_right(out, stats.priority as u32, 4);
+    out.puts("  ");
+    put_left(out, state, 8);
+    put_right(out, stats.run_count, 10);
+    put_right(out, percent as u32, 6);
+    out.puts("  ");
+    match stack_usage(i) {
+      Some(usage) => {
+        out.puti(usage.high_water_mark);
+        out.putc('/');
+        out.puti(usage.size);
+      },
+      None => (),
+    }
+    out.putc('\n');
+  }
+
+  out.puts("CPU load ");
+  out.puti(cpu_load());
+  out.puts("%, idle ");
+  out.puti(idle_percent());
+  out.puts("%\n");
+}
+
+fn put_left(out: &CharIO, s: &str, width: usize) {
+  out.puts(s);
+  for _ in s.len()..width {
+    out.putc(' ');
+  }
+}
+
+fn put_right(out: &CharIO, value: u32, width: usize) {
+  let mut buf = [0u8; 32];
+  strconv::itoa(value, &mut buf, 10);
+  let len = match buf.iter().position(|&c| c == 0) {
+    Some(l) => l,
+    None => buf.len(),
+  };
+  for _ in len..width {
+    out.putc(' ');
+  }
+  out.puti(value);
+}
+
+/// Switches tasks, called from PendSV.
 #[inline(always)]
 pub unsafe fn task_scheduler() {
-  stack::set_stack_limit(stack::stack_base() - ReservedPivilegedStackSize);
+  stack::set_stack_limit(stack::stack_base() as u32 - ReservedPivilegedStackSize);
+  let now = cycle_count();
+  let previous = Tasks.current_task;
+  let elapsed = now.wrapping_sub(Tasks.switched_at);
+  Tasks.current_task().run_time += elapsed as u64;
+  Tasks.switched_at = now;
+
   Tasks.current_task().save();
+  if !Tasks.current_task().stack_intact() {
+    if Tasks.current_task == Tasks.idle {
+      abort();
+    }
+    Tasks.current_task().overflowed = true;
+    kill(Tasks.current_task);
+  }
   Tasks.next_task();
+  if Tasks.current_task != previous {
+    Tasks.current_task().run_count += 1;
+  }
   Tasks.current_task().load();
 }
 
+/// SysTick handler, counts the system tick and the time slice.
+#[no_mangle]
+#[allow(missing_docs)]
+pub unsafe extern fn isr_systick() {
+  tick::tick();
+  Tasks.tick(tick::ticks());
+}
+
 // TODO(farcaller): this should not actually use stack!
 // At the time of the call of syscall(), the stack is overflown by 4, we still
 // have 12 bytes in reserve and 2*8*4 to save the frame in pendsv after kill.
 #[no_stack_check]
+#[allow(missing_docs)]
 pub fn morestack() {
   let psp = sched::get_task_stack_pointer();
   let sp = sched::get_current_stack_pointer();
@@ -229,7 +1346,17 @@ pub fn morestack() {
 #[inline(never)]
 #[no_mangle]
 #[no_stack_check]
+#[allow(missing_docs)]
 pub fn kill_current_task(_: u32) {
-  unsafe { Tasks.current_task().invalidate() };
+  unsafe { kill(Tasks.current_task) };
   sched::switch_context();
 }
+
+/// Marks the task `index` killed and unlocks the mutexes it holds through
+/// system calls. Mutexes locked with a guard on its stack stay locked.
+unsafe fn kill(index: usize) {
+  Tasks.task(index).invalidate();
+  if Tasks.task(index).held_mutexes.is_some() {
+    svc::release_mutexes(index);
+  }
+}
@@ -0,0 +1,445 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2015 zinc developers <http://zinc.rs>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+/*!
+Software timers.
+
+Timers count ticks of a single hardware time base, usually the system tick:
+register `process` with `hal::tick::add_hook`, or call it from any timer
+interrupt with a monotonic tick count. Timers are started relative to the
+current tick of the same time base. On expiry a timer calls its callback,
+then either stops (one-shot) or is rescheduled a period after its previous
+expiry, so periodic timers don't drift.
+
+Timers live in a fixed pool of `MAX_TIMERS`, returned to it by `delete`, and
+are kept in a timing wheel of `WHEEL_SIZE` slots, so a tick only looks at the
+timers that fall into its slot.
+Timers can be started and stopped from anywhere, including tasks and other
+timers' callbacks. Callbacks run in the context that calls `process`, with
+interrupts enabled; they should be short.
+*/
+
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+use util::shared::dummy_irq::NoInterrupts;
+
+/// Maximum number of timers.
+pub const MAX_TIMERS: usize = 16;
+
+/// Number of slots in the timing wheel.
+pub const WHEEL_SIZE: usize = 16;
+
+/// Function called when a timer expires.
+pub type Callback = fn(Timer);
+
+/// Handle to a software timer.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Timer(usize);
+
+#[derive(Clone, Copy)]
+struct Entry {
+  callback: Option<Callback>,
+  expires: u64,
+  interval: u32,
+  periodic: bool,
+  active: bool,
+  next: Option<usize>,
+}
+
+const FREE_ENTRY: Entry = Entry {
+  callback: None,
+  expires: 0,
+  interval: 0,
+  periodic: false,
+  active: false,
+  next: None,
+};
+
+struct Wheel {
+  entries: [Entry; MAX_TIMERS],
+  slots: [Option<usize>; WHEEL_SIZE],
+  /// The latest tick processed.
+  current: u64,
+  active: usize,
+}
+
+static mut TheWheel: Wheel = Wheel {
+  entries: [FREE_ENTRY; MAX_TIMERS],
+  slots: [None; WHEEL_SIZE],
+  current: 0,
+  active: 0,
+};
+
+fn slot_of(expires: u64) -> usize {
+  (expires % WHEEL_SIZE as u64) as usize
+}
+
+impl Wheel {
+  fn create(&mut self, callback: Callback) -> Option<Timer> {
+    for i in 0..MAX_TIMERS {
+      if self.entries[i].callback.is_none() {
+        self.entries[i] = FREE_ENTRY;
+        self.entries[i].callback = Some(callback);
+        return Some(Timer(i));
+      }
+    }
+    None
+  }
+
+  fn delete(&mut self, i: usize) {
+    self.stop(i);
+    self.entries[i] = FREE_ENTRY;
+  }
+
+  fn start(&mut self, i: usize, interval: u32, periodic: bool, now: u64) {
+    self.stop(i);
+    // `process` may not have caught up with `now` yet, e.g. before its first
+    // call. With no timers running there's nothing to catch up with.
+    if self.active == 0 && now > self.current {
+      self.current = now;
+    }
+    // A timer started during a tick expires on a later one.
+    let interval = if interval == 0 { 1 } else { interval };
+    self.entries[i].interval = interval;
+    self.entries[i].periodic = periodic;
+    let base = if now > self.current { now } else { self.current };
+    self.insert(i, base + interval as u64);
+  }
+
+  fn restart(&mut self, i: usize, now: u64) {
+    let (interval, periodic) = (self.entries[i].interval,
+                                self.entries[i].periodic);
+    if interval != 0 {
+      self.start(i, interval, periodic, now);
+    }
+  }
+
+  fn insert(&mut self, i: usize, expires: u64) {
+    let slot = slot_of(expires);
+    self.entries[i].expires = expires;
+    self.entries[i].active = true;
+    self.entries[i].next = self.slots[slot];
+    self.slots[slot] = Some(i);
+    self.active += 1;
+  }
+
+  fn stop(&mut self, i: usize) {
+    if !self.entries[i].active {
+      return;
+    }
+    let slot = slot_of(self.entries[i].expires);
+    let mut prev = None;
+    let mut cur = self.slots[slot];
+    loop {
+      match cur {
+        Some(j) if j == i => {
+          self.unlink(slot, prev, i);
+          return;
+        },
+        Some(j) => {
+          prev = cur;
+          cur = self.entries[j].next;
+        },
+        None => return,
+      }
+    }
+  }
+
+  fn unlink(&mut self, slot: usize, prev: Option<usize>, i: usize) {
+    let next = self.entries[i].next;
+    match prev {
+      Some(p) => self.entries[p].next = next,
+      None => self.slots[slot] = next,
+    }
+    self.entries[i].next = None;
+    self.entries[i].active = false;
+    self.active -= 1;
+  }
+
+  /// Removes the first timer expired by `now` and reschedules it if periodic.
+  fn next_expired(&mut self, now: u64) -> Option<(Timer, Callback)> {
+    if self.active == 0 {
+      // Nothing to catch up with.
+      if now > self.current {
+        self.current = now;
+      }
+      return None;
+    }
+
+    loop {
+      let slot = slot_of(self.current);
+      let mut prev = None;
+      let mut cur = self.slots[slot];
+      loop {
+        let i = match cur {
+          Some(i) => i,
+          None => break,
+        };
+        if self.entries[i].expires <= self.current {
+          self.unlink(slot, prev, i);
+          let entry = self.entries[i];
+          if entry.periodic {
+            self.insert(i, entry.expires + entry.interval as u64);
+          }
+          return Some((Timer(i), entry.callback.unwrap()));
+        }
+        prev = cur;
+        cur = self.entries[i].next;
+      }
+
+      if self.current >= now {
+        return None;
+      }
+      self.current += 1;
+    }
+  }
+
+  fn next_expiry(&self) -> Option<u64> {
+    let mut next = None;
+    for e in self.entries.iter() {
+      if e.active && (next.is_none() || Some(e.expires) < next) {
+        next = Some(e.expires);
+      }
+    }
+    next
+  }
+
+  fn remaining(&self, i: usize) -> Option<u64> {
+    if self.entries[i].active {
+      Some(self.entries[i].expires - self.current)
+    } else {
+      None
+    }
+  }
+}
+
+/// Allocates a timer calling `callback` on expiry.
+///
+/// The timer is created stopped. Returns None if all `MAX_TIMERS` timers are
+/// allocated.
+pub fn create(callback: Callback) -> Option<Timer> {
+  let _crit = NoInterrupts::new();
+  unsafe { TheWheel.create(callback) }
+}
+
+/// Advances timers to tick `now`, calling the callbacks of expired timers.
+///
+/// Ticks missed since the last call are caught up with, so timers expire in
+/// order even if `now` jumps.
+pub fn process(now: u64) {
+  loop {
+    let expired = {
+      let _crit = NoInterrupts::new();
+      unsafe { TheWheel.next_expired(now) }
+    };
+    match expired {
+      Some((timer, callback)) => callback(timer),
+      None => break,
+    }
+  }
+}
+
+/// Returns the tick the first active timer expires on, so that a tickless
+/// idle can sleep until then.
+pub fn next_expiry() -> Option<u64> {
+  let _crit = NoInterrupts::new();
+  unsafe { TheWheel.next_expiry() }
+}
+
+/// Returns the latest tick processed.
+pub fn current_tick() -> u64 {
+  let _crit = NoInterrupts::new();
+  unsafe { TheWheel.current }
+}
+
+impl Timer {
+  /// Starts the timer to expire once, `ticks` after `now`, the current tick
+  /// of the time base passed to `process`.
+  ///
+  /// A running timer is rescheduled.
+  pub fn start_once(&self, now: u64, ticks: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.start(self.0, ticks, false, now) };
+  }
+
+  /// Starts the timer to expire every `period` ticks, the first time
+  /// `period` after `now`.
+  ///
+  /// A running timer is rescheduled.
+  pub fn start_periodic(&self, now: u64, period: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.start(self.0, period, true, now) };
+  }
+
+  /// Starts the timer again at `now` with the last interval it was started
+  /// with.
+  pub fn restart(&self, now: u64) {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.restart(self.0, now) };
+  }
+
+  /// Stops the timer. Stopping a stopped timer does nothing.
+  pub fn stop(&self) {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.stop(self.0) };
+  }
+
+  /// Stops the timer and returns it to the pool.
+  ///
+  /// The handle, and any copy of it, must not be used afterwards, as the
+  /// timer may be handed out again by `create`.
+  pub fn delete(self) {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.delete(self.0) };
+  }
+
+  /// Returns true if the timer is running.
+  pub fn is_active(&self) -> bool {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.entries[self.0].active }
+  }
+
+  /// Returns the number of ticks until the timer expires, None if stopped.
+  pub fn remaining(&self) -> Option<u64> {
+    let _crit = NoInterrupts::new();
+    unsafe { TheWheel.remaining(self.0) }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::option::Option::{Some, None};
+  use std::vec::Vec;
+
+  use super::{Wheel, Timer, FREE_ENTRY, MAX_TIMERS, WHEEL_SIZE};
+
+  fn nop(_: Timer) {}
+
+  fn wheel() -> Wheel {
+    Wheel {
+      entries: [FREE_ENTRY; MAX_TIMERS],
+      slots: [None; WHEEL_SIZE],
+      current: 0,
+      active: 0,
+    }
+  }
+
+  /// Returns (tick, timer) for every expiry up to `now`.
+  fn run(wheel: &mut Wheel, now: u64) -> Vec<(u64, usize)> {
+    let mut fired = Vec::new();
+    loop {
+      match wheel.next_expired(now) {
+        Some((Timer(i), _)) => fired.push((wheel.current, i)),
+        None => return fired,
+      }
+    }
+  }
+
+  #[test]
+  fn fires_one_shot_once() {
+    let mut w = wheel();
+    let Timer(t) = w.create(nop).unwrap();
+    w.start(t, 3, false, 0);
+    assert!(run(&mut w, 2).len() == 0);
+    assert!(&run(&mut w, 10)[..] == &[(3, t)]);
+    assert!(w.remaining(t) == None);
+  }
+
+  #[test]
+  fn reschedules_periodic_without_drift() {
+    let mut w = wheel();
+    let Timer(t) = w.create(nop).unwrap();
+    w.start(t, 5, true, 0);
+    assert!(&run(&mut w, 17)[..] == &[(5, t), (10, t), (15, t)]);
+    assert!(w.remaining(t) == Some(3));
+  }
+
+  #[test]
+  fn keeps_timers_longer_than_the_wheel() {
+    let mut w = wheel();
+    let Timer(a) = w.create(nop).unwrap();
+    let Timer(b) = w.create(nop).unwrap();
+    w.start(a, WHEEL_SIZE as u32 + 2, false, 0);
+    w.start(b, 2, false, 0);
+    assert!(&run(&mut w, 40)[..] == &[(2, b), (WHEEL_SIZE as u64 + 2, a)]);
+  }
+
+  #[test]
+  fn stops_and_restarts() {
+    let mut w = wheel();
+    let Timer(a) = w.create(nop).unwrap();
+    let Timer(b) = w.create(nop).unwrap();
+    w.start(a, 4, false, 0);
+    w.start(b, 4, false, 0);
+    w.stop(a);
+    assert!(&run(&mut w, 6)[..] == &[(4, b)]);
+    w.restart(a, 6);
+    assert!(w.remaining(a) == Some(4));
+  }
+
+  #[test]
+  fn starts_from_now_before_first_process() {
+    let mut w = wheel();
+    let Timer(t) = w.create(nop).unwrap();
+    w.start(t, 3, false, 100);
+    assert!(run(&mut w, 102).len() == 0);
+    assert!(&run(&mut w, 110)[..] == &[(103, t)]);
+  }
+
+  #[test]
+  fn finds_next_expiry() {
+    let mut w = wheel();
+    assert!(w.next_expiry() == None);
+    let Timer(a) = w.create(nop).unwrap();
+    let Timer(b) = w.create(nop).unwrap();
+    w.start(a, 30, false, 0);
+    w.start(b, 7, false, 0);
+    assert!(w.next_expiry() == Some(7));
+    w.stop(b);
+    assert!(w.next_expiry() == Some(30));
+  }
+
+  #[test]
+  fn runs_out_of_timers() {
+    let mut w = wheel();
+    for _ in 0..MAX_TIMERS {
+      w.create(nop).unwrap();
+    }
+    assert!(w.create(nop).is_none());
+  }
+
+  #[test]
+  fn reuses_deleted_timers() {
+    let mut w = wheel();
+    for _ in 0..MAX_TIMERS {
+      w.create(nop).unwrap();
+    }
+    w.start(3, 5, true, 0);
+    w.delete(3);
+    assert!(w.next_expiry() == None);
+    assert!(w.create(nop) == Some(Timer(3)));
+  }
+}
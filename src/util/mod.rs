@@ -18,6 +18,6 @@
 pub mod strconv;
 pub mod support;
 pub mod shared;
-#[cfg(feature = "multitasking")] pub mod queue;
+pub mod queue;
 
 mod lang_items;
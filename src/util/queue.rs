@@ -1,47 +1,65 @@
 // Rough structure taken from libsync's mpcs_intrusive
-// all links are usize to allow for static initialization
+// Nodes are owned by the caller, e.g. on the stack of a blocked task.
 
 //
 // head                       tail
 // | |--->| |--->| |--->| |--->| |
 //
 
-use core::ty::Unsafe;
+//! Intrusive queues.
+
+use core::cell::UnsafeCell;
 use core::cmp::Ord;
 use core::ops::Deref;
-use core::ptr::RawPtr;
 use core::option::Option::{self, Some, None};
+use core::ptr;
 
-use hal::cortex_m3::sched::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+use util::shared::dummy_irq::NoInterrupts;
 
+/// A queue entry.
 pub struct Node<T> {
-  pub next: Unsafe<*mut Node<T>>,
+  /// Next entry, null for the tail.
+  pub next: UnsafeCell<*mut Node<T>>,
+  /// Queued value.
   pub data: T
 }
 
+/// A queue of nodes linked in place.
 pub struct Queue<T> {
-  pub head: Unsafe<*mut Node<T>>,
-  pub tail: Unsafe<*mut Node<T>>
+  /// First node, null if the queue is empty.
+  pub head: UnsafeCell<*mut Node<T>>,
+  /// Last node, null if the queue is empty.
+  pub tail: UnsafeCell<*mut Node<T>>
 }
 
-fn null_mut<T>() -> *mut T { 0 as *mut T }
-
 impl<T> Queue<T> {
-  pub fn new() -> Queue<T> {
+  /// Creates an empty queue.
+  pub const fn new() -> Queue<T> {
     Queue {
-      head: Unsafe::new(null_mut()),
-      tail: Unsafe::new(null_mut())
+      head: UnsafeCell::new(0 as *mut Node<T>),
+      tail: UnsafeCell::new(0 as *mut Node<T>)
     }
   }
 
   /// Push to tail.
   pub unsafe fn push(&self, node: *mut Node<T>, _: &NoInterrupts) {
-    if (*self.head.get()).is_null() {
-      *self.head.get() = node;
-    }
+    *(*node).next.get() = ptr::null_mut();
     let tail: *mut Node<T> = *self.tail.get();
-    *(*node).next.get() = null_mut();
-    if !tail.is_null() {
+    if tail.is_null() {
+      *self.head.get() = node;
+    } else {
       *(*tail).next.get() = node;
     }
     *self.tail.get() = node;
@@ -49,54 +67,158 @@ impl<T> Queue<T> {
 
   /// Peek at head.
   pub unsafe fn peek(&self) -> Option<*mut Node<T>> {
-    let head = self.head.get();
-    if (*head).is_null() {
+    let head = *self.head.get();
+    if head.is_null() {
       None
     } else {
-      Some(*head)
+      Some(head)
     }
   }
 
   /// Pop off of head.
   pub unsafe fn pop(&self, _: &NoInterrupts) -> Option<*mut Node<T>> {
-    let head = self.head.get();
-    if (*head).is_null() {
-      None
-    } else {
-      *head = *(**head).next.get();
-      Some(*head)
+    let head = *self.head.get();
+    if head.is_null() {
+      return None;
     }
+    *self.head.get() = *(*head).next.get();
+    if (*self.head.get()).is_null() {
+      *self.tail.get() = ptr::null_mut();
+    }
+    Some(head)
   }
-}
 
-impl<T: Ord> Queue<T> {
-  /// Priority insertion (higher ends up closer to head).
-  pub unsafe fn insert(&self, node: *mut Node<T>, _: &NoInterrupts) {
-    let mut next: &Unsafe<*mut Node<T>> = &self.head;
+  /// Removes `node` from anywhere in the queue. Returns false if it wasn't
+  /// queued.
+  pub unsafe fn remove(&self, node: *mut Node<T>, _: &NoInterrupts) -> bool {
+    let mut prev: *mut Node<T> = ptr::null_mut();
+    let mut cur: *mut Node<T> = *self.head.get();
     loop {
-      let i: *mut Node<T> = *next.get();
-      if i.is_null() {
-        break;
+      if cur.is_null() {
+        return false;
       }
-      if (*i).data > (*node).data {
+      if cur == node {
         break;
       }
-      next = &(*i).next;
+      prev = cur;
+      cur = *(*cur).next.get();
+    }
+    let next = *(*node).next.get();
+    if prev.is_null() {
+      *self.head.get() = next;
+    } else {
+      *(*prev).next.get() = next;
+    }
+    if next.is_null() {
+      *self.tail.get() = prev;
     }
-    *(*node).next.get() = *next.get();
-    *next.get() = node;
-    if (*(*node).next.get()).is_null() {
+    true
+  }
+}
+
+impl<T: Ord> Queue<T> {
+  /// Priority insertion (higher ends up closer to head, after the nodes
+  /// already queued with the same value).
+  pub unsafe fn insert(&self, node: *mut Node<T>, _: &NoInterrupts) {
+    let mut prev: *mut Node<T> = ptr::null_mut();
+    let mut next: *mut Node<T> = *self.head.get();
+    while !next.is_null() && (*next).data >= (*node).data {
+      prev = next;
+      next = *(*next).next.get();
+    }
+    *(*node).next.get() = next;
+    if prev.is_null() {
+      *self.head.get() = node;
+    } else {
+      *(*prev).next.get() = node;
+    }
+    if next.is_null() {
       *self.tail.get() = node;
     }
   }
 }
 
 impl<T> Node<T> {
+  /// Creates an unlinked node.
   pub fn new(data: T) -> Node<T> {
-    Node { next: Unsafe::new(null_mut()), data: data }
+    Node { next: UnsafeCell::new(ptr::null_mut()), data: data }
   }
 }
 
-impl<T> Deref<T> for Node<T> {
+impl<T> Deref for Node<T> {
+  type Target = T;
   fn deref<'a>(&'a self) -> &'a T {&self.data}
 }
+
+#[cfg(test)]
+mod test {
+  use core::option::Option::{Some, None};
+  use std::vec::Vec;
+
+  use super::{Queue, Node, NoInterrupts};
+
+  fn drain(q: &Queue<u32>, crit: &NoInterrupts) -> Vec<u32> {
+    let mut values = Vec::new();
+    loop {
+      match unsafe { q.pop(crit) } {
+        Some(node) => values.push(unsafe { (*node).data }),
+        None => return values,
+      }
+    }
+  }
+
+  #[test]
+  fn pushes_and_pops_in_order() {
+    let crit = NoInterrupts::new();
+    let q = Queue::new();
+    let mut a = Node::new(1);
+    let mut b = Node::new(2);
+    unsafe {
+      q.push(&mut a, &crit);
+      q.push(&mut b, &crit);
+    }
+    assert!(&drain(&q, &crit)[..] == &[1, 2]);
+    assert!(unsafe { q.peek() } == None);
+
+    // The tail is reset once empty.
+    unsafe { q.push(&mut a, &crit) };
+    assert!(&drain(&q, &crit)[..] == &[1]);
+  }
+
+  #[test]
+  fn inserts_by_priority() {
+    let crit = NoInterrupts::new();
+    let q = Queue::new();
+    let mut a = Node::new(1);
+    let mut b = Node::new(3);
+    let mut c = Node::new(2);
+    let mut d = Node::new(3);
+    unsafe {
+      q.insert(&mut a, &crit);
+      q.insert(&mut b, &crit);
+      q.insert(&mut c, &crit);
+      q.insert(&mut d, &crit);
+      assert!(q.peek() == Some(&mut b as *mut Node<u32>));
+    }
+    assert!(&drain(&q, &crit)[..] == &[3, 3, 2, 1]);
+  }
+
+  #[test]
+  fn removes_nodes() {
+    let crit = NoInterrupts::new();
+    let q = Queue::new();
+    let mut a = Node::new(1);
+    let mut b = Node::new(2);
+    let mut c = Node::new(3);
+    unsafe {
+      q.push(&mut a, &crit);
+      q.push(&mut b, &crit);
+      q.push(&mut c, &crit);
+      assert!(q.remove(&mut c, &crit));
+      assert!(!q.remove(&mut c, &crit));
+      assert!(q.remove(&mut a, &crit));
+      q.push(&mut c, &crit);
+    }
+    assert!(&drain(&q, &crit)[..] == &[2, 3]);
+  }
+}
@@ -19,25 +19,39 @@ use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
 use core::marker::{Sync, Send};
 
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::CriticalSection;
 #[cfg(feature = "cpu_cortex-m3")]
-use hal::cortex_m3::irq::NoInterrupts;
+use hal::cortex_m3::irq::CriticalSection;
 #[cfg(feature = "cpu_cortex-m4")]
-use hal::cortex_m4::irq::NoInterrupts;
-// If cpu doesn't have nointerrupts provide dummy implementation
-#[cfg(not(any(feature = "cpu_cortex-m3",
-              feature = "cpu_cortex-m4")))]
-use self::dummy_irq::NoInterrupts;
+use hal::cortex_m4::irq::CriticalSection;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::CriticalSection;
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+use self::dummy_irq::CriticalSection;
 
+/// Stand-ins for the cpu `irq` module when building without a cpu, e.g. for
+/// host tests, where there is nothing to mask.
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
 #[allow(missing_docs)]
-mod dummy_irq {
+pub mod dummy_irq {
+  pub unsafe trait CriticalSection {}
+
   pub struct NoInterrupts;
 
   impl NoInterrupts {
-    #[allow(dead_code)]
     pub fn new() -> NoInterrupts {
       NoInterrupts
     }
   }
+
+  unsafe impl CriticalSection for NoInterrupts {}
 }
 
 /// This allows safe sharing of state, ensuring access occurs only
@@ -51,7 +65,7 @@ pub struct Shared<T> {
 pub struct SharedRef<'a, T: 'a> {
   ptr: &'a Shared<T>,
   #[allow(dead_code)]
-  crit: &'a NoInterrupts
+  crit: &'a CriticalSection
 }
 
 impl<T> Shared<T> {
@@ -63,7 +77,10 @@ impl<T> Shared<T> {
   }
 
   /// Borrow a reference to the value
-  pub fn borrow<'a>(&'a self, crit: &'a NoInterrupts) -> SharedRef<'a, T> {
+  ///
+  /// With a `PriorityMask` critical section, the value must not be accessed
+  /// from interrupts above its ceiling.
+  pub fn borrow<'a, C: CriticalSection>(&'a self, crit: &'a C) -> SharedRef<'a, T> {
     SharedRef {ptr: self, crit: crit}
   }
 }
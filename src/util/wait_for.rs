@@ -22,3 +22,19 @@ macro_rules! wait_for(
     }
   )
 );
+
+macro_rules! try_wait_for(
+  ($cond:expr, $deadline:expr) => ({
+    let mut result = Ok(());
+    loop {
+      if $cond {
+        break;
+      }
+      if $deadline.is_expired() {
+        result = Err(::hal::time::Timeout);
+        break;
+      }
+    }
+    result
+  })
+);